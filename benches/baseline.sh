@@ -0,0 +1,57 @@
+#!/bin/sh
+#Measures the "before" column of the benchmark table in README.md: the step and pack benches on the commit before
+#particles were stored as one array per component, when they were a Vec<Particle> and `pack` copied the positions.
+#Extra arguments are passed to criterion, e.g. `benches/baseline.sh pack`.
+set -e
+
+root=$(git rev-parse --show-toplevel)
+change=$(git -C "$root" log --format=%H -1 --fixed-strings --grep='Store particles as structure of arrays')
+dir=$(mktemp -d)
+git -C "$root" worktree add --detach "$dir" "$change~1"
+trap 'git -C "$root" worktree remove --force "$dir"' EXIT
+
+cd "$dir"
+cp "$root/Cargo.lock" .
+cat >> Cargo.toml <<'EOF'
+
+[dev-dependencies]
+criterion = { version = "0.5", default-features = false }
+
+[[bench]]
+name = "simulation"
+harness = false
+EOF
+
+mkdir -p benches
+cat > benches/simulation.rs <<'EOF'
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use callisto::{Simulation, SimulationConfig};
+
+fn simulation(num_particles: i32) -> Simulation {
+    Simulation::from_config(&SimulationConfig { num_particles, seed: Some(1), deterministic: true, ..SimulationConfig::default() })
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.sample_size(10);
+    for size in [1_000, 10_000] {
+        let mut simulation = simulation(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| b.iter(|| simulation.step()));
+    }
+    group.finish();
+}
+
+fn pack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pack");
+    for size in [1_000, 10_000, 100_000] {
+        let simulation = simulation(size);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| b.iter(|| black_box(simulation.pack().len())));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step, pack);
+criterion_main!(benches);
+EOF
+
+CARGO_TARGET_DIR="$root/target/baseline" cargo bench --bench simulation -- "$@"
@@ -0,0 +1,61 @@
+use std::time::Duration;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use callisto::{Simulation, SimulationConfig};
+
+/// Sizes for the O(n²) force evaluation. 100k is measured separately by `step_large`.
+const STEP_SIZES: [i32; 2] = [1_000, 10_000];
+/// Sizes for the O(n) parts
+const SIZES: [i32; 3] = [1_000, 10_000, 100_000];
+
+fn simulation(num_particles: i32, deterministic: bool) -> Simulation {
+    Simulation::from_config(&SimulationConfig { num_particles, seed: Some(1), deterministic, ..SimulationConfig::default() })
+}
+
+/// One step of the force evaluation and integration. Forces are O(n²), so the larger size takes a while.
+/// `step` sums the forces in index order, `step_lanes` uses the vectorizable kernel.
+fn step(c: &mut Criterion) {
+    for (name, deterministic) in [("step", true), ("step_lanes", false)] {
+        let mut group = c.benchmark_group(name);
+        group.sample_size(10);
+        for size in STEP_SIZES {
+            let mut simulation = simulation(size, deterministic);
+            group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| b.iter(|| simulation.step()));
+        }
+        group.finish();
+    }
+}
+
+/// `step_lanes` at 100k particles. Each step takes tens of seconds, so the ten samples take several minutes.
+fn step_large(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step_lanes");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(600));
+    let mut simulation = simulation(100_000, false);
+    group.bench_function(BenchmarkId::from_parameter(100_000), |b| b.iter(|| simulation.step()));
+    group.finish();
+}
+
+/// Just the integration of a microstep, without the forces
+fn integrate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("integrate");
+    for size in SIZES {
+        let mut simulation = simulation(size, true);
+        let dt = simulation.dt;
+        let domain_mode = simulation.domain_mode.clone();
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| b.iter(|| simulation.particles.integrate(dt, &domain_mode)));
+    }
+    group.finish();
+}
+
+/// Handing the particle positions to a renderer
+fn pack(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pack");
+    for size in SIZES {
+        let simulation = simulation(size, true);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| b.iter(|| black_box(simulation.pack()[0].len())));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, step, step_large, integrate, pack);
+criterion_main!(benches);
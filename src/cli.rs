@@ -0,0 +1,288 @@
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use clap::{Args, Parser, Subcommand};
+use callisto::colormap::ColormapRegistry;
+use callisto::input::{Bindings, BindingsError};
+use callisto::scene::{Scene, SceneError};
+use callisto::simulation::{DOMAIN_MODE, Simulation};
+use callisto::snapshot::{SimulationSnapshot, SNAPSHOT_FORMAT, SnapshotError};
+
+/// Scene loaded when no `--scene` is given, if it exists
+const DEFAULT_SCENE: &str = "scene.toml";
+/// Bindings file loaded when --bindings isn't given
+const DEFAULT_BINDINGS: &str = "bindings.toml";
+
+/// Interactive particle simulation with a density visualization
+#[derive(Debug, Parser)]
+#[command(name = "callisto", version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Arguments of `run`, which is the default when no subcommand is given
+    #[command(flatten)]
+    pub run: RunArgs
+}
+
+impl Cli {
+    pub fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Run(self.run))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Open the interactive window
+    Run(RunArgs),
+    /// Render frames without a window, optionally encoding them to a video with ffmpeg
+    Render(RenderArgs),
+    /// Time the simulation without rendering
+    Bench(BenchArgs),
+    /// Write particle trajectories to a CSV file
+    Export(ExportArgs),
+    /// Check a scene file for errors
+    Validate(ValidateArgs)
+}
+
+/// Scene file plus overrides for its most commonly changed values
+#[derive(Debug, Default, Args)]
+pub struct SceneArgs {
+    /// Scene file. Defaults to scene.toml in the working directory if it exists.
+    #[arg(long, value_name = "PATH")]
+    pub scene: Option<PathBuf>,
+    /// Number of particles
+    #[arg(long, value_name = "COUNT", conflicts_with = "snapshot")]
+    pub particles: Option<i32>,
+    /// Seed for the initial particle state
+    #[arg(long, conflicts_with = "snapshot")]
+    pub seed: Option<u64>,
+    /// How the edges of the domain are treated: wrap, wall or infinite
+    #[arg(long, value_parser = parse_domain, conflicts_with = "snapshot")]
+    pub domain: Option<DOMAIN_MODE>,
+    /// Simulation timestep
+    #[arg(long, conflicts_with = "snapshot")]
+    pub dt: Option<f32>,
+    /// Resume the simulation saved in this snapshot file, in either format. The scene still provides everything
+    /// else, like the colormap and the window.
+    #[arg(long, value_name = "PATH")]
+    pub snapshot: Option<PathBuf>
+}
+
+impl SceneArgs {
+    /// Loads the scene file (or the defaults when there is none), applies the overrides and validates the result.
+    /// Returns the path the scene was loaded from.
+    pub fn load(&self, colormaps: &ColormapRegistry) -> Result<(Scene, Option<PathBuf>), SceneError> {
+        let path = match &self.scene {
+            Some(path) => Some(path.clone()),
+            None if Path::new(DEFAULT_SCENE).exists() => Some(PathBuf::from(DEFAULT_SCENE)),
+            None => None
+        };
+
+        let scene = match &path {
+            Some(path) => Scene::load_unvalidated(path)?,
+            None => Scene::default()
+        };
+        let source = path.clone().unwrap_or_else(|| PathBuf::from("command line"));
+        Ok((self.apply(scene, &source, colormaps)?, path))
+    }
+
+    /// Applies the overrides to `scene`, then validates it. A value that is only invalid in the file is fine
+    /// when an override replaces it.
+    fn apply(&self, mut scene: Scene, source: &Path, colormaps: &ColormapRegistry) -> Result<Scene, SceneError> {
+        if let Some(particles) = self.particles {
+            scene.simulation.num_particles = particles;
+        }
+        if let Some(seed) = self.seed {
+            scene.simulation.seed = Some(seed);
+        }
+        if let Some(domain) = &self.domain {
+            scene.simulation.domain_mode = domain.clone();
+        }
+        if let Some(dt) = self.dt {
+            scene.simulation.dt = dt;
+        }
+
+        scene.validate(source)?;
+        scene.validate_colormap(source, colormaps)?;
+        Ok(scene)
+    }
+
+    /// The simulation from `--snapshot`, or a new one from the scene
+    pub fn simulation(&self, scene: &Scene) -> Result<Simulation, SnapshotError> {
+        match &self.snapshot {
+            Some(path) => Simulation::from_snapshot(&SimulationSnapshot::load(path)?),
+            None => Ok(Simulation::from_config(&scene.simulation))
+        }
+    }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    /// Use the CPU renderer in a framebuffer window instead of OpenGL (needs the software-window feature)
+    #[arg(long)]
+    pub software: bool,
+    /// Run the simulation in compute shaders (needs the gpu-compute feature and OpenGL 4.3)
+    #[arg(long, conflicts_with = "software")]
+    pub gpu: bool,
+    /// Key and mouse bindings file. Defaults to bindings.toml in the working directory if it exists.
+    #[arg(long, value_name = "PATH")]
+    pub bindings: Option<PathBuf>,
+    /// Format of the snapshots saved from the window: toml or binary
+    #[arg(long, value_name = "FORMAT", value_parser = parse_snapshot_format, default_value = "toml")]
+    pub snapshot_format: SNAPSHOT_FORMAT
+}
+
+impl RunArgs {
+    /// Loads the bindings file, or the default bindings when there is none. Returns the path they were loaded from.
+    pub fn load_bindings(&self) -> Result<(Bindings, Option<PathBuf>), BindingsError> {
+        let path = match &self.bindings {
+            Some(path) => path.clone(),
+            None if Path::new(DEFAULT_BINDINGS).exists() => PathBuf::from(DEFAULT_BINDINGS),
+            None => return Ok((Bindings::default(), None))
+        };
+        Ok((Bindings::load(&path)?, Some(path)))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    /// Number of simulation steps to run
+    #[arg(long, default_value_t = 600)]
+    pub steps: usize,
+    /// Write a frame every k-th step
+    #[arg(long, value_name = "K", default_value_t = NonZeroUsize::MIN)]
+    pub every: NonZeroUsize,
+    /// Directory the frames are written to
+    #[arg(long, default_value = "frames")]
+    pub output: PathBuf,
+    /// Frame size in pixels. Defaults to the window size of the scene.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    /// Render on the CPU, without OpenGL
+    #[arg(long)]
+    pub software: bool,
+    /// Run the simulation in compute shaders (needs the gpu-compute feature and OpenGL 4.3)
+    #[arg(long, conflicts_with = "software")]
+    pub gpu: bool,
+    /// Encode the frames to this video file with ffmpeg once rendering is done
+    #[arg(long, value_name = "PATH")]
+    pub video: Option<PathBuf>,
+    /// Frame rate of the video
+    #[arg(long, default_value_t = 60)]
+    pub fps: u32
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    /// Number of timed steps
+    #[arg(long, default_value_t = 1000)]
+    pub steps: usize,
+    /// Untimed steps run first
+    #[arg(long, default_value_t = 10)]
+    pub warmup: usize
+}
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub scene: SceneArgs,
+    /// Number of simulation steps to run
+    #[arg(long, default_value_t = 1000)]
+    pub steps: usize,
+    /// Record the particles every k-th step
+    #[arg(long, value_name = "K", default_value_t = NonZeroUsize::MIN)]
+    pub every: NonZeroUsize,
+    /// CSV file the trajectories are written to
+    #[arg(long, default_value = "trajectories.csv")]
+    pub output: PathBuf
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// Scene files to check
+    #[arg(required = true)]
+    pub scenes: Vec<PathBuf>
+}
+
+fn parse_domain(value: &str) -> Result<DOMAIN_MODE, String> {
+    match value {
+        "wrap" => Ok(DOMAIN_MODE::WRAP),
+        "wall" => Ok(DOMAIN_MODE::WALL),
+        "infinite" => Ok(DOMAIN_MODE::INFINITE),
+        _ => Err("expected wrap, wall or infinite".to_string())
+    }
+}
+
+fn parse_snapshot_format(value: &str) -> Result<SNAPSHOT_FORMAT, String> {
+    match value {
+        "toml" => Ok(SNAPSHOT_FORMAT::TOML),
+        "binary" => Ok(SNAPSHOT_FORMAT::BINARY),
+        _ => Err("expected toml or binary".to_string())
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value.split_once('x').ok_or("expected <width>x<height>")?;
+    let width: u32 = width.parse().map_err(|_| "invalid width")?;
+    let height: u32 = height.parse().map_err(|_| "invalid height")?;
+    if width == 0 || height == 0 {
+        return Err("size must be greater than 0".to_string());
+    }
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene_args(args: &[&str]) -> SceneArgs {
+        match Cli::try_parse_from([&["callisto"], args].concat()).unwrap().into_command() {
+            Command::Run(args) => args.scene,
+            command => panic!("expected run, got {:?}", command)
+        }
+    }
+
+    fn apply(args: &[&str], source: &str) -> Result<Scene, SceneError> {
+        let scene = Scene::parse_unvalidated("scene.toml", source).unwrap();
+        scene_args(args).apply(scene, Path::new("scene.toml"), &ColormapRegistry::default())
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_file() {
+        let source = "[simulation]\nnum_particles = 50\ndt = 0.01\nseed = 3\ndomain_mode = \"wall\"\n";
+
+        let scene = apply(&[], source).unwrap();
+        assert_eq!((scene.simulation.num_particles, scene.simulation.dt, scene.simulation.seed), (50, 0.01, Some(3)));
+        assert_eq!(scene.simulation.domain_mode, DOMAIN_MODE::WALL);
+
+        let scene = apply(&["--particles", "10", "--dt", "0.02", "--seed", "7", "--domain", "infinite"], source).unwrap();
+        assert_eq!((scene.simulation.num_particles, scene.simulation.dt, scene.simulation.seed), (10, 0.02, Some(7)));
+        assert_eq!(scene.simulation.domain_mode, DOMAIN_MODE::INFINITE);
+    }
+
+    #[test]
+    fn overrides_replace_invalid_file_values() {
+        let source = "[simulation]\nnum_particles = 0\ndt = -1.0\n";
+
+        let Err(SceneError::Invalid { path, problems }) = apply(&[], source) else { panic!("invalid scene was accepted") };
+        assert_eq!(path, Path::new("scene.toml"));
+        assert_eq!(problems, ["simulation.dt must be greater than 0", "simulation.num_particles must be at least 1"]);
+
+        let scene = apply(&["--particles", "5", "--dt", "0.01"], source).unwrap();
+        assert_eq!((scene.simulation.num_particles, scene.simulation.dt), (5, 0.01));
+
+        let Err(SceneError::Invalid { problems, .. }) = apply(&["--dt", "0.01"], source) else { panic!("invalid scene was accepted") };
+        assert_eq!(problems, ["simulation.num_particles must be at least 1"]);
+    }
+
+    #[test]
+    fn overrides_are_validated() {
+        let Err(SceneError::Invalid { problems, .. }) = apply(&["--dt", "0"], "") else { panic!("invalid override was accepted") };
+        assert_eq!(problems, ["simulation.dt must be greater than 0"]);
+    }
+}
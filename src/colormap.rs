@@ -0,0 +1,395 @@
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+#[cfg(feature = "render")]
+use gl::types::*;
+#[cfg(feature = "render")]
+use crate::gpu::Texture;
+
+/// Number of entries in the lookup table uploaded for each colormap
+pub const LUT_SIZE: usize = 256;
+
+#[derive(Debug, Error)]
+pub enum ColormapError {
+    #[error("Error while reading colormap: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid colormap {name} on line {line}: {message}")]
+    Parse { name: String, line: usize, message: String },
+    #[error("Colormap {0} needs at least two color stops")]
+    TooFewStops(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Colormap {
+    pub name: String,
+    lut: Vec<[f32; 3]>
+}
+
+impl Colormap {
+    /// Builds a colormap by linearly interpolating between `(position, color)` stops.
+    /// Positions must be sorted and lie within `0.0..=1.0`.
+    ///
+    /// # Panics
+    /// With fewer than two stops, or unsorted positions.
+    pub fn from_stops(name: &str, stops: &[(f32, [f32; 3])]) -> Self {
+        assert!(stops.len() >= 2, "Colormap {} needs at least two color stops", name);
+        assert!(stops.windows(2).all(|pair| pair[0].0 <= pair[1].0), "Colormap {} has unsorted stop positions", name);
+        Self::from_fn(name, |t| {
+            let upper = stops.iter().position(|(position, _)| *position >= t).unwrap_or(stops.len() - 1);
+            if upper == 0 {
+                return stops[0].1;
+            }
+
+            let (p0, c0) = stops[upper - 1];
+            let (p1, c1) = stops[upper];
+            let span = p1 - p0;
+            let pct = if span > 0.0 { ((t - p0) / span).clamp(0.0, 1.0) } else { 1.0 };
+            [
+                c0[0] + (c1[0] - c0[0]) * pct,
+                c0[1] + (c1[1] - c0[1]) * pct,
+                c0[2] + (c1[2] - c0[2]) * pct
+            ]
+        })
+    }
+
+    /// Builds a colormap by sampling a function over `0.0..=1.0`
+    pub fn from_fn(name: &str, f: impl Fn(f32) -> [f32; 3]) -> Self {
+        let lut = (0..LUT_SIZE)
+            .map(|i| f(i as f32 / (LUT_SIZE - 1) as f32))
+            .collect();
+
+        Self { name: name.to_string(), lut }
+    }
+
+    /// Evenly spaced stops from a list of `0xRRGGBB` colors
+    ///
+    /// # Panics
+    /// With fewer than two colors.
+    pub fn from_hex(name: &str, colors: &[u32]) -> Self {
+        assert!(colors.len() >= 2, "Colormap {} needs at least two color stops", name);
+        let stops: Vec<(f32, [f32; 3])> = colors.iter()
+            .enumerate()
+            .map(|(i, color)| (i as f32 / (colors.len() - 1) as f32, hex_to_rgb(*color)))
+            .collect();
+
+        Self::from_stops(name, &stops)
+    }
+
+    /// Loads a user gradient. Each non-empty line holds one color stop, either as `#rrggbb` or as
+    /// three floats in `0.0..=1.0`, optionally preceded by the stop position. Lines starting with `#`
+    /// followed by a space are comments. Stops without positions are spaced evenly.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ColormapError> {
+        let path = path.as_ref();
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        Self::parse(&name, &fs::read_to_string(path)?)
+    }
+
+    pub fn parse(name: &str, source: &str) -> Result<Self, ColormapError> {
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+        let mut lines = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("# ") || line.starts_with("//") {
+                continue;
+            }
+
+            let error = |message: &str| ColormapError::Parse { name: name.to_string(), line: i + 1, message: message.to_string() };
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let (position, color) = match tokens.as_slice() {
+                [hex] => (None, parse_hex(hex).ok_or_else(|| error("expected #rrggbb"))?),
+                [position, hex] => (Some(parse_float(position).ok_or_else(|| error("invalid position"))?), parse_hex(hex).ok_or_else(|| error("expected #rrggbb"))?),
+                [r, g, b] => (None, parse_rgb(r, g, b).ok_or_else(|| error("invalid rgb value"))?),
+                [position, r, g, b] => (Some(parse_float(position).ok_or_else(|| error("invalid position"))?), parse_rgb(r, g, b).ok_or_else(|| error("invalid rgb value"))?),
+                _ => return Err(error("expected a color, optionally preceded by a position"))
+            };
+
+            if position.is_some_and(|position| !(0.0..=1.0).contains(&position)) {
+                return Err(error("positions must be within 0.0..=1.0"));
+            }
+
+            positions.push(position);
+            colors.push(color);
+            lines.push(i + 1);
+        }
+
+        if colors.len() < 2 {
+            return Err(ColormapError::TooFewStops(name.to_string()));
+        }
+
+        let stops: Vec<(f32, [f32; 3])> = colors.iter()
+            .enumerate()
+            .map(|(i, color)| (positions[i].unwrap_or(i as f32 / (colors.len() - 1) as f32), *color))
+            .collect();
+
+        //Checked on the final positions, since evenly spaced stops can land before a positioned one
+        if let Some(i) = (1..stops.len()).find(|i| stops[*i].0 < stops[i - 1].0) {
+            return Err(ColormapError::Parse {
+                name: name.to_string(),
+                line: lines[i],
+                message: format!("stop at {} comes after a stop at {}, but positions must be increasing", stops[i].0, stops[i - 1].0)
+            });
+        }
+
+        Ok(Self::from_stops(name, &stops))
+    }
+
+    /// Samples the lookup table the same way the shader does: clamped, linearly filtered between texel centers
+    pub fn sample(&self, value: f32) -> [f32; 3] {
+        let coord = value.clamp(0.0, 1.0) * (LUT_SIZE - 1) as f32;
+        let i0 = coord.floor() as usize;
+        let i1 = (i0 + 1).min(LUT_SIZE - 1);
+        let pct = coord - i0 as f32;
+        let (c0, c1) = (self.lut[i0], self.lut[i1]);
+        [
+            c0[0] + (c1[0] - c0[0]) * pct,
+            c0[1] + (c1[1] - c0[1]) * pct,
+            c0[2] + (c1[2] - c0[2]) * pct
+        ]
+    }
+
+    pub fn lut(&self) -> &[[f32; 3]] {
+        &self.lut
+    }
+}
+
+fn hex_to_rgb(color: u32) -> [f32; 3] {
+    [
+        ((color >> 16) & 0xff) as f32 / 255.0,
+        ((color >> 8) & 0xff) as f32 / 255.0,
+        (color & 0xff) as f32 / 255.0
+    ]
+}
+
+fn parse_hex(token: &str) -> Option<[f32; 3]> {
+    let hex = token.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(hex_to_rgb)
+}
+
+fn parse_float(token: &str) -> Option<f32> {
+    token.parse::<f32>().ok().filter(|value| value.is_finite())
+}
+
+fn parse_rgb(r: &str, g: &str, b: &str) -> Option<[f32; 3]> {
+    let rgb = [parse_float(r)?, parse_float(g)?, parse_float(b)?];
+    if rgb.iter().all(|channel| (0.0..=1.0).contains(channel)) { Some(rgb) } else { None }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub struct ColormapRegistry {
+    colormaps: Vec<Colormap>
+}
+
+impl Default for ColormapRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+
+        registry.register(Colormap::from_stops("lightblue", &[
+            (0.0, [0.0, 0.222, 0.731]),
+            (1.0, [0.212, 0.625, 0.684])
+        ]));
+        registry.register(Colormap::from_fn("sand", |value| {
+            let modded = smoothstep(0.8, 1.0, value);
+            let (a, b) = ([0.773, 0.475, 0.428], [0.858, 0.902, 0.865]);
+            [a[0] + (b[0] - a[0]) * modded, a[1] + (b[1] - a[1]) * modded, a[2] + (b[2] - a[2]) * modded]
+        }));
+        registry.register(Colormap::from_stops("purplered", &[
+            (0.0, [0.925, 0.4314, 0.678]),
+            (1.0, [0.204, 0.58, 0.9])
+        ]));
+        registry.register(Colormap::from_hex("viridis", &[
+            0x440154, 0x472d7b, 0x3b528b, 0x2c728e, 0x21918c, 0x28ae80, 0x5ec962, 0xaddc30, 0xfde725
+        ]));
+        registry.register(Colormap::from_hex("magma", &[
+            0x000004, 0x1c1044, 0x4f127b, 0x812581, 0xb5367a, 0xe55064, 0xfb8861, 0xfec287, 0xfcfdbf
+        ]));
+        registry.register(Colormap::from_hex("inferno", &[
+            0x000004, 0x1f0c48, 0x550f6d, 0x88226a, 0xba3655, 0xe35933, 0xf98c0a, 0xf9c932, 0xfcffa4
+        ]));
+
+        registry
+    }
+}
+
+impl ColormapRegistry {
+    pub fn new() -> Self {
+        Self {
+            colormaps: Vec::new()
+        }
+    }
+
+    /// Adds a colormap, replacing any existing colormap with the same name
+    pub fn register(&mut self, colormap: Colormap) {
+        match self.index_of(&colormap.name) {
+            Some(index) => self.colormaps[index] = colormap,
+            None => self.colormaps.push(colormap)
+        }
+    }
+
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<(), ColormapError> {
+        self.register(Colormap::load(path)?);
+        Ok(())
+    }
+
+    /// Loads every `*.cmap` file in a directory. A missing directory is not an error.
+    pub fn load_dir(&mut self, path: impl AsRef<Path>) -> Result<usize, ColormapError> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Ok(0);
+        }
+
+        let mut files: Vec<_> = fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "cmap"))
+            .collect();
+        files.sort();
+
+        for file in &files {
+            self.load_file(file)?;
+        }
+
+        Ok(files.len())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Colormap> {
+        self.colormaps.iter().find(|colormap| colormap.name == name)
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.colormaps.iter().position(|colormap| colormap.name == name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colormaps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colormaps.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Colormap> {
+        self.colormaps.iter()
+    }
+}
+
+/// A colormap uploaded to the GPU as a 1D lookup texture
+#[cfg(feature = "render")]
+pub struct ColormapTexture {
+    pub texture: Texture,
+    pub name: String
+}
+
+#[cfg(feature = "render")]
+impl ColormapTexture {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(colormap: &Colormap) -> Self {
+        let texture = Texture::new(gl::TEXTURE_1D);
+        texture.bind(0);
+        gl::TexImage1D(
+            gl::TEXTURE_1D,
+            0,
+            gl::RGB32F as GLint,
+            LUT_SIZE as GLsizei,
+            0,
+            gl::RGB,
+            gl::FLOAT,
+            colormap.lut().as_ptr() as *const _
+        );
+        texture.set_sampling(gl::LINEAR, gl::CLAMP_TO_EDGE);
+        gl::BindTexture(gl::TEXTURE_1D, 0);
+
+        Self { texture, name: colormap.name.clone() }
+    }
+
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind(&self, unit: GLuint) {
+        self.texture.bind(unit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        assert!(actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 0.01), "{:?} != {:?}", actual, expected);
+    }
+
+    fn parse_error_line(source: &str) -> usize {
+        match Colormap::parse("test", source) {
+            Err(ColormapError::Parse { line, .. }) => line,
+            result => panic!("expected a parse error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn parses_evenly_spaced_hex_stops() {
+        let colormap = Colormap::parse("test", "#000000\n#ff0000\n#ffffff\n").unwrap();
+        assert_close(colormap.sample(0.0), [0.0, 0.0, 0.0]);
+        assert_close(colormap.sample(0.25), [0.5, 0.0, 0.0]);
+        assert_close(colormap.sample(0.5), [1.0, 0.0, 0.0]);
+        assert_close(colormap.sample(1.0), [1.0, 1.0, 1.0]);
+        //Clamped like the shader's texture lookup
+        assert_close(colormap.sample(-1.0), [0.0, 0.0, 0.0]);
+        assert_close(colormap.sample(2.0), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn parses_rgb_stops_with_positions() {
+        let colormap = Colormap::parse("test", "0.0 1 0 0\n0.8 0 1 0\n1.0 0 0 1").unwrap();
+        assert_close(colormap.sample(0.4), [0.5, 0.5, 0.0]);
+        assert_close(colormap.sample(0.8), [0.0, 1.0, 0.0]);
+        assert_close(colormap.sample(0.9), [0.0, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn mixes_positioned_and_evenly_spaced_stops() {
+        let colormap = Colormap::parse("test", "#000000\n0.9 #ffffff\n#ffffff").unwrap();
+        assert_close(colormap.sample(0.45), [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let colormap = Colormap::parse("test", "# dark to light\n\n// start\n#000000\n  \n#ffffff\n").unwrap();
+        assert_close(colormap.sample(0.5), [0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn reports_the_line_of_invalid_stops() {
+        assert_eq!(parse_error_line("#000000\n#12345\n"), 2);
+        assert_eq!(parse_error_line("#000000\n\n1 2 0\n"), 3);
+        assert_eq!(parse_error_line("#000000\n1.5 #ffffff\n"), 2);
+        assert_eq!(parse_error_line("0.5 #000000\n0.2 #ffffff\n"), 2);
+        assert_eq!(parse_error_line("#000000 #ffffff\n#ffffff 0 0\n"), 1);
+        //The unpositioned stop would be spaced at 0.5, before the first one
+        assert_eq!(parse_error_line("0.9 #ff0000\n#00ff00\n#0000ff\n"), 2);
+    }
+
+    #[test]
+    fn needs_two_stops() {
+        assert!(matches!(Colormap::parse("test", "# empty\n"), Err(ColormapError::TooFewStops(_))));
+        assert!(matches!(Colormap::parse("test", "#ffffff\n"), Err(ColormapError::TooFewStops(_))));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least two color stops")]
+    fn from_stops_rejects_empty_stops() {
+        Colormap::from_stops("test", &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least two color stops")]
+    fn from_hex_rejects_a_single_color() {
+        Colormap::from_hex("test", &[0xffffff]);
+    }
+}
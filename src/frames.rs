@@ -0,0 +1,52 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FrameError {
+    #[error("Error while writing frame: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Error while encoding frame: {0}")]
+    Png(#[from] png::EncodingError),
+}
+
+/// What to render when writing a sequence of frames, shared by the OpenGL and software renderers
+#[derive(Clone, Debug)]
+pub struct SequenceOptions {
+    /// Number of simulation steps to run
+    pub steps: usize,
+    /// Render every k-th step
+    pub every: usize,
+    pub output_dir: PathBuf,
+    pub width: u32,
+    pub height: u32
+}
+
+impl Default for SequenceOptions {
+    fn default() -> Self {
+        Self {
+            steps: 600,
+            every: 1,
+            output_dir: PathBuf::from("frames"),
+            width: 800,
+            height: 800
+        }
+    }
+}
+
+impl SequenceOptions {
+    /// `output_dir/frame_00000.png`, `frame_00001.png`, ...
+    pub fn frame_path(&self, index: usize) -> PathBuf {
+        self.output_dir.join(format!("frame_{:05}.png", index))
+    }
+}
+
+pub fn write_png(path: impl AsRef<Path>, width: u32, height: u32, rgba: &[u8]) -> Result<(), FrameError> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgba)?;
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use gl::types::*;
+
+/// An OpenGL buffer object, deleted when dropped
+pub struct Buffer {
+    pub id: GLuint,
+    /// Target the buffer is bound to, e.g. `gl::ARRAY_BUFFER`
+    pub target: GLenum
+}
+
+impl Buffer {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(target: GLenum) -> Self {
+        let mut id: GLuint = 0;
+        gl::GenBuffers(1, &mut id);
+        Self { id, target }
+    }
+
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.target, self.id);
+    }
+
+    /// Binds the buffer and replaces its contents, reallocating the storage
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn set_data<T>(&self, data: &[T], usage: GLenum) {
+        self.bind();
+        gl::BufferData(self.target, std::mem::size_of_val(data) as GLsizeiptr, data.as_ptr() as *const _, usage);
+    }
+
+    /// Binds the buffer and fills it with `parts`, one after the other
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn set_data_parts<T>(&self, parts: &[&[T]], usage: GLenum) {
+        self.allocate(parts.iter().map(|part| std::mem::size_of_val(*part)).sum(), usage);
+        let mut offset = 0;
+        for part in parts {
+            self.set_sub_data(offset, part);
+            offset += std::mem::size_of_val(*part);
+        }
+    }
+
+    /// Binds the buffer and allocates `size` bytes of uninitialized storage
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn allocate(&self, size: usize, usage: GLenum) {
+        self.bind();
+        gl::BufferData(self.target, size as GLsizeiptr, std::ptr::null(), usage);
+    }
+
+    /// Binds the buffer and overwrites part of its storage, starting `offset` bytes in
+    ///
+    /// # Safety
+    /// Needs the OpenGL context the buffer was created in to be current, and `data` has to fit in the storage
+    /// from `offset` on.
+    pub unsafe fn set_sub_data<T>(&self, offset: usize, data: &[T]) {
+        self.bind();
+        gl::BufferSubData(self.target, offset as GLintptr, std::mem::size_of_val(data) as GLsizeiptr, data.as_ptr() as *const _);
+    }
+
+    /// Binds the buffer and reads `data.len()` values from its storage, starting `offset` bytes in
+    ///
+    /// # Safety
+    /// Needs the OpenGL context the buffer was created in to be current, and the storage has to hold `data.len()`
+    /// values from `offset` on.
+    pub unsafe fn get_sub_data<T>(&self, offset: usize, data: &mut [T]) {
+        self.bind();
+        gl::GetBufferSubData(self.target, offset as GLintptr, std::mem::size_of_val(data) as GLsizeiptr, data.as_mut_ptr() as *mut _);
+    }
+
+    /// Binds the buffer to an indexed binding point of its target, e.g. a `binding = n` shader storage block
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind_base(&self, index: GLuint) {
+        gl::BindBufferBase(self.target, index, self.id);
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
+
+/// An OpenGL vertex array object, deleted when dropped
+pub struct VertexArray {
+    pub id: GLuint
+}
+
+impl VertexArray {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new() -> Self {
+        let mut id: GLuint = 0;
+        gl::GenVertexArrays(1, &mut id);
+        Self { id }
+    }
+
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind(&self) {
+        gl::BindVertexArray(self.id);
+    }
+
+    /// Reads float attribute `index` from `buffer`, with the stride and offset counted in floats.
+    /// Leaves the vertex array bound.
+    ///
+    /// # Safety
+    /// Needs the OpenGL context both objects were created in to be current.
+    pub unsafe fn float_attribute(&self, buffer: &Buffer, index: GLuint, components: GLint, stride: usize, offset: usize) {
+        let float_size = std::mem::size_of::<f32>();
+        self.bind();
+        buffer.bind();
+        gl::VertexAttribPointer(index, components, gl::FLOAT, gl::FALSE, (stride * float_size) as GLsizei, (offset * float_size) as *const _);
+        gl::EnableVertexAttribArray(index);
+    }
+}
+
+impl Drop for VertexArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.id);
+        }
+    }
+}
+
+/// An OpenGL texture, deleted when dropped
+pub struct Texture {
+    pub id: GLuint,
+    /// Target the texture is bound to, e.g. `gl::TEXTURE_2D`
+    pub target: GLenum
+}
+
+impl Texture {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(target: GLenum) -> Self {
+        let mut id: GLuint = 0;
+        gl::GenTextures(1, &mut id);
+        Self { id, target }
+    }
+
+    /// Binds the texture to texture unit `unit`
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind(&self, unit: GLuint) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(self.target, self.id);
+    }
+
+    /// Sets the min and mag filter and the wrap mode of every axis. The texture has to be bound.
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn set_sampling(&self, filter: GLenum, wrap: GLenum) {
+        gl::TexParameteri(self.target, gl::TEXTURE_MIN_FILTER, filter as GLint);
+        gl::TexParameteri(self.target, gl::TEXTURE_MAG_FILTER, filter as GLint);
+        gl::TexParameteri(self.target, gl::TEXTURE_WRAP_S, wrap as GLint);
+        if self.target != gl::TEXTURE_1D {
+            gl::TexParameteri(self.target, gl::TEXTURE_WRAP_T, wrap as GLint);
+        }
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
+
+/// An OpenGL renderbuffer with storage of a fixed format and size, deleted when dropped
+pub struct Renderbuffer {
+    pub id: GLuint
+}
+
+impl Renderbuffer {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(format: GLenum, width: u32, height: u32) -> Self {
+        let mut id: GLuint = 0;
+        gl::GenRenderbuffers(1, &mut id);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, id);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, format, width as GLsizei, height as GLsizei);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        Self { id }
+    }
+}
+
+impl Drop for Renderbuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteRenderbuffers(1, &self.id);
+        }
+    }
+}
+
+/// An OpenGL framebuffer object, deleted when dropped. Attachments are owned by the caller.
+pub struct Framebuffer {
+    pub id: GLuint
+}
+
+impl Framebuffer {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new() -> Self {
+        let mut id: GLuint = 0;
+        gl::GenFramebuffers(1, &mut id);
+        Self { id }
+    }
+
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind(&self, target: GLenum) {
+        gl::BindFramebuffer(target, self.id);
+    }
+
+    /// Binds the framebuffer and attaches `renderbuffer` to `attachment`, e.g. `gl::COLOR_ATTACHMENT0`
+    ///
+    /// # Safety
+    /// Needs the OpenGL context both objects were created in to be current.
+    pub unsafe fn attach_renderbuffer(&self, attachment: GLenum, renderbuffer: &Renderbuffer) {
+        self.bind(gl::FRAMEBUFFER);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer.id);
+    }
+
+    /// Binds the framebuffer and attaches level 0 of a 2D texture to `attachment`
+    ///
+    /// # Safety
+    /// Needs the OpenGL context both objects were created in to be current, and `texture` has to be a 2D texture.
+    pub unsafe fn attach_texture(&self, attachment: GLenum, texture: &Texture) {
+        self.bind(gl::FRAMEBUFFER);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, texture.target, texture.id, 0);
+    }
+
+    /// Returns the completeness status as an error if the bound framebuffer can't be rendered to
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn check_status(&self) -> Result<(), GLenum> {
+        self.bind(gl::FRAMEBUFFER);
+        match gl::CheckFramebufferStatus(gl::FRAMEBUFFER) {
+            gl::FRAMEBUFFER_COMPLETE => Ok(()),
+            status => Err(status)
+        }
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use gl::types::*;
+use thiserror::Error;
+use crate::gpu::Buffer;
+use crate::shader::{Defines, Shader, ShaderError};
+use crate::shaderprogram::ShaderProgram;
+use crate::simulation::{DOMAIN_MODE, MOUSE_STATE, Simulation};
+use crate::uniform_block::{Std140Block, UniformBuffer};
+extern crate nalgebra_glm as glm;
+
+/// Name of the block declared in shaders/include/simulation.glsl
+pub const SIMULATION_BLOCK: &str = "SimulationParams";
+/// Binding point of the simulation block. 0 is the frame block.
+pub const SIMULATION_BLOCK_BINDING: GLuint = 1;
+
+/// Invocations per workgroup, which is also the number of particles per shared memory tile
+const WORKGROUP_SIZE: usize = 64;
+
+//Shader storage binding points, matching shaders/include/simulation.glsl
+const POSITIONS_BINDING: GLuint = 0;
+const VELOCITIES_BINDING: GLuint = 1;
+const ACCELERATIONS_BINDING: GLuint = 2;
+const NEW_ACCELERATIONS_BINDING: GLuint = 3;
+const PINNED_BINDING: GLuint = 4;
+
+#[derive(Debug, Error)]
+#[error("The GPU simulation has {gpu} particles, but the simulation has {cpu}")]
+pub struct ParticleCountMismatch {
+    pub gpu: usize,
+    pub cpu: usize
+}
+
+/// Values of a microstep, taken from the CPU [`Simulation`]. The member order must match
+/// shaders/include/simulation.glsl.
+#[derive(Clone, Debug, Default)]
+pub struct SimulationParams {
+    pub mouse_position: glm::Vec2,
+    pub dt: f32,
+    pub attractive_force: f32,
+    pub repulsive_force: f32,
+    pub drag: f32,
+    /// 0 when gravity is off
+    pub gravity_strength: f32,
+    /// Negative when repelling, 0 when the mouse is inactive
+    pub mouse_strength: f32,
+    pub num_particles: i32,
+    pub domain_mode: i32,
+    pub mouse_radius: f32
+}
+
+impl SimulationParams {
+    pub fn from_simulation(simulation: &Simulation) -> Self {
+        let mouse_strength = match (simulation.mouse_active, &simulation.mouse_state) {
+            (false, _) => 0.0,
+            (true, MOUSE_STATE::ATTRACTIVE) => simulation.mouse_strength,
+            (true, MOUSE_STATE::REPULSIVE) => -simulation.mouse_strength
+        };
+
+        Self {
+            mouse_position: simulation.mouse_position(),
+            dt: simulation.dt,
+            attractive_force: simulation.attractive_force,
+            repulsive_force: simulation.repulsive_force,
+            drag: simulation.drag,
+            gravity_strength: if simulation.gravity { simulation.gravity_strength } else { 0.0 },
+            mouse_strength,
+            num_particles: simulation.particles.len() as i32,
+            //DOMAIN_WRAP, DOMAIN_INFINITE and DOMAIN_WALL in the shader
+            domain_mode: match simulation.domain_mode {
+                DOMAIN_MODE::WRAP => 0,
+                DOMAIN_MODE::INFINITE => 1,
+                DOMAIN_MODE::WALL => 2
+            },
+            mouse_radius: simulation.mouse_radius
+        }
+    }
+
+    pub fn to_std140(&self) -> Vec<u8> {
+        let mut block = Std140Block::new();
+        block.push(&self.mouse_position);
+        block.push(&self.dt);
+        block.push(&self.attractive_force);
+        block.push(&self.repulsive_force);
+        block.push(&self.drag);
+        block.push(&self.gravity_strength);
+        block.push(&self.mouse_strength);
+        block.push(&self.num_particles);
+        block.push(&self.domain_mode);
+        block.push(&self.mouse_radius);
+        block.finish()
+    }
+
+    /// Size of the block in bytes
+    pub fn size() -> usize {
+        Self::default().to_std140().len()
+    }
+}
+
+/// Runs the force evaluation and integration of a [`Simulation`] in compute shaders. Needs OpenGL 4.3.
+///
+/// The particle state lives in shader storage buffers, laid out like [`Simulation::pack`], so the renderer can read
+/// the positions without a copy. The CPU simulation only provides the parameters; its particles are left untouched
+/// until [`GpuSimulation::download`].
+pub struct GpuSimulation {
+    forces: ShaderProgram,
+    integrate: ShaderProgram,
+    params: UniformBuffer,
+    positions: Buffer,
+    velocities: Buffer,
+    accelerations: Buffer,
+    new_accelerations: Buffer,
+    /// 1 for pinned particles, one value per particle
+    pinned: Buffer,
+    num_particles: usize
+}
+
+impl GpuSimulation {
+    /// Compiles the compute shaders and uploads the particles of `simulation`
+    ///
+    /// # Safety
+    /// Needs a current OpenGL 4.3 context with loaded function pointers.
+    pub unsafe fn new(simulation: &Simulation) -> Result<Self, ShaderError> {
+        let defines = Defines::from([("WORKGROUP_SIZE".to_string(), WORKGROUP_SIZE.to_string())]);
+        let forces = ShaderProgram::new(vec!(Shader::new("forces".to_string(), include_str!("shaders/forces.comp"), gl::COMPUTE_SHADER, &defines)?))?;
+        let integrate = ShaderProgram::new(vec!(Shader::new("integrate".to_string(), include_str!("shaders/integrate.comp"), gl::COMPUTE_SHADER, &defines)?))?;
+        for program in [&forces, &integrate] {
+            program.bind_uniform_block(SIMULATION_BLOCK, SIMULATION_BLOCK_BINDING, SimulationParams::size())?;
+        }
+
+        let mut gpu_simulation = Self {
+            forces,
+            integrate,
+            params: UniformBuffer::new(SIMULATION_BLOCK_BINDING, SimulationParams::size()),
+            positions: Buffer::new(gl::SHADER_STORAGE_BUFFER),
+            velocities: Buffer::new(gl::SHADER_STORAGE_BUFFER),
+            accelerations: Buffer::new(gl::SHADER_STORAGE_BUFFER),
+            new_accelerations: Buffer::new(gl::SHADER_STORAGE_BUFFER),
+            pinned: Buffer::new(gl::SHADER_STORAGE_BUFFER),
+            num_particles: 0
+        };
+        gpu_simulation.upload(simulation);
+        Ok(gpu_simulation)
+    }
+
+    /// Replaces the particle state on the GPU with the particles of `simulation`
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn upload(&mut self, simulation: &Simulation) {
+        let particles = &simulation.particles;
+        let (ax, ay) = particles.accelerations();
+        self.positions.set_data_parts(&simulation.pack(), gl::DYNAMIC_COPY);
+        let (vx, vy) = particles.velocities();
+        self.velocities.set_data_parts(&[vx, vy], gl::DYNAMIC_COPY);
+        self.accelerations.set_data_parts(&[ax, ay], gl::DYNAMIC_COPY);
+        self.new_accelerations.set_data(&vec![0.0f32; 2 * particles.len()], gl::DYNAMIC_COPY);
+        let pinned: Vec<u32> = particles.pinned().iter().map(|pinned| *pinned as u32).collect();
+        self.pinned.set_data(&pinned, gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        self.num_particles = particles.len();
+    }
+
+    /// Runs `simulation.microsteps` microsteps with the current parameters of `simulation`
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn step(&mut self, simulation: &mut Simulation) {
+        self.params.update(SimulationParams::from_simulation(simulation).to_std140());
+        self.positions.bind_base(POSITIONS_BINDING);
+        self.velocities.bind_base(VELOCITIES_BINDING);
+        self.accelerations.bind_base(ACCELERATIONS_BINDING);
+        self.new_accelerations.bind_base(NEW_ACCELERATIONS_BINDING);
+        self.pinned.bind_base(PINNED_BINDING);
+
+        let workgroups = self.num_particles.div_ceil(WORKGROUP_SIZE) as GLuint;
+        for _ in 0..simulation.microsteps {
+            simulation.t += simulation.dt as f64;
+            if workgroups == 0 {
+                continue;
+            }
+            self.forces.apply();
+            gl::DispatchCompute(workgroups, 1, 1);
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+            self.integrate.apply();
+            gl::DispatchCompute(workgroups, 1, 1);
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+        }
+        //Later reads go through a buffer texture or glGetBufferSubData
+        gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT | gl::BUFFER_UPDATE_BARRIER_BIT);
+    }
+
+    /// Copies the particle state back into `simulation.particles`. Fails without changing them when their count
+    /// changed since the last upload.
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn download(&self, simulation: &mut Simulation) -> Result<(), ParticleCountMismatch> {
+        let particles = &mut simulation.particles;
+        if particles.len() != self.num_particles {
+            return Err(ParticleCountMismatch { gpu: self.num_particles, cpu: particles.len() });
+        }
+        //Byte offset of the y components
+        let y_offset = self.num_particles * std::mem::size_of::<f32>();
+
+        let (x, y) = particles.xy_mut();
+        self.positions.get_sub_data(0, x);
+        self.positions.get_sub_data(y_offset, y);
+        let (vx, vy) = particles.velocities_mut();
+        self.velocities.get_sub_data(0, vx);
+        self.velocities.get_sub_data(y_offset, vy);
+        let (ax, ay) = particles.accelerations_mut();
+        self.accelerations.get_sub_data(0, ax);
+        self.accelerations.get_sub_data(y_offset, ay);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        Ok(())
+    }
+
+    /// Reads back just the positions, all x coordinates followed by all y coordinates
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn read_positions(&self) -> Vec<f32> {
+        let mut positions = vec![0.0f32; 2 * self.num_particles];
+        self.positions.get_sub_data(0, &mut positions);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        positions
+    }
+
+    /// Copies the positions into `target` on the GPU, resizing it to fit
+    ///
+    /// # Safety
+    /// Needs the OpenGL context the simulation and `target` were created in to be current.
+    pub unsafe fn copy_positions(&self, target: &Buffer) {
+        let size = 2 * self.num_particles * std::mem::size_of::<f32>();
+        target.allocate(size, gl::STREAM_COPY);
+        gl::BindBuffer(gl::COPY_READ_BUFFER, self.positions.id);
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, target.id);
+        gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, 0, size as GLsizeiptr);
+    }
+
+    /// All x coordinates followed by all y coordinates, like [`Simulation::pack`]
+    pub fn positions(&self) -> &Buffer {
+        &self.positions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessContext;
+    use crate::simulation::SimulationConfig;
+
+    fn config() -> SimulationConfig {
+        SimulationConfig { num_particles: 100, seed: Some(11), gravity: true, gravity_strength: 10.0, domain_mode: DOMAIN_MODE::WALL, ..SimulationConfig::default() }
+    }
+
+    #[test]
+    #[ignore = "needs an OpenGL 4.3 context, run with `cargo test --features gpu-compute -- --ignored` (LIBGL_ALWAYS_SOFTWARE=1 uses llvmpipe)"]
+    fn matches_cpu_simulation() {
+        let _context = HeadlessContext::new(4, 3).expect("Cannot create an OpenGL 4.3 context");
+
+        let mut cpu = Simulation::from_config(&config());
+        let mut gpu_state = Simulation::from_config(&config());
+        for simulation in [&mut cpu, &mut gpu_state] {
+            simulation.set_mouse_position(0.4, 0.6);
+            simulation.set_mouse_force(Some(MOUSE_STATE::ATTRACTIVE));
+            simulation.particles.set_pinned(7, true);
+        }
+        let mut gpu = unsafe { GpuSimulation::new(&gpu_state) }.expect("Cannot create GPU simulation");
+
+        for _ in 0..5 {
+            cpu.step();
+            unsafe { gpu.step(&mut gpu_state) };
+        }
+        unsafe { gpu.download(&mut gpu_state) }.unwrap();
+
+        //The GPU sums the pair forces in a different order, so the results only agree up to rounding
+        for (i, (expected, found)) in cpu.particles.iter().zip(gpu_state.particles.iter()).enumerate() {
+            let position_error = glm::length(&(expected.position - found.position));
+            let velocity_error = glm::length(&(expected.velocity - found.velocity));
+            assert!(position_error < 1e-5, "Particle {} is at {:?} on the GPU, expected {:?}", i, found.position, expected.position);
+            assert!(velocity_error < 1e-5 * glm::length(&expected.velocity).max(1.0), "Particle {} moves at {:?} on the GPU, expected {:?}", i, found.velocity, expected.velocity);
+        }
+    }
+}
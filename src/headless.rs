@@ -0,0 +1,211 @@
+use std::fs;
+use gl::types::*;
+use khronos_egl as egl;
+use thiserror::Error;
+use crate::frames::{FrameError, SequenceOptions, write_png};
+use crate::gpu::{Framebuffer, Renderbuffer};
+use crate::renderer::Renderer;
+use crate::shader::ShaderError;
+use crate::uniform::UniformError;
+
+/// `EGL_PLATFORM_SURFACELESS_MESA`, which lets Mesa (including llvmpipe) create a context without any display server
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+#[derive(Debug, Error)]
+pub enum HeadlessError {
+    #[error("Cannot create headless OpenGL context: {0}")]
+    Context(String),
+    #[error("Offscreen framebuffer is incomplete (status {0:#x})")]
+    IncompleteFramebuffer(GLenum),
+    #[error("{0}")]
+    Shader(#[from] ShaderError),
+    #[error("{0}")]
+    Frame(#[from] FrameError),
+    #[error("{0}")]
+    Uniform(#[from] UniformError),
+}
+
+/// An OpenGL context with no window or default framebuffer, created through EGL's surfaceless platform.
+/// All rendering has to go to an [`OffscreenTarget`].
+pub struct HeadlessContext {
+    egl: egl::DynamicInstance<egl::EGL1_5>,
+    display: egl::Display,
+    context: egl::Context
+}
+
+impl HeadlessContext {
+    /// Creates a context and makes it current on this thread, loading the GL function pointers
+    pub fn new(major: i32, minor: i32) -> Result<Self, HeadlessError> {
+        let error = |e: &dyn std::fmt::Display| HeadlessError::Context(e.to_string());
+
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }.map_err(|e| error(&e))?;
+        let display = unsafe { egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE]) }
+            .map_err(|e| error(&e))?;
+        egl.initialize(display).map_err(|e| error(&e))?;
+        egl.bind_api(egl::OPENGL_API).map_err(|e| error(&e))?;
+
+        //The surfaceless platform usually exposes no configs, in which case EGL_KHR_no_config_context is used
+        let config = egl.choose_first_config(display, &[egl::RENDERABLE_TYPE, egl::OPENGL_BIT, egl::NONE])
+            .map_err(|e| error(&e))?
+            .unwrap_or_else(|| unsafe { egl::Config::from_ptr(std::ptr::null_mut()) });
+
+        let context = egl.create_context(display, config, None, &[
+            egl::CONTEXT_MAJOR_VERSION, major,
+            egl::CONTEXT_MINOR_VERSION, minor,
+            egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_COMPATIBILITY_PROFILE_BIT,
+            egl::NONE
+        ]).map_err(|e| error(&e))?;
+        egl.make_current(display, None, None, Some(context)).map_err(|e| error(&e))?;
+
+        gl::load_with(|name| egl.get_proc_address(name).map_or(std::ptr::null(), |f| f as *const _));
+
+        Ok(Self { egl, display, context })
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
+
+/// A framebuffer with an RGBA8 color attachment that frames can be rendered into and read back from
+pub struct OffscreenTarget {
+    pub framebuffer: Framebuffer,
+    /// Only held so the attachment lives as long as the framebuffer
+    #[allow(dead_code)]
+    color: Renderbuffer,
+    pub width: u32,
+    pub height: u32
+}
+
+impl OffscreenTarget {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(width: u32, height: u32) -> Result<Self, HeadlessError> {
+        let color = Renderbuffer::new(gl::RGBA8, width, height);
+        let framebuffer = Framebuffer::new();
+        framebuffer.attach_renderbuffer(gl::COLOR_ATTACHMENT0, &color);
+        framebuffer.check_status().map_err(HeadlessError::IncompleteFramebuffer)?;
+
+        Ok(Self { framebuffer, color, width, height })
+    }
+
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind(&self) {
+        self.framebuffer.bind(gl::FRAMEBUFFER);
+        gl::Viewport(0, 0, self.width as GLsizei, self.height as GLsizei);
+    }
+
+    /// Reads the frame back as tightly packed RGBA rows, top row first
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn read_rgba(&self) -> Vec<u8> {
+        let row_size = self.width as usize * 4;
+        let mut pixels = vec![0u8; row_size * self.height as usize];
+
+        self.framebuffer.bind(gl::READ_FRAMEBUFFER);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(0, 0, self.width as GLsizei, self.height as GLsizei, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+
+        //OpenGL's origin is the bottom left, images start at the top
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks_exact(row_size).rev() {
+            flipped.extend_from_slice(row);
+        }
+        flipped
+    }
+}
+
+/// Steps the renderer's simulation `options.steps` times, writing every `options.every`-th frame to
+/// `output_dir/frame_00000.png`, `frame_00001.png`, ... Returns the number of frames written.
+pub fn render_sequence(renderer: &mut Renderer, target: &OffscreenTarget, options: &SequenceOptions) -> Result<usize, HeadlessError> {
+    fs::create_dir_all(&options.output_dir).map_err(FrameError::from)?;
+
+    let mut frames = 0;
+    for step in 0..options.steps {
+        renderer.step();
+        if step % options.every != 0 {
+            continue;
+        }
+
+        unsafe {
+            target.bind();
+        }
+        renderer.render()?;
+
+        let rgba = unsafe { target.read_rgba() };
+        write_png(options.frame_path(frames), target.width, target.height, &rgba)?;
+        frames += 1;
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colormap::ColormapRegistry;
+    use crate::scene::RenderConfig;
+    use crate::simulation::{Simulation, SimulationConfig};
+    use crate::software::SoftwareRenderer;
+    use crate::view::View;
+
+    fn renderer(width: u32, height: u32) -> Renderer {
+        let simulation = Simulation::from_config(&SimulationConfig { num_particles: 50, seed: Some(1), ..SimulationConfig::default() });
+        let view = View::new(width, height, 1.0);
+        let mut renderer = Renderer::new(simulation, ColormapRegistry::default(), view, &RenderConfig::default()).expect("Cannot create renderer");
+        renderer.show_crosshair = false;
+        renderer.set_hud_visible(false);
+        renderer
+    }
+
+    #[test]
+    #[ignore = "needs EGL, LIBGL_ALWAYS_SOFTWARE=1 uses llvmpipe"]
+    fn renders_every_kth_step_to_png() {
+        let _context = HeadlessContext::new(3, 3).expect("Cannot create a headless OpenGL context");
+        let output_dir = std::env::temp_dir().join(format!("callisto-headless-{}", std::process::id()));
+        let options = SequenceOptions { steps: 7, every: 3, output_dir: output_dir.clone(), width: 48, height: 32 };
+        let target = unsafe { OffscreenTarget::new(options.width, options.height) }.unwrap();
+
+        //Steps 0, 3 and 6
+        let frames = render_sequence(&mut renderer(options.width, options.height), &target, &options).unwrap();
+        assert_eq!(frames, 3);
+
+        let mut names: Vec<_> = fs::read_dir(&output_dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["frame_00000.png", "frame_00001.png", "frame_00002.png"]);
+        for name in names {
+            let reader = png::Decoder::new(fs::File::open(output_dir.join(name)).unwrap()).read_info().unwrap();
+            let info = reader.info();
+            assert_eq!((info.width, info.height, info.color_type), (48, 32, png::ColorType::Rgba));
+        }
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs EGL, LIBGL_ALWAYS_SOFTWARE=1 uses llvmpipe"]
+    fn matches_software_renderer() {
+        let _context = HeadlessContext::new(3, 3).expect("Cannot create a headless OpenGL context");
+        let target = unsafe { OffscreenTarget::new(64, 64) }.unwrap();
+        let mut renderer = renderer(64, 64);
+        for _ in 0..3 {
+            renderer.step();
+        }
+        unsafe {
+            target.bind();
+        }
+        renderer.render().unwrap();
+        let gl_rgba = unsafe { target.read_rgba() };
+
+        let config = RenderConfig::default();
+        let colormaps = ColormapRegistry::default();
+        let colormap = colormaps.get(&config.colormap).unwrap();
+        let software_rgba = SoftwareRenderer::new(config.shader).render(&renderer.simulation, &renderer.view, colormap).to_vec();
+        assert_eq!(gl_rgba, software_rgba);
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use toml::Spanned;
+
+/// Everything that can be triggered from the keyboard or mouse. Names in bindings files are the lowercase
+/// variant names, e.g. `toggle_pause`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(non_camel_case_types)]
+pub enum ACTION {
+    /// Held: applies the current tool while the binding is down. The force tool attracts particles.
+    USE_TOOL,
+    /// Held: the cursor repels particles while the binding is down
+    REPEL,
+    /// Held: dragging the mouse pans the camera
+    PAN,
+    ZOOM_IN,
+    ZOOM_OUT,
+    GROW_MOUSE_RADIUS,
+    SHRINK_MOUSE_RADIUS,
+    STRENGTHEN_MOUSE,
+    WEAKEN_MOUSE,
+    TOOL_FORCE,
+    TOOL_SPAWN,
+    TOOL_ERASE,
+    TOOL_GRAB,
+    TOOL_PIN,
+    UNPIN_ALL,
+    NEXT_COLORMAP,
+    TOGGLE_HUD,
+    TOGGLE_HELP,
+    FIT_CAMERA,
+    TOGGLE_PAUSE,
+    /// Run a single step, pausing the simulation
+    STEP_ONCE,
+    SLOW_DOWN,
+    SPEED_UP,
+    /// Respawn the particles from the current seed
+    RESET,
+    /// Respawn the particles from a new random seed
+    RESEED,
+    TOGGLE_GRAVITY,
+    NEXT_DOMAIN_MODE,
+    /// Save the full simulation state to a snapshot file in the working directory
+    SAVE_SNAPSHOT
+}
+
+impl ACTION {
+    /// Name in bindings files
+    pub fn name(&self) -> String {
+        format!("{:?}", self).to_lowercase()
+    }
+
+    /// Shown next to the bindings in the help overlay
+    pub fn description(&self) -> &'static str {
+        match self {
+            ACTION::USE_TOOL => "Use the current tool (hold)",
+            ACTION::REPEL => "Repel particles (hold)",
+            ACTION::PAN => "Pan the camera (hold and drag)",
+            ACTION::ZOOM_IN => "Zoom in around the cursor",
+            ACTION::ZOOM_OUT => "Zoom out around the cursor",
+            ACTION::GROW_MOUSE_RADIUS => "Grow the mouse force radius",
+            ACTION::SHRINK_MOUSE_RADIUS => "Shrink the mouse force radius",
+            ACTION::STRENGTHEN_MOUSE => "Strengthen the mouse force",
+            ACTION::WEAKEN_MOUSE => "Weaken the mouse force",
+            ACTION::TOOL_FORCE => "Tool: attract particles",
+            ACTION::TOOL_SPAWN => "Tool: paint new particles",
+            ACTION::TOOL_ERASE => "Tool: delete particles",
+            ACTION::TOOL_GRAB => "Tool: drag particles",
+            ACTION::TOOL_PIN => "Tool: pin particles in place",
+            ACTION::UNPIN_ALL => "Release all pinned particles",
+            ACTION::NEXT_COLORMAP => "Next colormap",
+            ACTION::TOGGLE_HUD => "Toggle the HUD",
+            ACTION::TOGGLE_HELP => "Toggle this help",
+            ACTION::FIT_CAMERA => "Fit the camera to all particles",
+            ACTION::TOGGLE_PAUSE => "Pause or resume",
+            ACTION::STEP_ONCE => "Run a single step",
+            ACTION::SLOW_DOWN => "Halve the simulation speed",
+            ACTION::SPEED_UP => "Double the simulation speed",
+            ACTION::RESET => "Reset to the initial state",
+            ACTION::RESEED => "Restart with a new seed",
+            ACTION::TOGGLE_GRAVITY => "Toggle gravity",
+            ACTION::NEXT_DOMAIN_MODE => "Cycle the domain mode",
+            ACTION::SAVE_SNAPSHOT => "Save a snapshot"
+        }
+    }
+}
+
+/// Bindings used for every action a bindings file doesn't mention
+const DEFAULT_BINDINGS: &[(ACTION, &[&str])] = &[
+    (ACTION::USE_TOOL, &["MouseLeft"]),
+    (ACTION::REPEL, &["MouseRight"]),
+    (ACTION::PAN, &["MouseMiddle"]),
+    (ACTION::ZOOM_IN, &["WheelUp"]),
+    (ACTION::ZOOM_OUT, &["WheelDown"]),
+    (ACTION::GROW_MOUSE_RADIUS, &["Ctrl+WheelUp"]),
+    (ACTION::SHRINK_MOUSE_RADIUS, &["Ctrl+WheelDown"]),
+    (ACTION::STRENGTHEN_MOUSE, &["Shift+WheelUp"]),
+    (ACTION::WEAKEN_MOUSE, &["Shift+WheelDown"]),
+    (ACTION::TOOL_FORCE, &["Key1"]),
+    (ACTION::TOOL_SPAWN, &["Key2"]),
+    (ACTION::TOOL_ERASE, &["Key3"]),
+    (ACTION::TOOL_GRAB, &["Key4"]),
+    (ACTION::TOOL_PIN, &["Key5"]),
+    (ACTION::UNPIN_ALL, &["U"]),
+    (ACTION::NEXT_COLORMAP, &["C"]),
+    (ACTION::TOGGLE_HUD, &["H"]),
+    (ACTION::TOGGLE_HELP, &["F1"]),
+    (ACTION::FIT_CAMERA, &["F"]),
+    (ACTION::TOGGLE_PAUSE, &["Space"]),
+    (ACTION::STEP_ONCE, &["Period"]),
+    (ACTION::SLOW_DOWN, &["LBracket"]),
+    (ACTION::SPEED_UP, &["RBracket"]),
+    (ACTION::RESET, &["R"]),
+    (ACTION::RESEED, &["N"]),
+    (ACTION::TOGGLE_GRAVITY, &["G"]),
+    (ACTION::NEXT_DOMAIN_MODE, &["D"]),
+    (ACTION::SAVE_SNAPSHOT, &["Ctrl+S"])
+];
+
+/// Key names accepted in bindings files. These are the names of winit's `VirtualKeyCode`, which is what the
+/// window reports.
+const KEY_NAMES: &[&str] = &[
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z",
+    "Key0", "Key1", "Key2", "Key3", "Key4", "Key5", "Key6", "Key7", "Key8", "Key9",
+    "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+    "Escape", "Space", "Return", "Tab", "Back", "Delete", "Insert", "Home", "End", "PageUp", "PageDown",
+    "Left", "Right", "Up", "Down",
+    "LControl", "RControl", "LShift", "RShift", "LAlt", "RAlt", "LWin", "RWin",
+    "Minus", "Equals", "LBracket", "RBracket", "Backslash", "Semicolon", "Apostrophe", "Comma", "Period", "Slash", "Grave",
+    "Numpad0", "Numpad1", "Numpad2", "Numpad3", "Numpad4", "Numpad5", "Numpad6", "Numpad7", "Numpad8", "Numpad9",
+    "NumpadAdd", "NumpadSubtract", "NumpadMultiply", "NumpadDivide", "NumpadDecimal", "NumpadEnter"
+];
+
+#[derive(Debug, Error)]
+pub enum BindingsError {
+    #[error("Cannot read bindings {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Cannot parse bindings {path}: {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("Invalid bindings {path}:\n  {}", .problems.join("\n  "))]
+    Invalid { path: PathBuf, problems: Vec<String> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[allow(non_camel_case_types)]
+pub enum MOUSE_BUTTON {
+    LEFT,
+    RIGHT,
+    MIDDLE
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[allow(non_camel_case_types)]
+pub enum WHEEL_DIRECTION {
+    UP,
+    DOWN
+}
+
+/// A physical key, by its winit name, a mouse button or a scroll wheel direction
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(String),
+    Mouse(MOUSE_BUTTON),
+    Wheel(WHEEL_DIRECTION)
+}
+
+/// Modifier keys held down
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    /// The Windows, Command or Super key
+    pub logo: bool
+}
+
+impl Modifiers {
+    fn flags(&self) -> [bool; 4] {
+        [self.ctrl, self.shift, self.alt, self.logo]
+    }
+
+    fn count(&self) -> usize {
+        self.flags().iter().filter(|held| **held).count()
+    }
+
+    /// True if every modifier of `other` is held in `self`
+    fn contains(&self, other: &Modifiers) -> bool {
+        self.flags().iter().zip(other.flags()).all(|(held, needed)| *held || !needed)
+    }
+}
+
+/// An input plus the modifiers it needs, written like `Ctrl+Shift+R` or `MouseLeft`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Binding {
+    pub modifiers: Modifiers,
+    pub input: Input
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let mut parts: Vec<&str> = value.split('+').map(str::trim).collect();
+        let name = parts.pop().filter(|name| !name.is_empty()).ok_or_else(|| format!("\"{}\" has no key or button", value))?;
+
+        let mut modifiers = Modifiers::default();
+        for modifier in parts {
+            match modifier {
+                "Ctrl" => modifiers.ctrl = true,
+                "Shift" => modifiers.shift = true,
+                "Alt" => modifiers.alt = true,
+                "Super" => modifiers.logo = true,
+                _ => return Err(format!("\"{}\" has an unknown modifier \"{}\" (expected Ctrl, Shift, Alt or Super)", value, modifier))
+            }
+        }
+
+        let input = match name {
+            "MouseLeft" => Input::Mouse(MOUSE_BUTTON::LEFT),
+            "MouseRight" => Input::Mouse(MOUSE_BUTTON::RIGHT),
+            "MouseMiddle" => Input::Mouse(MOUSE_BUTTON::MIDDLE),
+            "WheelUp" => Input::Wheel(WHEEL_DIRECTION::UP),
+            "WheelDown" => Input::Wheel(WHEEL_DIRECTION::DOWN),
+            _ if KEY_NAMES.contains(&name) => Input::Key(name.to_string()),
+            _ => return Err(format!("\"{}\" has an unknown key \"{}\"", value, name))
+        };
+
+        Ok(Self { modifiers, input })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = ["Ctrl", "Shift", "Alt", "Super"];
+        for (name, held) in names.iter().zip(self.modifiers.flags()) {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match &self.input {
+            Input::Key(name) => write!(f, "{}", name),
+            Input::Mouse(MOUSE_BUTTON::LEFT) => write!(f, "MouseLeft"),
+            Input::Mouse(MOUSE_BUTTON::RIGHT) => write!(f, "MouseRight"),
+            Input::Mouse(MOUSE_BUTTON::MIDDLE) => write!(f, "MouseMiddle"),
+            Input::Wheel(WHEEL_DIRECTION::UP) => write!(f, "WheelUp"),
+            Input::Wheel(WHEEL_DIRECTION::DOWN) => write!(f, "WheelDown")
+        }
+    }
+}
+
+/// Which inputs trigger which actions. Loaded from a TOML file mapping action names to lists of bindings,
+/// e.g. `toggle_pause = ["Space", "P"]`. Actions the file leaves out keep their default bindings, and an
+/// empty list unbinds an action.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    bindings: BTreeMap<ACTION, Vec<Binding>>
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS.iter()
+            .map(|(action, bindings)| (*action, bindings.iter().map(|binding| binding.parse().expect("Invalid default binding")).collect()))
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Bindings {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindingsError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| BindingsError::Io { path: path.to_path_buf(), source })?;
+        Self::parse(path, &source)
+    }
+
+    /// Parses a bindings file. An input with the same modifiers can only be bound to one action, including the
+    /// default bindings of actions the file leaves out.
+    pub fn parse(path: impl AsRef<Path>, source: &str) -> Result<Self, BindingsError> {
+        let path = path.as_ref();
+        let file: BTreeMap<ACTION, Vec<Spanned<String>>> = toml::from_str(source).map_err(|source| BindingsError::Parse { path: path.to_path_buf(), source })?;
+        let line = |value: &Spanned<String>| source[..value.span().start].matches('\n').count() + 1;
+
+        let mut bindings = Self::default();
+        let mut problems = Vec::new();
+        //Line of every binding from the file, to report conflicts at
+        let mut lines = HashMap::new();
+        for (action, values) in file {
+            let mut parsed = Vec::new();
+            for value in &values {
+                match value.get_ref().parse::<Binding>() {
+                    Ok(binding) => {
+                        lines.insert((action, binding.clone()), line(value));
+                        parsed.push(binding);
+                    },
+                    Err(e) => problems.push((line(value), e))
+                }
+            }
+            bindings.bindings.insert(action, parsed);
+        }
+
+        let mut bound: HashMap<&Binding, ACTION> = HashMap::new();
+        for (action, binding) in bindings.bindings.iter().flat_map(|(action, bindings)| bindings.iter().map(move |binding| (*action, binding))) {
+            let Some(other) = bound.insert(binding, action).filter(|other| *other != action) else { continue };
+            //Reported at the later of the two lines. At least one of them is in the file, since the defaults don't conflict.
+            let line = [action, other].iter().filter_map(|action| lines.get(&(*action, binding.clone()))).max().copied().unwrap_or_default();
+            problems.push((line, format!("\"{}\" is bound to both {} and {}", binding, other.name(), action.name())));
+        }
+
+        if problems.is_empty() {
+            Ok(bindings)
+        } else {
+            problems.sort();
+            Err(BindingsError::Invalid { path: path.to_path_buf(), problems: problems.into_iter().map(|(line, problem)| format!("line {}: {}", line, problem)).collect() })
+        }
+    }
+
+    /// The action bound to `input`. When several bindings match, the one needing the most modifiers wins, so
+    /// `Ctrl+R` takes precedence over `R` while Ctrl is held.
+    pub fn action(&self, input: &Input, modifiers: Modifiers) -> Option<ACTION> {
+        self.bindings.iter()
+            .flat_map(|(action, bindings)| bindings.iter().map(move |binding| (action, binding)))
+            .filter(|(_, binding)| binding.input == *input && modifiers.contains(&binding.modifiers))
+            .max_by_key(|(_, binding)| binding.modifiers.count())
+            .map(|(action, _)| *action)
+    }
+
+    /// The bindings of `action`, comma separated, or None when it is unbound
+    pub fn describe(&self, action: ACTION) -> Option<String> {
+        let bindings = self.bindings.get(&action).filter(|bindings| !bindings.is_empty())?;
+        Some(bindings.iter().map(|binding| binding.to_string()).collect::<Vec<_>>().join(", "))
+    }
+
+    /// One `bindings: description` line per bound action, for the help overlay
+    pub fn help_lines(&self) -> Vec<String> {
+        self.bindings.keys()
+            .filter_map(|action| self.describe(*action).map(|bindings| format!("{}: {}", bindings, action.description())))
+            .collect()
+    }
+}
+
+/// Turns raw presses and releases into actions, tracking the modifiers and which actions are held
+#[derive(Clone, Debug, Default)]
+pub struct InputMapper {
+    pub bindings: Bindings,
+    modifiers: Modifiers,
+    /// Inputs that are down, with the action they started
+    held: Vec<(Input, ACTION)>
+}
+
+impl InputMapper {
+    pub fn new(bindings: Bindings) -> Self {
+        Self { bindings, ..Self::default() }
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: Modifiers) {
+        self.modifiers = modifiers;
+    }
+
+    /// The action started by pressing `input`. Key repeats of an input that is already down are ignored.
+    pub fn press(&mut self, input: Input) -> Option<ACTION> {
+        if self.held.iter().any(|(held, _)| *held == input) {
+            return None;
+        }
+        let action = self.bindings.action(&input, self.modifiers)?;
+        self.held.push((input, action));
+        Some(action)
+    }
+
+    /// The action bound to scrolling by `steps` wheel notches. Scrolling has no release, so nothing is held.
+    pub fn scroll(&self, steps: f32) -> Option<ACTION> {
+        let direction = if steps < 0.0 { WHEEL_DIRECTION::DOWN } else { WHEEL_DIRECTION::UP };
+        self.bindings.action(&Input::Wheel(direction), self.modifiers)
+    }
+
+    /// The action that pressing `input` started, which ends now. This doesn't depend on the modifiers, so
+    /// releasing Ctrl before the key still ends a `Ctrl+...` action.
+    pub fn release(&mut self, input: Input) -> Option<ACTION> {
+        let index = self.held.iter().position(|(held, _)| *held == input)?;
+        Some(self.held.remove(index).1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> Input {
+        Input::Key(name.to_string())
+    }
+
+    #[test]
+    fn bindings_round_trip() {
+        for value in ["R", "Ctrl+Shift+R", "Alt+MouseRight", "Super+F1", "Ctrl+WheelDown"] {
+            assert_eq!(value.parse::<Binding>().unwrap().to_string(), value);
+        }
+        assert!("Hyper+R".parse::<Binding>().is_err());
+        assert!("Ctrl+".parse::<Binding>().is_err());
+        assert!("Ctrl+Enter".parse::<Binding>().is_err());
+    }
+
+    #[test]
+    fn default_file_matches_built_in_defaults() {
+        let file = Bindings::parse("bindings/default.toml", include_str!("../bindings/default.toml")).unwrap();
+        assert_eq!(file.help_lines(), Bindings::default().help_lines());
+    }
+
+    #[test]
+    fn files_override_and_unbind_actions() {
+        let bindings = Bindings::parse("test.toml", "reset = [\"Ctrl+R\"]\nreseed = []\n").unwrap();
+        let ctrl = Modifiers { ctrl: true, ..Modifiers::default() };
+        assert_eq!(bindings.action(&key("R"), ctrl), Some(ACTION::RESET));
+        assert_eq!(bindings.action(&key("R"), Modifiers::default()), None);
+        assert_eq!(bindings.action(&key("N"), Modifiers::default()), None);
+        assert_eq!(bindings.action(&key("Space"), ctrl), Some(ACTION::TOGGLE_PAUSE));
+
+        let error = Bindings::parse("test.toml", "reset = [\"Ctrl+Foo\", \"Meta+R\"]\n").unwrap_err().to_string();
+        assert!(error.contains("Foo") && error.contains("Meta"), "{}", error);
+        assert!(Bindings::parse("test.toml", "explode = [\"E\"]\n").is_err());
+    }
+
+    #[test]
+    fn rejects_conflicting_bindings() {
+        let error = Bindings::parse("test.toml", "reset = [\"Ctrl+R\"]\n\nreseed = [\"N\", \"Ctrl+R\"]\n").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid bindings test.toml:\n  line 3: \"Ctrl+R\" is bound to both reset and reseed");
+
+        //Default bindings of actions the file leaves out count as well
+        let error = Bindings::parse("test.toml", "reset = [\"R\"]\nreseed = [\"Space\"]\n").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid bindings test.toml:\n  line 2: \"Space\" is bound to both toggle_pause and reseed");
+
+        let error = Bindings::parse("test.toml", "reset = [\"Ctrl+Foo\"]\nreseed = [\"R\"]\n").unwrap_err();
+        assert!(error.to_string().contains("line 1: \"Ctrl+Foo\" has an unknown key"), "{}", error);
+
+        //Different modifiers don't conflict, and neither does binding the same input twice to one action
+        Bindings::parse("test.toml", "reset = [\"R\", \"R\"]\nreseed = [\"Shift+R\"]\n").unwrap();
+    }
+
+    #[test]
+    fn most_specific_binding_wins() {
+        let bindings = Bindings::parse("test.toml", "reset = [\"R\"]\nreseed = [\"Ctrl+R\"]\n").unwrap();
+        let ctrl = Modifiers { ctrl: true, ..Modifiers::default() };
+        assert_eq!(bindings.action(&key("R"), Modifiers::default()), Some(ACTION::RESET));
+        assert_eq!(bindings.action(&key("R"), ctrl), Some(ACTION::RESEED));
+    }
+
+    #[test]
+    fn held_actions_end_on_release() {
+        let mut mapper = InputMapper::default();
+        mapper.set_modifiers(Modifiers { shift: true, ..Modifiers::default() });
+        assert_eq!(mapper.press(Input::Mouse(MOUSE_BUTTON::MIDDLE)), Some(ACTION::PAN));
+        assert_eq!(mapper.press(Input::Mouse(MOUSE_BUTTON::MIDDLE)), None);
+        mapper.set_modifiers(Modifiers::default());
+        assert_eq!(mapper.release(Input::Mouse(MOUSE_BUTTON::MIDDLE)), Some(ACTION::PAN));
+        assert_eq!(mapper.release(Input::Mouse(MOUSE_BUTTON::MIDDLE)), None);
+    }
+
+    #[test]
+    fn modifiers_select_wheel_action() {
+        let mut mapper = InputMapper::default();
+        assert_eq!(mapper.scroll(1.0), Some(ACTION::ZOOM_IN));
+        assert_eq!(mapper.scroll(-0.5), Some(ACTION::ZOOM_OUT));
+        mapper.set_modifiers(Modifiers { ctrl: true, ..Modifiers::default() });
+        assert_eq!(mapper.scroll(-1.0), Some(ACTION::SHRINK_MOUSE_RADIUS));
+        mapper.set_modifiers(Modifiers { shift: true, ..Modifiers::default() });
+        assert_eq!(mapper.scroll(2.0), Some(ACTION::STRENGTHEN_MOUSE));
+    }
+}
@@ -0,0 +1,44 @@
+//! Particle simulation with a density visualization.
+//!
+//! The simulation core ([`Simulation`], [`Particle`], the step scheduler, input bindings, brush tools, scene files, snapshots, colormaps and the CPU renderer) has no
+//! OpenGL dependency. The OpenGL renderer, shaders and headless rendering are behind the `render` feature, and
+//! the compute shader simulation backend behind `gpu-compute`.
+//!
+//! # Safety
+//! The `unsafe` functions of the render layer call OpenGL. They need a current context whose function pointers
+//! have been loaded with `gl::load_with`, on the thread that made it current.
+
+pub mod particle;
+pub mod simulation;
+pub mod colormap;
+pub mod scene;
+pub mod view;
+pub mod frames;
+pub mod scheduler;
+pub mod input;
+pub mod tools;
+pub mod snapshot;
+pub mod software;
+
+#[cfg(feature = "render")]
+pub mod shader;
+#[cfg(feature = "render")]
+pub mod shaderprogram;
+#[cfg(feature = "render")]
+pub mod uniform;
+#[cfg(feature = "render")]
+pub mod uniform_block;
+#[cfg(feature = "render")]
+pub mod gpu;
+#[cfg(feature = "render")]
+pub mod renderer;
+#[cfg(feature = "render")]
+pub mod headless;
+#[cfg(feature = "gpu-compute")]
+pub mod gpu_simulation;
+
+pub use particle::Particle;
+pub use scene::Scene;
+pub use simulation::{Simulation, SimulationConfig};
+#[cfg(feature = "render")]
+pub use renderer::Renderer;
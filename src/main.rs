@@ -1,26 +1,301 @@
-mod shader;
-mod shaderprogram;
-mod renderer;
-mod uniform;
-mod particle;
-mod simulation;
+mod cli;
+#[cfg(feature = "software-window")]
+mod software_window;
 
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::time::Instant;
+use clap::Parser;
 use glutin::{Api, ContextBuilder, GlRequest};
-use glutin::event::{Event, WindowEvent};
-use glutin::event::WindowEvent::MouseInput;
+use glutin::event::{ElementState, Event, MouseButton, WindowEvent};
 use glutin::event_loop::{ControlFlow, EventLoop};
 use glutin::window::WindowBuilder;
 
-use crate::renderer::Renderer;
+use callisto::colormap::ColormapRegistry;
+use callisto::frames::SequenceOptions;
+use callisto::headless::{self, HeadlessContext, HeadlessError, OffscreenTarget};
+use callisto::input::{Bindings, Input, InputMapper, MOUSE_BUTTON, Modifiers};
+use callisto::renderer::{ACTION, Renderer};
+use callisto::scene::Scene;
+use callisto::shader::ShaderError;
+use callisto::simulation::Simulation;
+use callisto::snapshot::{SNAPSHOT_FORMAT, SnapshotError};
+use callisto::software;
+use callisto::view::View;
+use crate::cli::{BenchArgs, Cli, Command, ExportArgs, RenderArgs, RunArgs, SceneArgs, ValidateArgs};
+
+fn exit_with_error(e: impl std::fmt::Display) -> ! {
+    eprintln!("Error: {}", e);
+    process::exit(1);
+}
+
+fn load_colormaps() -> ColormapRegistry {
+    let mut colormaps = ColormapRegistry::default();
+    match colormaps.load_dir("colormaps") {
+        Ok(0) => (),
+        Ok(count) => println!("Loaded {} user colormaps", count),
+        Err(e) => eprintln!("Error: {}", e)
+    }
+    colormaps
+}
+
+fn load_scene(args: &SceneArgs, colormaps: &ColormapRegistry) -> Scene {
+    let (scene, path) = args.load(colormaps).unwrap_or_else(|e| exit_with_error(e));
+    if let Some(path) = path {
+        println!("Loaded scene {}", path.display());
+    }
+    scene
+}
+
+fn load_simulation(args: &SceneArgs, scene: &Scene) -> Simulation {
+    let simulation = args.simulation(scene).unwrap_or_else(|e| exit_with_error(e));
+    if let Some(path) = &args.snapshot {
+        println!("Loaded snapshot {}", path.display());
+    }
+    simulation
+}
+
+/// Prints the errors, warnings and notes the library logs, e.g. shader compiler warnings, after their level
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        let level = match record.level() {
+            log::Level::Error => "Error",
+            log::Level::Warn => "Warning",
+            log::Level::Info => "Note",
+            _ => return
+        };
+        eprintln!("{}: {}", level, record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
 
 fn main() {
+    log::set_logger(&LOGGER).expect("Cannot install logger");
+    log::set_max_level(log::LevelFilter::Info);
+    match Cli::parse().into_command() {
+        Command::Run(args) => run(args),
+        Command::Render(args) => render(args),
+        Command::Bench(args) => bench(args),
+        Command::Export(args) => export(args),
+        Command::Validate(args) => validate(args)
+    }
+}
+
+fn render(args: RenderArgs) {
+    let colormaps = load_colormaps();
+    let scene = load_scene(&args.scene, &colormaps);
+    let simulation = load_simulation(&args.scene, &scene);
+    let (width, height) = args.size.unwrap_or((scene.window.width, scene.window.height));
+    let options = SequenceOptions { steps: args.steps, every: args.every.get(), output_dir: args.output, width, height };
+
+    let frames = if args.software {
+        let colormap = colormaps.get(&scene.render.colormap).expect("Missing colormap");
+        let mut simulation = simulation;
+        software::render_sequence(&mut simulation, colormap, &scene.render.shader, &options).map_err(HeadlessError::from)
+    } else {
+        render_headless(simulation, &scene, colormaps, &options, args.gpu)
+    }.unwrap_or_else(|e| exit_with_error(e));
+    println!("Wrote {} frames to {}", frames, options.output_dir.display());
+
+    if let Some(video) = args.video {
+        encode_video(&options.output_dir, args.fps, &video).unwrap_or_else(|e| exit_with_error(e));
+        println!("Wrote {}", video.display());
+    }
+}
+
+/// OpenGL version to request. Compute shaders need 4.3.
+fn gl_version(gpu: bool) -> (u8, u8) {
+    if gpu { (4, 3) } else { (3, 3) }
+}
+
+fn render_headless(simulation: Simulation, scene: &Scene, colormaps: ColormapRegistry, options: &SequenceOptions, gpu: bool) -> Result<usize, HeadlessError> {
+    let (major, minor) = gl_version(gpu);
+    let _context = HeadlessContext::new(major as i32, minor as i32)?;
+    let target = unsafe { OffscreenTarget::new(options.width, options.height) }?;
+
+    let view = View::new(options.width, options.height, 1.0);
+    let mut renderer = Renderer::new(simulation, colormaps, view, &scene.render)?;
+    renderer.show_crosshair = false;
+    renderer.set_hud_visible(false);
+    if gpu {
+        enable_gpu_simulation(&mut renderer)?;
+    }
+
+    headless::render_sequence(&mut renderer, &target, options)
+}
+
+/// Encodes `frame_00000.png`, `frame_00001.png`, ... into a video with the ffmpeg executable
+fn encode_video(frames_dir: &Path, fps: u32, output: &Path) -> Result<(), String> {
+    let status = process::Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-framerate", &fps.to_string(), "-i"])
+        .arg(frames_dir.join("frame_%05d.png"))
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(output)
+        .status()
+        .map_err(|e| format!("Cannot run ffmpeg: {}", e))?;
+
+    if !status.success() {
+        return Err(format!("ffmpeg failed ({})", status));
+    }
+    Ok(())
+}
+
+fn bench(args: BenchArgs) {
+    let scene = load_scene(&args.scene, &load_colormaps());
+    let mut simulation = load_simulation(&args.scene, &scene);
+
+    for _ in 0..args.warmup {
+        simulation.step();
+    }
+
+    let start = Instant::now();
+    for _ in 0..args.steps {
+        simulation.step();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let steps = args.steps.max(1) as f64;
+    println!("{} particles, {} microsteps per step", simulation.particles.len(), simulation.microsteps);
+    println!("{} steps in {:.3} s", args.steps, elapsed);
+    println!("{:.3} ms/step, {:.1} steps/s", elapsed * 1000.0 / steps, steps / elapsed);
+}
+
+fn export(args: ExportArgs) {
+    let scene = load_scene(&args.scene, &load_colormaps());
+    let mut simulation = load_simulation(&args.scene, &scene);
+
+    let rows = write_trajectories(&mut simulation, args.steps, args.every.get(), &args.output).unwrap_or_else(|e| exit_with_error(e));
+    println!("Wrote {} rows to {}", rows, args.output.display());
+}
+
+/// Writes `step,time,particle,x,y,vx,vy` rows for the initial state and every `every`-th step after it.
+/// `time` is `Simulation::t`, so a run resumed from a snapshot continues its clock. Returns the number of rows written.
+fn write_trajectories(simulation: &mut Simulation, steps: usize, every: usize, path: &Path) -> std::io::Result<usize> {
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "step,time,particle,x,y,vx,vy")?;
+
+    let mut rows = 0;
+    for step in 0..=steps {
+        if step > 0 {
+            simulation.step();
+        }
+        if step % every != 0 {
+            continue;
+        }
+
+        for (i, particle) in simulation.particles.iter().enumerate() {
+            writeln!(file, "{},{},{},{},{},{},{}", step, simulation.t, i, particle.position.x, particle.position.y, particle.velocity.x, particle.velocity.y)?;
+            rows += 1;
+        }
+    }
+
+    file.flush()?;
+    Ok(rows)
+}
+
+fn validate(args: ValidateArgs) {
+    let colormaps = load_colormaps();
+    let mut failed = false;
+
+    for path in &args.scenes {
+        let result = Scene::load(path).and_then(|scene| scene.validate_colormap(path, &colormaps));
+        match result {
+            Ok(()) => println!("{}: OK", path.display()),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+#[cfg(feature = "gpu-compute")]
+fn enable_gpu_simulation(renderer: &mut Renderer) -> Result<(), ShaderError> {
+    renderer.enable_gpu_simulation()
+}
+
+#[cfg(not(feature = "gpu-compute"))]
+fn enable_gpu_simulation(_renderer: &mut Renderer) -> Result<(), ShaderError> {
+    eprintln!("Error: --gpu requires the gpu-compute feature. Build with `--features gpu-compute`.");
+    process::exit(1);
+}
+
+#[cfg(feature = "software-window")]
+fn run_software_window(simulation: Simulation, scene: Scene, colormaps: ColormapRegistry, bindings: Bindings, snapshot_format: SNAPSHOT_FORMAT) {
+    let (width, height) = (scene.window.width as usize, scene.window.height as usize);
+    software_window::run(simulation, colormaps, InputMapper::new(bindings), &scene.render, &scene.schedule, snapshot_format, width, height)
+        .expect("Cannot run software window");
+}
+
+#[cfg(not(feature = "software-window"))]
+fn run_software_window(_simulation: Simulation, _scene: Scene, _colormaps: ColormapRegistry, _bindings: Bindings, _snapshot_format: SNAPSHOT_FORMAT) {
+    eprintln!("Error: the software window requires the software-window feature. Use `callisto render --software` to render frames instead.");
+    process::exit(1);
+}
+
+/// Prints the message or error of an action
+fn report(result: Result<Option<String>, SnapshotError>) {
+    match result {
+        Ok(Some(message)) => println!("{}", message),
+        Ok(None) => (),
+        Err(e) => eprintln!("Error: {}", e)
+    }
+}
+
+/// Feeds a press or release to the mapper and the resulting action to the renderer
+fn on_input(mapper: &mut InputMapper, renderer: &mut Renderer, input: Input, state: ElementState) {
+    match state {
+        ElementState::Pressed => if let Some(action) = mapper.press(input) {
+            report(renderer.on_action(action));
+        },
+        ElementState::Released => if let Some(action) = mapper.release(input) {
+            renderer.on_action_release(action);
+        }
+    }
+}
+
+fn run(args: RunArgs) {
+    let colormaps = load_colormaps();
+    let scene = load_scene(&args.scene, &colormaps);
+    let (bindings, path) = args.load_bindings().unwrap_or_else(|e| exit_with_error(e));
+    if let Some(path) = path {
+        println!("Loaded bindings {}", path.display());
+    }
+    let simulation = load_simulation(&args.scene, &scene);
+
+    if args.software {
+        return run_software_window(simulation, scene, colormaps, bindings, args.snapshot_format);
+    }
+
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().with_title("Callisto").with_inner_size(glutin::dpi::LogicalSize::new(800.0, 800.0));
+    let window = WindowBuilder::new()
+        .with_title(&scene.window.title)
+        .with_inner_size(glutin::dpi::LogicalSize::new(scene.window.width, scene.window.height));
 
-    let gl_context = ContextBuilder::new()
-        .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
-        .build_windowed(window, &event_loop)
-        .expect("Cannot create windowed context");
+    let gl_context = match ContextBuilder::new()
+        .with_gl(GlRequest::Specific(Api::OpenGl, gl_version(args.gpu)))
+        .build_windowed(window, &event_loop) {
+        Ok(gl_context) => gl_context,
+        Err(e) => {
+            eprintln!("Cannot create windowed context ({}), falling back to the software renderer", e);
+            return run_software_window(simulation, scene, colormaps, bindings, args.snapshot_format);
+        }
+    };
 
     let gl_context = unsafe {
         gl_context
@@ -30,46 +305,105 @@ fn main() {
 
     gl::load_with(|ptr| gl_context.get_proc_address(ptr) as *const _);
 
-    let mut renderer = Renderer::new().expect("Cannot create renderer");
+    let window_size = gl_context.window().inner_size();
+    let view = View::new(window_size.width, window_size.height, gl_context.window().scale_factor());
+    let mut renderer = Renderer::new(simulation, colormaps, view, &scene.render).unwrap_or_else(|e| exit_with_error(e));
+    renderer.set_schedule(&scene.schedule);
+    renderer.set_tools(&scene.tools);
+    renderer.set_snapshot_format(args.snapshot_format);
+    if args.gpu {
+        if scene.schedule.threaded {
+            eprintln!("The GPU simulation runs on the render thread, ignoring schedule.threaded");
+        }
+        enable_gpu_simulation(&mut renderer).unwrap_or_else(|e| exit_with_error(e));
+    }
+    renderer.set_help(bindings.help_lines(), bindings.describe(ACTION::TOGGLE_HELP));
+    let mut mapper = InputMapper::new(bindings);
 
     event_loop.run(move |event, _, control_flow| {
         match event {
             Event::LoopDestroyed => (),
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::Resized(physical_size) => {
-                    println!("Window resized");
-                    gl_context.resize(physical_size)
+                    gl_context.resize(physical_size);
+                    renderer.resize(physical_size.width, physical_size.height);
+                },
+                WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                    gl_context.resize(*new_inner_size);
+                    renderer.resize(new_inner_size.width, new_inner_size.height);
+                    if let Err(e) = renderer.set_scale_factor(scale_factor) {
+                        eprintln!("Error: {}", e);
+                    }
                 },
                 WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                 WindowEvent::CursorMoved { position, .. } => {
                     renderer.set_mouse_position(position.x as f32, position.y as f32);
                 },
-                MouseInput { button, .. } => {
-                    match button {
-                        glutin::event::MouseButton::Left => {
-                            renderer.on_mouse_click();
-                        },
-                        _ => ()
+                WindowEvent::ModifiersChanged(state) => {
+                    mapper.set_modifiers(Modifiers { ctrl: state.ctrl(), shift: state.shift(), alt: state.alt(), logo: state.logo() });
+                },
+                WindowEvent::MouseInput { button, state, .. } => {
+                    let button = match button {
+                        MouseButton::Left => MOUSE_BUTTON::LEFT,
+                        MouseButton::Right => MOUSE_BUTTON::RIGHT,
+                        MouseButton::Middle => MOUSE_BUTTON::MIDDLE,
+                        MouseButton::Other(_) => return
+                    };
+                    on_input(&mut mapper, &mut renderer, Input::Mouse(button), state);
+                },
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let steps = match delta {
+                        glutin::event::MouseScrollDelta::LineDelta(_, y) => y,
+                        glutin::event::MouseScrollDelta::PixelDelta(position) => position.y as f32 / 40.0
+                    };
+                    if let Some(action) = mapper.scroll(steps) {
+                        report(renderer.on_scroll(action, steps.abs()));
                     }
                 },
                 WindowEvent::KeyboardInput { input, .. } => {
-                    if input.state == glutin::event::ElementState::Pressed {
-                        match input.virtual_keycode {
-                            Some(glutin::event::VirtualKeyCode::LControl) => {
-                                renderer.on_keypress(renderer::KEY::LCTRL)
-                            },
-                            _ => (),
-                        }
+                    //Bindings name keys like winit's VirtualKeyCode variants
+                    if let Some(keycode) = input.virtual_keycode {
+                        on_input(&mut mapper, &mut renderer, Input::Key(format!("{:?}", keycode)), input.state);
                     }
                 },
                 _ => (),
             },
             Event::RedrawRequested(_) => {
-                renderer.draw();
+                if let Err(e) = renderer.draw() {
+                    eprintln!("Error: {}", e);
+                }
                 gl_context.swap_buffers().unwrap();
             }
             Event::MainEventsCleared => gl_context.window().request_redraw(), //Trigger drawing the next frame
             _ => (),
         }
     });
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use callisto::simulation::SimulationConfig;
+
+    #[test]
+    fn exported_time_continues_from_a_snapshot() {
+        let mut original = Simulation::from_config(&SimulationConfig { num_particles: 4, seed: Some(1), ..SimulationConfig::default() });
+        for _ in 0..3 {
+            original.step();
+        }
+        let mut resumed = Simulation::from_snapshot(&original.snapshot()).unwrap();
+        let start = resumed.t;
+        assert!(start > 0.0);
+
+        let path = std::env::temp_dir().join(format!("callisto-export-{}.csv", process::id()));
+        let rows = write_trajectories(&mut resumed, 2, 1, &path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows, 12);
+        let times: Vec<f64> = csv.lines().skip(1).step_by(4).map(|row| row.split(',').nth(1).unwrap().parse().unwrap()).collect();
+        original.step();
+        let after_one = original.t;
+        original.step();
+        assert_eq!(times, [start, after_one, original.t]);
+    }
+}
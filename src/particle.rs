@@ -2,75 +2,238 @@ extern crate nalgebra_glm as glm;
 
 use crate::simulation::DOMAIN_MODE;
 
-#[derive(Clone, Debug)]
+/// Position and velocity of a single particle, as a value. The simulation itself stores particles in [`Particles`].
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Particle {
     pub position: glm::Vec2,
-    pub velocity: glm::Vec2,
-    acceleration: glm::Vec2,
-    pub new_acceleration: glm::Vec2,
-    domain_wrap: DOMAIN_MODE
+    pub velocity: glm::Vec2
 }
 
 impl Particle {
-    pub fn new(position: glm::Vec2, velocity: glm::Vec2, domain_wrap: DOMAIN_MODE) -> Self {
-        Self {
-            position,
-            velocity,
-            acceleration: glm::vec2(0.0, 0.0),
-            new_acceleration: glm::vec2(0.0, 0.0),
-            domain_wrap
+    pub fn new(position: glm::Vec2, velocity: glm::Vec2) -> Self {
+        Self { position, velocity }
+    }
+}
+
+/// Particle state stored as one array per component, so the force kernels run over contiguous floats and the
+/// positions can be handed to the renderers as is.
+#[derive(Clone, Debug, Default)]
+pub struct Particles {
+    x: Vec<f32>,
+    y: Vec<f32>,
+    vx: Vec<f32>,
+    vy: Vec<f32>,
+    /// Acceleration of the previous microstep
+    ax: Vec<f32>,
+    ay: Vec<f32>,
+    /// Acceleration being accumulated for the current microstep
+    new_ax: Vec<f32>,
+    new_ay: Vec<f32>,
+    /// Pinned particles stay where they are, but still exert forces on the others
+    pinned: Vec<bool>
+}
+
+impl Particles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vx.is_empty()
+    }
+
+    pub fn push(&mut self, particle: Particle) {
+        self.x.push(particle.position.x);
+        self.y.push(particle.position.y);
+        self.vx.push(particle.velocity.x);
+        self.vy.push(particle.velocity.y);
+        for acceleration in [&mut self.ax, &mut self.ay, &mut self.new_ax, &mut self.new_ay] {
+            acceleration.push(0.0);
         }
+        self.pinned.push(false);
     }
 
-    pub fn to_flat(&self) -> Vec<f32> {
-        vec!(self.position.x, self.position.y)
+    pub fn extend(&mut self, particles: impl IntoIterator<Item = Particle>) {
+        for particle in particles {
+            self.x.push(particle.position.x);
+            self.y.push(particle.position.y);
+            self.vx.push(particle.velocity.x);
+            self.vy.push(particle.velocity.y);
+        }
+        let len = self.len();
+        for acceleration in [&mut self.ax, &mut self.ay, &mut self.new_ax, &mut self.new_ay] {
+            acceleration.resize(len, 0.0);
+        }
+        self.pinned.resize(len, false);
     }
 
-    pub fn update(&mut self, dt: f32) {
-        let new_pos = self.position + (self.velocity * dt) + (self.acceleration * (dt * dt * 0.5));
-        let new_vel = self.velocity + (self.acceleration + self.new_acceleration) * (dt * 0.5);
-        self.position = new_pos;
-        self.velocity = new_vel;
-        self.acceleration = self.new_acceleration;
-        self.new_acceleration = glm::vec2(0.0, 0.0);
+    /// Removes the particles for which `remove` returns true, keeping the order of the rest. Returns the number
+    /// of particles removed.
+    pub fn remove_where(&mut self, mut remove: impl FnMut(usize, Particle) -> bool) -> usize {
+        let len = self.len();
+        let keep: Vec<bool> = (0..len).map(|i| !remove(i, self.get(i))).collect();
+        for values in [&mut self.x, &mut self.y, &mut self.vx, &mut self.vy, &mut self.ax, &mut self.ay, &mut self.new_ax, &mut self.new_ay] {
+            retain_kept(values, &keep);
+        }
+        retain_kept(&mut self.pinned, &keep);
+        len - self.len()
+    }
 
-        match self.domain_wrap {
-            DOMAIN_MODE::WRAP => {
-                if self.position.x > 1.0 {
-                    self.position.x = 0.0;
-                }
+    pub fn x(&self) -> &[f32] {
+        &self.x
+    }
 
-                if self.position.x < 0.0 {
-                    self.position.x = 1.0;
-                }
+    pub fn y(&self) -> &[f32] {
+        &self.y
+    }
 
-                if self.position.y > 1.0 {
-                    self.position.y = 0.0;
-                }
+    /// Mutable x and y coordinates
+    pub fn xy_mut(&mut self) -> (&mut [f32], &mut [f32]) {
+        (&mut self.x, &mut self.y)
+    }
+
+    /// Velocity x and y components
+    pub fn velocities(&self) -> (&[f32], &[f32]) {
+        (&self.vx, &self.vy)
+    }
+
+    pub fn velocities_mut(&mut self) -> (&mut [f32], &mut [f32]) {
+        (&mut self.vx, &mut self.vy)
+    }
+
+    /// Acceleration of the previous microstep, as x and y components
+    pub fn accelerations(&self) -> (&[f32], &[f32]) {
+        (&self.ax, &self.ay)
+    }
+
+    pub fn accelerations_mut(&mut self) -> (&mut [f32], &mut [f32]) {
+        (&mut self.ax, &mut self.ay)
+    }
+
+    /// Acceleration accumulated so far in the current microstep
+    pub(crate) fn new_acceleration(&self, i: usize) -> glm::Vec2 {
+        glm::vec2(self.new_ax[i], self.new_ay[i])
+    }
+
+    pub(crate) fn set_new_acceleration(&mut self, i: usize, acceleration: glm::Vec2) {
+        self.new_ax[i] = acceleration.x;
+        self.new_ay[i] = acceleration.y;
+    }
+
+    pub fn position(&self, i: usize) -> glm::Vec2 {
+        glm::vec2(self.x()[i], self.y()[i])
+    }
 
-                if self.position.y < 0.0 {
-                    self.position.y = 1.0;
+    pub fn velocity(&self, i: usize) -> glm::Vec2 {
+        glm::vec2(self.vx[i], self.vy[i])
+    }
+
+    pub fn set_position(&mut self, i: usize, position: glm::Vec2) {
+        self.x[i] = position.x;
+        self.y[i] = position.y;
+    }
+
+    pub fn set_velocity(&mut self, i: usize, velocity: glm::Vec2) {
+        self.vx[i] = velocity.x;
+        self.vy[i] = velocity.y;
+    }
+
+    pub fn is_pinned(&self, i: usize) -> bool {
+        self.pinned[i]
+    }
+
+    /// Pins or releases particle `i`. Pinning also stops it.
+    pub fn set_pinned(&mut self, i: usize, pinned: bool) {
+        self.pinned[i] = pinned;
+        if pinned {
+            self.set_velocity(i, glm::vec2(0.0, 0.0));
+        }
+    }
+
+    /// Whether each particle is pinned
+    pub fn pinned(&self) -> &[bool] {
+        &self.pinned
+    }
+
+    /// Indices of the particles within `radius` of `center`
+    pub fn within(&self, center: glm::Vec2, radius: f32) -> Vec<usize> {
+        self.positions().enumerate()
+            .filter(|(_, position)| glm::distance2(position, &center) < radius * radius)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn get(&self, i: usize) -> Particle {
+        Particle::new(self.position(i), self.velocity(i))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Particle> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = glm::Vec2> + '_ {
+        self.x().iter().zip(self.y()).map(|(x, y)| glm::vec2(*x, *y))
+    }
+
+    /// Velocity Verlet step using the accelerations accumulated this microstep, which are then reset
+    pub fn integrate(&mut self, dt: f32, domain_mode: &DOMAIN_MODE) {
+        integrate_axis(&mut self.x, &mut self.vx, &mut self.ax, &mut self.new_ax, &self.pinned, dt, domain_mode);
+        integrate_axis(&mut self.y, &mut self.vy, &mut self.ay, &mut self.new_ay, &self.pinned, dt, domain_mode);
+    }
+}
+
+impl FromIterator<Particle> for Particles {
+    fn from_iter<I: IntoIterator<Item = Particle>>(iter: I) -> Self {
+        let mut particles = Particles::new();
+        particles.extend(iter);
+        particles
+    }
+}
+
+/// Keeps the values whose entry in `keep` is true
+fn retain_kept<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut kept = keep.iter();
+    values.retain(|_| *kept.next().unwrap());
+}
+
+fn integrate_axis(position: &mut [f32], velocity: &mut [f32], acceleration: &mut [f32], new_acceleration: &mut [f32], pinned: &[bool], dt: f32, domain_mode: &DOMAIN_MODE) {
+    for i in 0..position.len() {
+        if pinned[i] {
+            acceleration[i] = 0.0;
+            new_acceleration[i] = 0.0;
+            continue;
+        }
+        let new_pos = position[i] + (velocity[i] * dt) + (acceleration[i] * (dt * dt * 0.5));
+        let new_vel = velocity[i] + (acceleration[i] + new_acceleration[i]) * (dt * 0.5);
+        position[i] = new_pos;
+        velocity[i] = new_vel;
+        acceleration[i] = new_acceleration[i];
+        new_acceleration[i] = 0.0;
+
+        match domain_mode {
+            DOMAIN_MODE::WRAP => {
+                if position[i] > 1.0 {
+                    position[i] = 0.0;
+                }
+                if position[i] < 0.0 {
+                    position[i] = 1.0;
                 }
             },
             DOMAIN_MODE::WALL => {
-                if self.position.x > 1.0 {
-                  self.position.x = 1.0;
-                  self.velocity.x = -self.velocity.x;
-                }
-                if self.position.x < 0.0 {
-                  self.position.x = 0.0;
-                  self.velocity.x = -self.velocity.x;
+                if position[i] > 1.0 {
+                    position[i] = 1.0;
+                    velocity[i] = -velocity[i];
                 }
-                if self.position.y > 1.0 {
-                  self.position.y = 1.0;
-                  self.velocity.y = -self.velocity.y;
-                }
-                if self.position.y < 0.0 {
-                  self.position.y = 0.0;
-                  self.velocity.y = -self.velocity.y;
+                if position[i] < 0.0 {
+                    position[i] = 0.0;
+                    velocity[i] = -velocity[i];
                 }
             },
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
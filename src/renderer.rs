@@ -1,108 +1,824 @@
-use std::ptr;
-use gl::types::GLuint;
-use crate::shader::{Shader, ShaderError};
+pub mod frame;
+pub mod hud;
+pub mod text;
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use crate::colormap::{ColormapRegistry, ColormapTexture};
+use crate::gpu::{Buffer, Texture, VertexArray};
+#[cfg(feature = "gpu-compute")]
+use crate::gpu_simulation::GpuSimulation;
+use crate::shader::{Defines, Shader, ShaderError};
 use crate::shaderprogram::ShaderProgram;
-use crate::uniform::UniformValue;
+use crate::uniform::{UniformError, UniformValue};
+use crate::uniform_block::UniformBuffer;
 extern crate nalgebra_glm as glm;
-use crate::simulation::Simulation;
+use crate::simulation::{MOUSE_STATE, Simulation};
+use crate::snapshot::{next_snapshot_path, SimulationSnapshot, SNAPSHOT_FORMAT, SnapshotError};
+use crate::tools::{SpawnClock, TOOL, ToolConfig};
+use crate::scheduler::{FixedTimestep, ScheduleConfig, SimulationThread};
+use crate::renderer::frame::{FRAME_BLOCK, FRAME_BLOCK_BINDING, FrameUniforms};
+use crate::renderer::hud::Hud;
+use crate::view::View;
+use crate::scene::RenderConfig;
+pub use crate::input::ACTION;
+
+const COLORMAP_CROSSFADE: Duration = Duration::from_millis(600);
+
+/// Texture unit of the particle positions. Units 0 and 1 hold the colormaps.
+const TRACER_TEXTURE_UNIT: u32 = 2;
+/// Texture unit of the positions before the latest step, for interpolation
+const PREVIOUS_TRACER_TEXTURE_UNIT: u32 = 3;
+
+/// Factor the time scale changes by per keypress
+const TIME_SCALE_STEP: f64 = 2.0;
+/// Factor the zoom, the mouse radius and the mouse strength change by per scroll wheel notch
+const SCROLL_STEP: f32 = 1.1;
+
+/// Corners of the fullscreen quad in clip space, drawn as a triangle strip
+const QUAD_VERTICES: [f32; 8] = [
+    -1.0, -1.0, //Bottom left
+    1.0, -1.0, //Bottom right
+    -1.0, 1.0, //Top left
+    1.0, 1.0 //Top right
+];
 
-pub enum KEY {
-    LCTRL
-}
 pub struct Renderer {
     pub program: ShaderProgram,
+    /// The simulation. With a simulation thread this is a copy that receives the same changes but is never stepped,
+    /// and is only brought up to date by `sync_simulation` and `update_particles`.
     pub simulation: Simulation,
-    mouse_position: glm::Vec2
+    /// Paces the steps of `draw` against real time. With a simulation thread, a copy of the thread's clock.
+    pub clock: FixedTimestep,
+    /// Draw particles between their last two states
+    pub interpolate: bool,
+    pub view: View,
+    pub show_crosshair: bool,
+    mouse_position: glm::Vec2,
+    panning: bool,
+    /// Mouse forces whose bindings are held, latest last. The latest one acts.
+    mouse_forces: Vec<MOUSE_STATE>,
+    tool: TOOL,
+    tools: ToolConfig,
+    /// When the held tool was last applied, or None while it isn't held
+    tool_applied_at: Option<Instant>,
+    spawn_clock: SpawnClock,
+    /// Format of the snapshots saved by `ACTION::SAVE_SNAPSHOT`
+    snapshot_format: SNAPSHOT_FORMAT,
+    colormaps: Vec<ColormapTexture>,
+    colormap_index: usize,
+    previous_colormap_index: usize,
+    colormap_switched_at: Option<Instant>,
+    hud: Hud,
+    /// Lines of the key bindings overlay, and whether it is shown
+    help_lines: Vec<String>,
+    show_help: bool,
+    /// Bindings that show the overlay, for the HUD
+    help_hint: Option<String>,
+    frame_block: UniformBuffer,
+    quad_vao: VertexArray,
+    /// Only held so the vertices live as long as `quad_vao`
+    #[allow(dead_code)]
+    quad_vbo: Buffer,
+    /// Particle positions, read by the shader through a buffer texture
+    tracer_buffer: Buffer,
+    tracer_texture: Texture,
+    previous_tracer_buffer: Buffer,
+    previous_tracer_texture: Texture,
+    /// Positions before the latest step, for `previous_tracer_buffer`
+    previous_positions: Vec<f32>,
+    /// `previous_positions` changed since they were last uploaded
+    previous_positions_changed: bool,
+    /// Mix between the previous and the latest positions used for the next frame
+    interpolation: f32,
+    /// Number of particles in the tracer buffers
+    num_tracers: usize,
+    simulation_thread: Option<SimulationThread>,
+    /// Compute shader backend. When set, the particles of `simulation` are only updated by `sync_simulation`.
+    #[cfg(feature = "gpu-compute")]
+    gpu_simulation: Option<GpuSimulation>
 }
 
-impl Renderer {
-    pub fn new() -> Result<Self, ShaderError> {
-        let FRAGMENT_SHADER_SOURCE = include_str!("shaders/visualize.frag");
-
-        println!("{}", FRAGMENT_SHADER_SOURCE);
+/// Shader constants derived from the scene, so they never have to be kept in sync by hand
+fn visualize_defines(config: &RenderConfig) -> Defines {
+    //Debug formatting always keeps a decimal point, which GLSL needs to treat the value as a float
+    let shader = &config.shader;
+    Defines::from([
+        ("SIM_RESOLUTION".to_string(), format!("{:?}", shader.sim_resolution)),
+        ("BRIGHTNESS".to_string(), format!("{:?}", shader.brightness)),
+        ("SPREAD".to_string(), format!("{:?}", shader.spread)),
+        ("CROSSHAIR_LINE_WIDTH".to_string(), format!("{:?}", shader.crosshair_line_width)),
+        ("CROSSHAIR_SIZE".to_string(), format!("{:?}", shader.crosshair_size)),
+        ("CROSSHAIR_GAP_SIZE".to_string(), format!("{:?}", shader.crosshair_gap_size))
+    ])
+}
 
-        let simulation: Simulation = Default::default();
+impl Renderer {
+    pub fn new(simulation: Simulation, colormaps: ColormapRegistry, view: View, config: &RenderConfig) -> Result<Self, ShaderError> {
+        let fragment_shader_source = include_str!("shaders/visualize.frag");
 
         unsafe {
-            let mut fragment_shader = Shader::new("visualize".to_string(), FRAGMENT_SHADER_SOURCE, gl::FRAGMENT_SHADER)?;
+            let vertex_shader = Shader::new("fullscreen".to_string(), include_str!("shaders/fullscreen.vert"), gl::VERTEX_SHADER, &Defines::new())?;
+            let mut fragment_shader = Shader::new("visualize".to_string(), fragment_shader_source, gl::FRAGMENT_SHADER, &visualize_defines(config))?;
             fragment_shader.add_uniform("u_mouse_active".to_string(), UniformValue::Bool(false));
+            fragment_shader.add_uniform("u_crosshair_visible".to_string(), UniformValue::Bool(true));
             fragment_shader.add_uniform("u_mouse_attractive".to_string(), UniformValue::Bool(true));
             fragment_shader.add_uniform("u_mouse_position".to_string(), UniformValue::Vec2(glm::vec2(0.0, 0.0)));
-            fragment_shader.add_uniform("u_resolution".to_string(), UniformValue::Float(800.0));
-            fragment_shader.add_uniform("u_time".to_string(), UniformValue::Float(0.0));
-            fragment_shader.add_uniform("u_tracer_data".to_string(), UniformValue::Array_F(vec!(0.0, 0.0).repeat(simulation.particles.len())));
+            fragment_shader.add_uniform("u_mouse_radius".to_string(), UniformValue::Float(simulation.mouse_radius));
+            fragment_shader.add_uniform("u_tracers".to_string(), UniformValue::Sampler(TRACER_TEXTURE_UNIT));
+            fragment_shader.add_uniform("u_previous_tracers".to_string(), UniformValue::Sampler(PREVIOUS_TRACER_TEXTURE_UNIT));
+            fragment_shader.add_uniform("u_interpolation".to_string(), UniformValue::Float(1.0));
+            fragment_shader.add_uniform("u_colormap_a".to_string(), UniformValue::Sampler(0));
+            fragment_shader.add_uniform("u_colormap_b".to_string(), UniformValue::Sampler(1));
+            fragment_shader.add_uniform("u_colormap_mix".to_string(), UniformValue::Float(0.0));
 
-            let program = ShaderProgram::new(vec!(fragment_shader))?;
+            let program = ShaderProgram::new(vec!(vertex_shader, fragment_shader))?;
+            program.bind_uniform_block(FRAME_BLOCK, FRAME_BLOCK_BINDING, FrameUniforms::size())?;
 
-            Ok(Self { program, simulation, mouse_position: glm::vec2(0.0, 0.0) })
+            let colormap_index = colormaps.index_of(&config.colormap).unwrap_or(0);
+            let colormaps = colormaps.iter().map(|colormap| ColormapTexture::new(colormap)).collect();
+
+            let quad_vao = VertexArray::new();
+            let quad_vbo = Buffer::new(gl::ARRAY_BUFFER);
+            quad_vbo.set_data(&QUAD_VERTICES, gl::STATIC_DRAW);
+            quad_vao.float_attribute(&quad_vbo, 0, 2, 2, 0);
+            gl::BindVertexArray(0);
+
+            let tracer_buffer = Buffer::new(gl::TEXTURE_BUFFER);
+            tracer_buffer.set_data_parts(&simulation.pack(), gl::STREAM_DRAW);
+            let tracer_texture = Texture::new(gl::TEXTURE_BUFFER);
+            tracer_texture.bind(TRACER_TEXTURE_UNIT);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, gl::R32F, tracer_buffer.id);
+            let previous_tracer_buffer = Buffer::new(gl::TEXTURE_BUFFER);
+            previous_tracer_buffer.set_data_parts(&simulation.pack(), gl::STREAM_DRAW);
+            let previous_tracer_texture = Texture::new(gl::TEXTURE_BUFFER);
+            previous_tracer_texture.bind(PREVIOUS_TRACER_TEXTURE_UNIT);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, gl::R32F, previous_tracer_buffer.id);
+
+            gl::Viewport(0, 0, view.screen_size.x as i32, view.screen_size.y as i32);
+            let num_tracers = simulation.particles.len();
+
+            Ok(Self {
+                program,
+                simulation,
+                clock: FixedTimestep::new(&ScheduleConfig::default()),
+                interpolate: true,
+                hud: Hud::new(view.scale_factor)?,
+                help_lines: Vec::new(),
+                show_help: false,
+                help_hint: None,
+                view,
+                show_crosshair: true,
+                mouse_position: glm::vec2(0.0, 0.0),
+                panning: false,
+                mouse_forces: Vec::new(),
+                tool: TOOL::FORCE,
+                tools: ToolConfig::default(),
+                tool_applied_at: None,
+                spawn_clock: SpawnClock::default(),
+                snapshot_format: SNAPSHOT_FORMAT::TOML,
+                colormaps,
+                colormap_index,
+                previous_colormap_index: colormap_index,
+                colormap_switched_at: None,
+                frame_block: UniformBuffer::new(FRAME_BLOCK_BINDING, FrameUniforms::size()),
+                quad_vao,
+                quad_vbo,
+                tracer_buffer,
+                tracer_texture,
+                previous_tracer_buffer,
+                previous_tracer_texture,
+                previous_positions: Vec::new(),
+                previous_positions_changed: false,
+                interpolation: 1.0,
+                num_tracers,
+                simulation_thread: None,
+                #[cfg(feature = "gpu-compute")]
+                gpu_simulation: None
+            })
         }
     }
 
+    /// Switches to the named colormap, crossfading from the current one. Returns false if no colormap has that name.
+    pub fn set_colormap(&mut self, name: &str) -> bool {
+        match self.colormaps.iter().position(|colormap| colormap.name == name) {
+            Some(index) => {
+                self.switch_colormap(index);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn colormap_name(&self) -> &str {
+        &self.colormaps[self.colormap_index].name
+    }
+
+    pub fn next_colormap(&mut self) {
+        self.switch_colormap((self.colormap_index + 1) % self.colormaps.len());
+    }
+
+    fn switch_colormap(&mut self, index: usize) {
+        self.previous_colormap_index = self.colormap_mix_source();
+        self.colormap_index = index;
+        self.colormap_switched_at = Some(Instant::now());
+    }
+
+    /// The colormap being faded out of. Once a crossfade finishes this is the current colormap.
+    fn colormap_mix_source(&self) -> usize {
+        if self.colormap_mix() < 1.0 { self.previous_colormap_index } else { self.colormap_index }
+    }
+
+    fn colormap_mix(&self) -> f32 {
+        match self.colormap_switched_at {
+            Some(switched_at) => (switched_at.elapsed().as_secs_f32() / COLORMAP_CROSSFADE.as_secs_f32()).min(1.0),
+            None => 1.0
+        }
+    }
+
+    /// Takes the cursor position in physical pixels from the top left of the window
     pub fn set_mouse_position(&mut self, x: f32, y: f32) {
-        self.mouse_position = glm::vec2(x, y);
-        self.simulation.set_mouse_position(x, y);
+        let position = glm::vec2(x, y);
+        if self.panning {
+            self.view.pan(position - self.mouse_position);
+        }
+        self.mouse_position = position;
+        self.update_simulation_mouse();
+    }
+
+    pub fn set_panning(&mut self, panning: bool) {
+        self.panning = panning;
+    }
+
+    /// Applies `action` `notches` times, for scrolling. Zooming and the mouse radius and strength change by
+    /// fractions of a notch as well; any other action is applied once, like `on_action`.
+    pub fn on_scroll(&mut self, action: ACTION, notches: f32) -> Result<Option<String>, SnapshotError> {
+        let factor = SCROLL_STEP.powf(notches);
+        match action {
+            ACTION::ZOOM_IN => {
+                self.view.zoom_at(factor, self.mouse_position);
+                self.update_simulation_mouse();
+            },
+            ACTION::ZOOM_OUT => {
+                self.view.zoom_at(1.0 / factor, self.mouse_position);
+                self.update_simulation_mouse();
+            },
+            ACTION::GROW_MOUSE_RADIUS => {
+                self.update_simulation(move |simulation| simulation.set_mouse_size(simulation.mouse_radius * factor, simulation.mouse_strength));
+            },
+            ACTION::SHRINK_MOUSE_RADIUS => {
+                self.update_simulation(move |simulation| simulation.set_mouse_size(simulation.mouse_radius / factor, simulation.mouse_strength));
+            },
+            ACTION::STRENGTHEN_MOUSE => {
+                self.update_simulation(move |simulation| simulation.set_mouse_size(simulation.mouse_radius, simulation.mouse_strength * factor));
+            },
+            ACTION::WEAKEN_MOUSE => {
+                self.update_simulation(move |simulation| simulation.set_mouse_size(simulation.mouse_radius, simulation.mouse_strength / factor));
+            },
+            _ => return self.on_action(action)
+        }
+        Ok(None)
+    }
+
+    /// Starts or stops holding a mouse force. Releasing one while the other is still held switches back to it.
+    fn hold_mouse_force(&mut self, force: MOUSE_STATE, held: bool) {
+        self.mouse_forces.retain(|held_force| *held_force != force);
+        if held {
+            self.mouse_forces.push(force);
+        }
+        let active = self.mouse_forces.last().cloned();
+        self.update_simulation(move |simulation| simulation.set_mouse_force(active.clone()));
     }
 
-    pub fn on_mouse_click(&mut self) {
-        self.simulation.on_mouse_click();
+    /// Runs the simulation in compute shaders from now on, starting from the current particles.
+    /// The renderer then reads the positions straight from the GPU buffers. Needs an OpenGL 4.3 context.
+    /// A simulation thread is stopped, since the GPU simulation runs on the render thread.
+    #[cfg(feature = "gpu-compute")]
+    pub fn enable_gpu_simulation(&mut self) -> Result<(), ShaderError> {
+        if let Some(thread) = self.simulation_thread.take() {
+            self.simulation = thread.join();
+        }
+        unsafe {
+            let gpu_simulation = GpuSimulation::new(&self.simulation)?;
+            self.tracer_texture.bind(TRACER_TEXTURE_UNIT);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, gl::R32F, gpu_simulation.positions().id);
+            self.gpu_simulation = Some(gpu_simulation);
+        }
+        Ok(())
+    }
+
+    fn simulation_on_gpu(&self) -> bool {
+        #[cfg(feature = "gpu-compute")]
+        return self.gpu_simulation.is_some();
+        #[cfg(not(feature = "gpu-compute"))]
+        false
     }
 
-    pub fn on_keypress(&mut self, key: KEY) {
-        match key {
-            KEY::LCTRL => {
-                self.simulation.next_mouse_mode();
+    /// Copies the full state back from the simulation thread or the GPU backend into `simulation`
+    pub fn sync_simulation(&mut self) {
+        if let Some(thread) = &self.simulation_thread {
+            self.simulation = thread.query(Simulation::clone);
+        }
+        #[cfg(feature = "gpu-compute")]
+        if let Some(gpu_simulation) = &mut self.gpu_simulation {
+            unsafe {
+                //`simulation` was changed directly, so it is the newer state
+                if gpu_simulation.download(&mut self.simulation).is_err() {
+                    gpu_simulation.upload(&self.simulation);
+                }
             }
         }
     }
 
-    pub fn draw(&mut self) {
+    /// Applies the pacing of `config`. With `config.threaded`, the simulation moves to its own thread.
+    pub fn set_schedule(&mut self, config: &ScheduleConfig) {
+        if let Some(thread) = self.simulation_thread.take() {
+            self.simulation = thread.join();
+        }
+        self.clock = FixedTimestep::new(config);
+        self.interpolate = config.interpolate;
+        if config.threaded {
+            self.simulation_thread = Some(SimulationThread::spawn(self.simulation.clone(), self.clock.clone()));
+        }
+    }
+
+    /// Applies `change` to the simulation, and to the simulation thread's copy if there is one
+    pub fn update_simulation(&mut self, change: impl Fn(&mut Simulation) + Send + 'static) {
+        change(&mut self.simulation);
+        if let Some(thread) = &self.simulation_thread {
+            thread.update_simulation(change);
+        }
+    }
+
+    /// Like `update_simulation`, for changes that replace or move the particles. The change runs once, on the
+    /// latest state: on the simulation thread, whose result is copied back, or on `simulation` after downloading
+    /// it from the GPU backend, which then takes the result over. It is drawn without interpolating from the old
+    /// particles.
+    pub fn update_particles(&mut self, change: impl FnOnce(&mut Simulation) + Send + 'static) {
+        if let Some(thread) = &self.simulation_thread {
+            thread.update_particles(change);
+            self.simulation = thread.query(Simulation::clone);
+        } else {
+            self.sync_simulation();
+            change(&mut self.simulation);
+        }
+        #[cfg(feature = "gpu-compute")]
+        if let Some(gpu_simulation) = &mut self.gpu_simulation {
+            unsafe {
+                gpu_simulation.upload(&self.simulation);
+                gpu_simulation.copy_positions(&self.previous_tracer_buffer);
+            }
+        }
+        self.previous_positions.clear();
+        for positions in self.simulation.pack() {
+            self.previous_positions.extend_from_slice(positions);
+        }
+        self.previous_positions_changed = true;
+    }
+
+    /// Applies `change` to the clock, and to the simulation thread's clock if there is one
+    pub fn update_clock(&mut self, change: impl Fn(&mut FixedTimestep) + Send + 'static) {
+        change(&mut self.clock);
+        if let Some(thread) = &self.simulation_thread {
+            thread.update_clock(change);
+        }
+    }
+
+    /// Advances the simulation by one step right away and draws the latest state next, regardless of the clock.
+    /// With a simulation thread the step is only requested, and pauses the thread like `step_once`.
+    pub fn step(&mut self) {
+        if self.simulation_thread.is_some() {
+            self.update_clock(|clock| clock.step_once());
+            return;
+        }
+        self.step_simulation();
+        self.interpolation = 1.0;
+    }
+
+    fn step_simulation(&mut self) {
+        #[cfg(feature = "gpu-compute")]
+        if let Some(gpu_simulation) = &mut self.gpu_simulation {
+            unsafe {
+                if self.interpolate {
+                    gpu_simulation.copy_positions(&self.previous_tracer_buffer);
+                }
+                gpu_simulation.step(&mut self.simulation);
+            }
+            return;
+        }
+        if self.interpolate {
+            self.previous_positions.clear();
+            for positions in self.simulation.pack() {
+                self.previous_positions.extend_from_slice(positions);
+            }
+            self.previous_positions_changed = true;
+        }
         self.simulation.step();
+    }
 
-        let program_id = self.program.id;
-        let shader = self.program.get_shader("visualize".to_string()).unwrap();
+    /// Simulated time of the latest step. The simulation thread's copy is the only one that is stepped.
+    pub fn time(&self) -> f64 {
+        match &self.simulation_thread {
+            Some(thread) => thread.snapshot().time,
+            None => self.simulation.t
+        }
+    }
 
-        shader.update_uniform_value("u_mouse_active".to_string(), UniformValue::Bool(self.simulation.mouse_active));
-        shader.update_uniform_value("u_mouse_attractive".to_string(), UniformValue::Bool(self.simulation.mouse_state == crate::simulation::MOUSE_STATE::ATTRACTIVE));
-        shader.update_uniform_value("u_mouse_position".to_string(), UniformValue::Vec2(self.mouse_position));
-        shader.update_uniform_value("u_time".to_string(), UniformValue::Float(self.simulation.t));
-        shader.update_uniform_value("u_resolution".to_string(), UniformValue::Float(800.0));
-        shader.update_uniform_value("u_tracer_data".to_string(), UniformValue::Array_F(self.simulation.pack()));
-        shader.apply_uniforms(program_id);
-
-        let vertex_data: [f32; 20] = [
-            -1.0, -1.0, 1.0, 0.0, 0.0, //Bottom left, red
-            1.0, -1.0, 0.0, 1.0, 0.0, //Bottom right, green
-            -1.0, 1.0, 0.0, 0.0, 1.0, //Top left, blue
-            1.0, 1.0, 1.0, 1.0, 1.0 //Top right, white
-        ];
+    /// Runs the steps the clock says are due, and works out how far to interpolate for the next frame
+    fn advance(&mut self) {
+        if let Some(thread) = &self.simulation_thread {
+            let snapshot = thread.snapshot();
+            //The thread steps on its own, so the fraction of the next step comes from when the latest one finished
+            self.interpolation = match snapshot.stepped_at {
+                Some(stepped_at) if self.interpolate && !self.clock.is_paused() => {
+                    (stepped_at.elapsed().as_secs_f64() * self.clock.time_scale / self.clock.step_interval).min(1.0) as f32
+                },
+                _ => 1.0
+            };
+            return;
+        }
+
+        for _ in 0..self.clock.tick() {
+            self.step_simulation();
+        }
+        self.interpolation = if self.interpolate { self.clock.alpha() } else { 1.0 };
+    }
 
+    pub fn set_snapshot_format(&mut self, format: SNAPSHOT_FORMAT) {
+        self.snapshot_format = format;
+    }
+
+    /// The full state of the simulation, including what only the simulation thread or the GPU backend has
+    pub fn snapshot(&mut self) -> SimulationSnapshot {
+        self.sync_simulation();
+        self.simulation.snapshot()
+    }
+
+    /// Saves a snapshot to the next free `snapshot_<n>` file in the working directory and returns its path
+    pub fn save_snapshot(&mut self) -> Result<PathBuf, SnapshotError> {
+        let path = next_snapshot_path("", self.snapshot_format);
+        self.snapshot().save(&path, self.snapshot_format)?;
+        Ok(path)
+    }
+
+    /// Frames the camera around every particle, including ones that have left the domain
+    pub fn fit_camera(&mut self) {
+        self.sync_simulation();
+        self.view.fit(self.simulation.particles.positions());
+        self.update_simulation_mouse();
+    }
+
+    fn update_simulation_mouse(&mut self) {
+        let world = self.view.screen_to_world(self.mouse_position);
+        self.update_simulation(move |simulation| simulation.set_mouse_position(world.x, world.y));
+        if self.tool == TOOL::GRAB && self.tool_applied_at.is_some() {
+            self.update_particles(|simulation| simulation.drag_to(simulation.mouse_position()));
+        }
+    }
+
+    pub fn set_tools(&mut self, config: &ToolConfig) {
+        self.tools = config.clone();
+    }
+
+    pub fn tool(&self) -> TOOL {
+        self.tool
+    }
+
+    /// Switches the tool of the primary button, letting go of the current one if it is held
+    pub fn set_tool(&mut self, tool: TOOL) {
+        if self.tool_applied_at.is_some() {
+            self.use_tool(false);
+        }
+        self.tool = tool;
+    }
+
+    /// Starts or stops using the current tool at the cursor
+    fn use_tool(&mut self, held: bool) {
+        if !held {
+            self.tool_applied_at = None;
+            match self.tool {
+                TOOL::FORCE => self.hold_mouse_force(MOUSE_STATE::ATTRACTIVE, false),
+                TOOL::GRAB => self.update_particles(|simulation| simulation.release_grab()),
+                _ => ()
+            }
+            return;
+        }
+
+        self.tool_applied_at = Some(Instant::now());
+        self.spawn_clock.reset();
+        match self.tool {
+            TOOL::FORCE => self.hold_mouse_force(MOUSE_STATE::ATTRACTIVE, true),
+            TOOL::GRAB => self.update_particles(|simulation| {
+                simulation.grab(simulation.mouse_position(), simulation.mouse_radius);
+            }),
+            //A click paints at least one particle
+            TOOL::SPAWN => self.spawn(1),
+            TOOL::ERASE | TOOL::PIN => self.apply_tool()
+        }
+    }
+
+    /// Paints up to `count` particles at the cursor, stopping at the particle limit
+    fn spawn(&mut self, count: usize) {
+        let max_particles = self.tools.max_particles;
+        let velocity = glm::vec2(self.tools.spawn_velocity[0], self.tools.spawn_velocity[1]);
+        let jitter = self.tools.spawn_velocity_jitter;
+        self.update_particles(move |simulation| {
+            let count = count.min(max_particles.saturating_sub(simulation.particles.len()));
+            simulation.spawn_in(simulation.mouse_position(), simulation.mouse_radius, count, velocity, jitter);
+        });
+    }
+
+    /// Applies the held brush for the time since it was last applied. Called every frame.
+    fn apply_tool(&mut self) {
+        let Some(applied_at) = self.tool_applied_at else { return };
+        let now = Instant::now();
+        let elapsed = now.duration_since(applied_at).as_secs_f32();
+        self.tool_applied_at = Some(now);
+
+        match self.tool {
+            TOOL::SPAWN => {
+                let count = self.spawn_clock.advance(self.tools.spawn_rate, elapsed);
+                if count > 0 {
+                    self.spawn(count);
+                }
+            },
+            //Only touch the particles when the brush changes something, since that syncs them and restarts interpolation
+            TOOL::ERASE if self.brush_hits() => self.update_particles(|simulation| {
+                simulation.erase_in(simulation.mouse_position(), simulation.mouse_radius);
+            }),
+            TOOL::PIN if self.brush_hits() => self.update_particles(|simulation| {
+                simulation.pin_in(simulation.mouse_position(), simulation.mouse_radius, true);
+            }),
+            //The force acts by itself, and grabbed particles follow the cursor
+            _ => ()
+        }
+    }
+
+    /// Whether the erase or pin brush would change any particle, going by the latest positions. Pinned particles
+    /// don't count for the pin brush.
+    fn brush_hits(&self) -> bool {
+        let center = self.simulation.mouse_position();
+        let radius = self.simulation.mouse_radius;
+        let pinned = self.simulation.particles.pinned();
+        let skip_pinned = self.tool == TOOL::PIN;
+        let hits = |x: &[f32], y: &[f32]| {
+            x.iter().zip(y).enumerate().any(|(i, (x, y))| {
+                !(skip_pinned && pinned.get(i) == Some(&true)) && glm::distance2(&glm::vec2(*x, *y), &center) < radius * radius
+            })
+        };
+
+        if let Some(thread) = &self.simulation_thread {
+            let positions = &thread.snapshot().current;
+            let (x, y) = positions.split_at(positions.len() / 2);
+            return hits(x, y);
+        }
+        #[cfg(feature = "gpu-compute")]
+        if let Some(gpu_simulation) = &self.gpu_simulation {
+            let positions = unsafe { gpu_simulation.read_positions() };
+            let (x, y) = positions.split_at(positions.len() / 2);
+            return hits(x, y);
+        }
+        let [x, y] = self.simulation.pack();
+        hits(x, y)
+    }
+
+    /// Takes the new framebuffer size in physical pixels
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.view.resize(width, height);
         unsafe {
-            let mut vbo: GLuint = 0;
-            gl::GenBuffers(1, &mut vbo);
-            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (vertex_data.len() * std::mem::size_of::<f32>()) as isize,
-                vertex_data.as_ptr() as *const _,
-                gl::STATIC_DRAW
-            );
+            gl::Viewport(0, 0, self.view.screen_size.x as i32, self.view.screen_size.y as i32);
+        }
+        self.update_simulation_mouse();
+    }
 
-            let mut vao: GLuint = 0;
-            gl::GenVertexArrays(1, &mut vao);
-            gl::BindVertexArray(vao);
+    pub fn set_hud_visible(&mut self, visible: bool) {
+        self.hud.visible = visible;
+    }
+
+    pub fn set_scale_factor(&mut self, scale_factor: f64) -> Result<(), ShaderError> {
+        self.view.scale_factor = scale_factor;
+        self.hud.set_scale_factor(scale_factor)
+    }
+
+    /// Sets the key bindings listed by the help overlay, one per line, and the bindings that toggle it
+    pub fn set_help(&mut self, lines: Vec<String>, hint: Option<String>) {
+        self.help_lines = lines;
+        self.help_hint = hint;
+    }
+
+    /// Starts `action`. Held actions last until `on_action_release`. Returns a message for the user if the action
+    /// has a result to report, e.g. the new colormap or the path of a saved snapshot.
+    pub fn on_action(&mut self, action: ACTION) -> Result<Option<String>, SnapshotError> {
+        match action {
+            ACTION::USE_TOOL => {
+                self.use_tool(true);
+            },
+            ACTION::REPEL => {
+                self.hold_mouse_force(MOUSE_STATE::REPULSIVE, true);
+            },
+            ACTION::PAN => {
+                self.set_panning(true);
+            },
+            ACTION::ZOOM_IN | ACTION::ZOOM_OUT | ACTION::GROW_MOUSE_RADIUS | ACTION::SHRINK_MOUSE_RADIUS | ACTION::STRENGTHEN_MOUSE | ACTION::WEAKEN_MOUSE => {
+                return self.on_scroll(action, 1.0);
+            },
+            ACTION::TOGGLE_HELP => {
+                self.show_help = !self.show_help;
+            },
+            ACTION::TOOL_FORCE => {
+                self.set_tool(TOOL::FORCE);
+            },
+            ACTION::TOOL_SPAWN => {
+                self.set_tool(TOOL::SPAWN);
+            },
+            ACTION::TOOL_ERASE => {
+                self.set_tool(TOOL::ERASE);
+            },
+            ACTION::TOOL_GRAB => {
+                self.set_tool(TOOL::GRAB);
+            },
+            ACTION::TOOL_PIN => {
+                self.set_tool(TOOL::PIN);
+            },
+            ACTION::UNPIN_ALL => {
+                self.update_particles(|simulation| simulation.unpin_all());
+            },
+            ACTION::NEXT_COLORMAP => {
+                self.next_colormap();
+                return Ok(Some(format!("Colormap: {}", self.colormap_name())));
+            },
+            ACTION::TOGGLE_HUD => {
+                self.hud.toggle();
+            },
+            ACTION::FIT_CAMERA => {
+                self.fit_camera();
+            },
+            ACTION::TOGGLE_PAUSE => {
+                self.update_clock(|clock| clock.toggle_pause());
+            },
+            ACTION::STEP_ONCE => {
+                self.update_clock(|clock| clock.step_once());
+            },
+            ACTION::SLOW_DOWN => {
+                self.update_clock(|clock| clock.set_time_scale(clock.time_scale / TIME_SCALE_STEP));
+            },
+            ACTION::SPEED_UP => {
+                self.update_clock(|clock| clock.set_time_scale(clock.time_scale * TIME_SCALE_STEP));
+            },
+            ACTION::RESET => {
+                self.update_particles(|simulation| simulation.reset());
+            },
+            ACTION::RESEED => {
+                let seed = rand::random();
+                self.update_particles(move |simulation| simulation.reseed(seed));
+            },
+            ACTION::TOGGLE_GRAVITY => {
+                self.update_simulation(|simulation| simulation.gravity = !simulation.gravity);
+            },
+            ACTION::NEXT_DOMAIN_MODE => {
+                self.update_simulation(|simulation| simulation.domain_mode = simulation.domain_mode.next());
+            },
+            ACTION::SAVE_SNAPSHOT => {
+                let path = self.save_snapshot()?;
+                return Ok(Some(format!("Saved snapshot {}", path.display())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Ends a held action. Other actions ignore the release.
+    pub fn on_action_release(&mut self, action: ACTION) {
+        match action {
+            ACTION::USE_TOOL => {
+                self.use_tool(false);
+            },
+            ACTION::REPEL => {
+                self.hold_mouse_force(MOUSE_STATE::REPULSIVE, false);
+            },
+            ACTION::PAN => {
+                self.set_panning(false);
+            },
+            _ => ()
+        }
+    }
+
+    fn hud_lines(&self) -> Vec<String> {
+        let simulation = &self.simulation;
+        let mouse_mode = if simulation.mouse_state == MOUSE_STATE::ATTRACTIVE { "attract" } else { "repel" };
+
+        let mut lines = vec!(
+            format!("FPS: {:.0}", self.hud.fps()),
+            format!("Particles: {}", self.num_tracers),
+            format!("Seed: {}", simulation.seed),
+            format!("Sim time: {:.3e} s", self.time()),
+            format!("Speed: {:.2}x{}", self.clock.time_scale, if self.clock.is_paused() { " (paused)" } else { "" }),
+            format!("Mouse: {} ({})", mouse_mode, if simulation.mouse_active { "active" } else { "inactive" }),
+            format!("Tool: {}", self.tool.name()),
+            format!("Mouse radius: {:.3}", simulation.mouse_radius),
+            format!("Mouse strength: {:.3e}", simulation.mouse_strength),
+            format!("Domain: {:?}", simulation.domain_mode),
+            format!("dt: {:.3e}", simulation.dt),
+            format!("Attractive force: {:.3e}", simulation.attractive_force),
+            format!("Repulsive force: {:.3e}", simulation.repulsive_force),
+            format!("Drag: {}", simulation.drag),
+            format!("Microsteps: {}", simulation.microsteps),
+            format!("Gravity: {}", if simulation.gravity { "on" } else { "off" }),
+            format!("Colormap: {}", self.colormap_name()),
+            format!("Zoom: {:.2}x", self.view.camera.zoom)
+        );
+        if let Some(hint) = &self.help_hint {
+            lines.push(format!("{}: show key bindings", hint));
+        }
+        lines
+    }
+
+    /// Runs the simulation steps that are due and draws the frame
+    pub fn draw(&mut self) -> Result<(), UniformError> {
+        self.apply_tool();
+        self.advance();
+        self.render()
+    }
 
-            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 5 * std::mem::size_of::<f32>() as i32, ptr::null());
-            gl::EnableVertexAttribArray(0);
+    fn update_uniforms(&mut self) -> Result<(), UniformError> {
+        let colormap_mix = self.colormap_mix();
+        let shader = self.program.get_shader("visualize".to_string()).unwrap();
+
+        shader.set_uniform("u_mouse_active", self.simulation.mouse_active)?;
+        shader.set_uniform("u_crosshair_visible", self.show_crosshair)?;
+        shader.set_uniform("u_mouse_attractive", self.simulation.mouse_state == crate::simulation::MOUSE_STATE::ATTRACTIVE)?;
+        shader.set_uniform("u_mouse_position", self.mouse_position)?;
+        shader.set_uniform("u_mouse_radius", self.simulation.mouse_radius)?;
+        shader.set_uniform("u_colormap_mix", colormap_mix)?;
+        shader.set_uniform("u_interpolation", self.interpolation)?;
+        Ok(())
+    }
+
+    fn frame_uniforms(&self) -> FrameUniforms {
+        FrameUniforms {
+            resolution: self.view.screen_size,
+            view_center: self.view.camera.center,
+            pixels_per_unit: self.view.pixels_per_unit(),
+            scale_factor: self.view.scale_factor as f32,
+            time: self.time() as f32,
+            dt: self.simulation.dt,
+            num_particles: self.num_tracers as i32
+        }
+    }
+
+    /// Uploads the latest and previous positions. They come straight from the simulation's storage, from the
+    /// simulation thread's snapshot, or are already on the GPU.
+    unsafe fn upload_tracers(&mut self) {
+        if let Some(thread) = &self.simulation_thread {
+            let snapshot = thread.snapshot();
+            self.num_tracers = snapshot.current.len() / 2;
+            self.tracer_buffer.set_data(&snapshot.current, gl::STREAM_DRAW);
+            self.previous_tracer_buffer.set_data(&snapshot.previous, gl::STREAM_DRAW);
+            return;
+        }
+        self.num_tracers = self.simulation.particles.len();
+        if self.simulation_on_gpu() {
+            return;
+        }
+        self.tracer_buffer.set_data_parts(&self.simulation.pack(), gl::STREAM_DRAW);
+        if self.previous_positions_changed {
+            self.previous_tracer_buffer.set_data(&self.previous_positions, gl::STREAM_DRAW);
+            self.previous_positions_changed = false;
+        }
+    }
 
-            gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, 5 * std::mem::size_of::<f32>() as i32, (2 * std::mem::size_of::<f32>()) as *const _);
-            gl::EnableVertexAttribArray(1);
+    /// Draws the current simulation state into the bound framebuffer without stepping the simulation
+    pub fn render(&mut self) -> Result<(), UniformError> {
+        self.update_uniforms()?;
+        unsafe {
+            //First, so the frame block has the particle count of the uploaded positions
+            self.upload_tracers();
+            self.frame_block.update(self.frame_uniforms().to_std140());
         }
+        unsafe {
+            self.program.apply();
+        }
+        self.program.get_shader("visualize".to_string()).unwrap().apply_uniforms();
 
         unsafe {
             gl::ClearColor(0.3, 0.3, 0.3, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT);
-            self.program.apply();
+            self.colormaps[self.colormap_mix_source()].bind(0);
+            self.colormaps[self.colormap_index].bind(1);
+            self.tracer_texture.bind(TRACER_TEXTURE_UNIT);
+            self.previous_tracer_texture.bind(PREVIOUS_TRACER_TEXTURE_UNIT);
+            self.quad_vao.bind();
             gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+            gl::BindVertexArray(0);
+        }
+
+        self.hud.tick();
+        if self.show_help {
+            //The overlay replaces the HUD while shown, even if the HUD is hidden
+            self.hud.draw_panel(&self.help_lines);
+        } else {
+            let hud_lines = self.hud_lines();
+            self.hud.draw(&hud_lines);
         }
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,44 @@
+use gl::types::GLuint;
+use crate::uniform_block::Std140Block;
+extern crate nalgebra_glm as glm;
+
+/// Name of the block declared in shaders/include/frame.glsl
+pub const FRAME_BLOCK: &str = "Frame";
+/// Binding point the frame block is uploaded to, shared by every program
+pub const FRAME_BLOCK_BINDING: GLuint = 0;
+
+/// Camera, time and simulation values uploaded once per frame and read by every pass.
+/// The member order must match shaders/include/frame.glsl.
+#[derive(Clone, Debug, Default)]
+pub struct FrameUniforms {
+    /// Framebuffer size in physical pixels
+    pub resolution: glm::Vec2,
+    /// World position at the center of the screen
+    pub view_center: glm::Vec2,
+    /// Physical pixels per world unit
+    pub pixels_per_unit: f32,
+    /// Physical pixels per logical pixel
+    pub scale_factor: f32,
+    pub time: f32,
+    pub dt: f32,
+    pub num_particles: i32
+}
+
+impl FrameUniforms {
+    pub fn to_std140(&self) -> Vec<u8> {
+        let mut block = Std140Block::new();
+        block.push(&self.resolution);
+        block.push(&self.view_center);
+        block.push(&self.pixels_per_unit);
+        block.push(&self.scale_factor);
+        block.push(&self.time);
+        block.push(&self.dt);
+        block.push(&self.num_particles);
+        block.finish()
+    }
+
+    /// Size of the block in bytes
+    pub fn size() -> usize {
+        Self::default().to_std140().len()
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::Instant;
+use crate::renderer::text::TextRenderer;
+use crate::shader::ShaderError;
+extern crate nalgebra_glm as glm;
+
+const FONT_SIZE: f32 = 16.0;
+const MARGIN: f32 = 8.0;
+const FPS_SMOOTHING: f32 = 0.05;
+
+/// Heads-up display listing simulation statistics in the top left corner of the window
+pub struct Hud {
+    pub visible: bool,
+    text: TextRenderer,
+    last_frame: Instant,
+    frame_time: f32,
+    scale_factor: f32
+}
+
+impl Hud {
+    pub fn new(scale_factor: f64) -> Result<Self, ShaderError> {
+        Ok(Self {
+            visible: true,
+            text: Self::text_renderer(scale_factor)?,
+            last_frame: Instant::now(),
+            frame_time: 1.0 / 60.0,
+            scale_factor: scale_factor as f32
+        })
+    }
+
+    fn text_renderer(scale_factor: f64) -> Result<TextRenderer, ShaderError> {
+        TextRenderer::new(include_bytes!("../fonts/Roboto-Regular.ttf"), FONT_SIZE * scale_factor as f32)
+    }
+
+    /// Rebuilds the glyph atlas so text stays sharp when the window moves to a display with a different DPI
+    pub fn set_scale_factor(&mut self, scale_factor: f64) -> Result<(), ShaderError> {
+        self.scale_factor = scale_factor as f32;
+        self.text = Self::text_renderer(scale_factor)?;
+        Ok(())
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Records the end of a frame. Called every frame, even while hidden, so the FPS is current when shown.
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        self.frame_time += (elapsed - self.frame_time) * FPS_SMOOTHING;
+    }
+
+    pub fn fps(&self) -> f32 {
+        if self.frame_time > 0.0 { 1.0 / self.frame_time } else { 0.0 }
+    }
+
+    pub fn draw(&mut self, lines: &[String]) {
+        if self.visible {
+            self.draw_panel(lines);
+        }
+    }
+
+    /// Draws `lines` in the HUD's panel whether or not the HUD is visible
+    pub fn draw_panel(&mut self, lines: &[String]) {
+        let margin = MARGIN * self.scale_factor;
+        let line_height = self.text.line_height();
+        let width = lines.iter().map(|line| self.text.measure(line)).fold(0.0, f32::max);
+        let height = line_height * lines.len() as f32;
+
+        self.text.queue_rect(
+            glm::vec2(margin / 2.0, margin / 2.0),
+            glm::vec2(width + margin * 1.5, height + margin * 1.5),
+            glm::vec4(0.0, 0.0, 0.0, 0.5)
+        );
+
+        for (i, line) in lines.iter().enumerate() {
+            self.text.queue_text(line, glm::vec2(margin, margin + line_height * i as f32), glm::vec4(1.0, 1.0, 1.0, 1.0));
+        }
+
+        self.text.flush();
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use gl::types::*;
+use rusttype::{point, Font, Scale};
+use crate::gpu::{Buffer, Texture, VertexArray};
+use crate::renderer::frame::{FRAME_BLOCK, FRAME_BLOCK_BINDING, FrameUniforms};
+use crate::shader::{Defines, Shader, ShaderError};
+use crate::shaderprogram::ShaderProgram;
+use crate::uniform::UniformValue;
+extern crate nalgebra_glm as glm;
+
+const ATLAS_WIDTH: usize = 512;
+const GLYPH_PADDING: usize = 1;
+const FLOATS_PER_VERTEX: usize = 8; //x, y, u, v, r, g, b, a
+
+#[derive(Clone, Copy, Debug)]
+pub struct Glyph {
+    uv_min: glm::Vec2,
+    uv_max: glm::Vec2,
+    size: glm::Vec2,
+    /// Offset of the bitmap's top left corner from the pen position on the baseline
+    offset: glm::Vec2,
+    advance: f32
+}
+
+/// The printable ASCII range of a font rasterized into a single-channel texture
+pub struct GlyphAtlas {
+    pub texture: Texture,
+    glyphs: HashMap<char, Glyph>,
+    /// Texture coordinate of a fully opaque texel, used to draw solid rectangles with the text shader
+    solid_uv: glm::Vec2,
+    pub ascent: f32,
+    pub line_height: f32
+}
+
+impl GlyphAtlas {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(font: &Font, pixel_size: f32) -> Self {
+        let scale = Scale::uniform(pixel_size);
+        let v_metrics = font.v_metrics(scale);
+
+        //Rasterize every glyph first so the atlas height is known before packing into it
+        let mut bitmaps = Vec::new();
+        for c in (32u8..127).map(char::from) {
+            let glyph = font.glyph(c).scaled(scale);
+            let advance = glyph.h_metrics().advance_width;
+            let glyph = glyph.positioned(point(0.0, 0.0));
+            let mut bitmap = Vec::new();
+            let mut bounds = (0, 0, 0, 0);
+            if let Some(bb) = glyph.pixel_bounding_box() {
+                bounds = (bb.min.x, bb.min.y, bb.width() as usize, bb.height() as usize);
+                bitmap = vec![0u8; bounds.2 * bounds.3];
+                glyph.draw(|x, y, v| bitmap[y as usize * bounds.2 + x as usize] = (v * 255.0) as u8);
+            }
+            bitmaps.push((c, advance, bounds, bitmap));
+        }
+
+        //Shelf pack, reserving a 2x2 opaque block in the top left corner
+        let mut placements = Vec::new();
+        let (mut x, mut y, mut shelf_height) = (2 + GLYPH_PADDING, 0, 2);
+        for (_, _, (_, _, width, height), _) in &bitmaps {
+            if x + width > ATLAS_WIDTH {
+                x = 0;
+                y += shelf_height + GLYPH_PADDING;
+                shelf_height = 0;
+            }
+            placements.push((x, y));
+            x += width + GLYPH_PADDING;
+            shelf_height = shelf_height.max(*height);
+        }
+        let atlas_height = (y + shelf_height).next_power_of_two();
+
+        let mut pixels = vec![0u8; ATLAS_WIDTH * atlas_height];
+        pixels[0] = 255;
+        pixels[1] = 255;
+        pixels[ATLAS_WIDTH] = 255;
+        pixels[ATLAS_WIDTH + 1] = 255;
+
+        let atlas_size = glm::vec2(ATLAS_WIDTH as f32, atlas_height as f32);
+        let mut glyphs = HashMap::new();
+        for ((c, advance, (min_x, min_y, width, height), bitmap), (px, py)) in bitmaps.iter().zip(placements) {
+            for row in 0..*height {
+                let start = (py + row) * ATLAS_WIDTH + px;
+                pixels[start..start + width].copy_from_slice(&bitmap[row * width..(row + 1) * width]);
+            }
+
+            let size = glm::vec2(*width as f32, *height as f32);
+            let uv_min = glm::vec2(px as f32, py as f32).component_div(&atlas_size);
+            glyphs.insert(*c, Glyph {
+                uv_min,
+                uv_max: uv_min + size.component_div(&atlas_size),
+                size,
+                offset: glm::vec2(*min_x as f32, *min_y as f32),
+                advance: *advance
+            });
+        }
+
+        let texture = Texture::new(gl::TEXTURE_2D);
+        texture.bind(0);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::R8 as GLint,
+            ATLAS_WIDTH as GLsizei,
+            atlas_height as GLsizei,
+            0,
+            gl::RED,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const _
+        );
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        texture.set_sampling(gl::NEAREST, gl::CLAMP_TO_EDGE);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        Self {
+            texture,
+            glyphs,
+            solid_uv: glm::vec2(1.0, 1.0).component_div(&atlas_size),
+            ascent: v_metrics.ascent,
+            line_height: v_metrics.ascent - v_metrics.descent + v_metrics.line_gap
+        }
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+    }
+}
+
+/// Batches text and solid rectangles into a single draw call. Coordinates are in pixels with the
+/// origin in the top left corner of the screen.
+pub struct TextRenderer {
+    program: ShaderProgram,
+    atlas: GlyphAtlas,
+    vao: VertexArray,
+    vbo: Buffer,
+    vertices: Vec<f32>
+}
+
+impl TextRenderer {
+    pub fn new(font_data: &'static [u8], pixel_size: f32) -> Result<Self, ShaderError> {
+        let font = Font::try_from_bytes(font_data).expect("Cannot load font");
+
+        unsafe {
+            let vertex_shader = Shader::new("text_vertex".to_string(), include_str!("../shaders/text.vert"), gl::VERTEX_SHADER, &Defines::new())?;
+            let mut fragment_shader = Shader::new("text".to_string(), include_str!("../shaders/text.frag"), gl::FRAGMENT_SHADER, &Defines::new())?;
+            fragment_shader.add_uniform("u_atlas".to_string(), UniformValue::Sampler(0));
+
+            let program = ShaderProgram::new(vec!(vertex_shader, fragment_shader))?;
+            program.bind_uniform_block(FRAME_BLOCK, FRAME_BLOCK_BINDING, FrameUniforms::size())?;
+            let atlas = GlyphAtlas::new(&font, pixel_size);
+
+            let vao = VertexArray::new();
+            let vbo = Buffer::new(gl::ARRAY_BUFFER);
+            vao.float_attribute(&vbo, 0, 4, FLOATS_PER_VERTEX, 0);
+            vao.float_attribute(&vbo, 1, 4, FLOATS_PER_VERTEX, 4);
+            gl::BindVertexArray(0);
+
+            Ok(Self { program, atlas, vao, vbo, vertices: Vec::new() })
+        }
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.atlas.line_height
+    }
+
+    /// Width of a single line of text in pixels
+    pub fn measure(&self, text: &str) -> f32 {
+        text.chars()
+            .filter_map(|c| self.atlas.glyph(c))
+            .map(|glyph| glyph.advance)
+            .sum()
+    }
+
+    /// Queues a line of text with its top left corner at `position`
+    pub fn queue_text(&mut self, text: &str, position: glm::Vec2, color: glm::Vec4) {
+        let mut pen = glm::vec2(position.x, position.y + self.atlas.ascent);
+        for c in text.chars() {
+            if let Some(glyph) = self.atlas.glyph(c).copied() {
+                if glyph.size.x > 0.0 {
+                    let min = glm::vec2(pen.x.round(), pen.y.round()) + glyph.offset;
+                    self.push_quad(min, min + glyph.size, glyph.uv_min, glyph.uv_max, color);
+                }
+                pen.x += glyph.advance;
+            }
+        }
+    }
+
+    pub fn queue_rect(&mut self, min: glm::Vec2, max: glm::Vec2, color: glm::Vec4) {
+        let uv = self.atlas.solid_uv;
+        self.push_quad(min, max, uv, uv, color);
+    }
+
+    fn push_quad(&mut self, min: glm::Vec2, max: glm::Vec2, uv_min: glm::Vec2, uv_max: glm::Vec2, color: glm::Vec4) {
+        let corners = [
+            (min.x, min.y, uv_min.x, uv_min.y),
+            (max.x, min.y, uv_max.x, uv_min.y),
+            (min.x, max.y, uv_min.x, uv_max.y),
+            (max.x, min.y, uv_max.x, uv_min.y),
+            (max.x, max.y, uv_max.x, uv_max.y),
+            (min.x, max.y, uv_min.x, uv_max.y)
+        ];
+
+        for (x, y, u, v) in corners {
+            self.vertices.extend_from_slice(&[x, y, u, v, color.x, color.y, color.z, color.w]);
+        }
+    }
+
+    /// Draws and clears everything queued since the last flush. The screen size is read from the frame block.
+    pub fn flush(&mut self) {
+        if self.vertices.is_empty() {
+            return;
+        }
+
+        unsafe {
+            self.program.apply();
+            self.program.get_shader("text".to_string()).unwrap().apply_uniforms();
+
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            self.atlas.texture.bind(0);
+
+            self.vao.bind();
+            self.vbo.set_data(&self.vertices, gl::STREAM_DRAW);
+            gl::DrawArrays(gl::TRIANGLES, 0, (self.vertices.len() / FLOATS_PER_VERTEX) as GLsizei);
+            gl::BindVertexArray(0);
+            gl::Disable(gl::BLEND);
+        }
+
+        self.vertices.clear();
+    }
+}
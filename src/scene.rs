@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::colormap::ColormapRegistry;
+use crate::scheduler::ScheduleConfig;
+use crate::simulation::{SimulationConfig, SPAWN_DISTRIBUTION};
+use crate::software::DensityParams;
+use crate::tools::ToolConfig;
+
+#[derive(Debug, Error)]
+pub enum SceneError {
+    #[error("Cannot read scene {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Cannot parse scene {path}: {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("Invalid scene {path}:\n  {}", .problems.join("\n  "))]
+    Invalid { path: PathBuf, problems: Vec<String> },
+}
+
+/// `[render]` table: the colormap, plus the constants of the density shader in `[render.shader]`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    pub colormap: String,
+    pub shader: DensityParams
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            colormap: "lightblue".to_string(),
+            shader: DensityParams::default()
+        }
+    }
+}
+
+/// `[window]` table. Sizes are in logical pixels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub title: String
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 800,
+            title: "Callisto".to_string()
+        }
+    }
+}
+
+/// Declarative description of a run, loaded from a TOML file. Every table and key is optional and
+/// falls back to the same defaults as `Simulation::default()`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    pub simulation: SimulationConfig,
+    pub render: RenderConfig,
+    pub window: WindowConfig,
+    pub schedule: ScheduleConfig,
+    pub tools: ToolConfig
+}
+
+impl Scene {
+    /// Parses and validates a scene file. Colormap names are checked separately by `validate_colormap`,
+    /// since user colormaps are loaded at runtime.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let scene = Self::load_unvalidated(&path)?;
+        scene.validate(path)?;
+        Ok(scene)
+    }
+
+    pub fn parse(path: impl AsRef<Path>, source: &str) -> Result<Self, SceneError> {
+        let scene = Self::parse_unvalidated(&path, source)?;
+        scene.validate(path)?;
+        Ok(scene)
+    }
+
+    /// Parses a scene file without checking the values, for changing them before calling `validate`
+    pub fn load_unvalidated(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+        Self::parse_unvalidated(path, &source)
+    }
+
+    pub fn parse_unvalidated(path: impl AsRef<Path>, source: &str) -> Result<Self, SceneError> {
+        toml::from_str(source).map_err(|source| SceneError::Parse { path: path.as_ref().to_path_buf(), source })
+    }
+
+    /// Checks value ranges. Needed again after changing a loaded scene, e.g. with command line overrides.
+    pub fn validate(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SceneError::Invalid { path: path.as_ref().to_path_buf(), problems })
+        }
+    }
+
+    pub fn validate_colormap(&self, path: impl AsRef<Path>, colormaps: &ColormapRegistry) -> Result<(), SceneError> {
+        if colormaps.get(&self.render.colormap).is_some() {
+            return Ok(());
+        }
+
+        let available: Vec<&str> = colormaps.iter().map(|colormap| colormap.name.as_str()).collect();
+        Err(SceneError::Invalid {
+            path: path.as_ref().to_path_buf(),
+            problems: vec!(format!("render.colormap: unknown colormap \"{}\" (available: {})", self.render.colormap, available.join(", ")))
+        })
+    }
+
+    /// Every validation failure, so they can all be reported at once
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        parameter_problems(&self.simulation, "simulation.", &mut problems);
+        spawn_problems(&self.simulation, "simulation.", &mut problems);
+        let mut check = |ok: bool, message: &str| {
+            if !ok {
+                problems.push(message.to_string());
+            }
+        };
+
+        let shader = &self.render.shader;
+        check(shader.sim_resolution.is_finite() && shader.sim_resolution > 0.0, "render.shader.sim_resolution must be greater than 0");
+        check(non_negative(shader.brightness), "render.shader.brightness must be 0 or greater");
+        check(non_negative(shader.spread), "render.shader.spread must be 0 or greater");
+        check(non_negative(shader.crosshair_line_width), "render.shader.crosshair_line_width must be 0 or greater");
+        check(non_negative(shader.crosshair_size), "render.shader.crosshair_size must be 0 or greater");
+        check(non_negative(shader.crosshair_gap_size), "render.shader.crosshair_gap_size must be 0 or greater");
+
+        check(self.window.width > 0 && self.window.height > 0, "window.width and window.height must be greater than 0");
+
+        let schedule = &self.schedule;
+        check(schedule.steps_per_second.is_finite() && schedule.steps_per_second > 0.0, "schedule.steps_per_second must be greater than 0");
+        check(non_negative(schedule.time_scale), "schedule.time_scale must be 0 or greater");
+        check(schedule.max_steps_per_frame >= 1, "schedule.max_steps_per_frame must be at least 1");
+
+        let tools = &self.tools;
+        check(non_negative(tools.spawn_rate), "tools.spawn_rate must be 0 or greater");
+        check(finite(&tools.spawn_velocity), "tools.spawn_velocity must be finite");
+        check(non_negative(tools.spawn_velocity_jitter), "tools.spawn_velocity_jitter must be 0 or greater");
+
+        problems
+    }
+}
+
+/// Problems with the parameters a running simulation keeps, with `prefix` before the key names
+pub(crate) fn parameter_problems(simulation: &SimulationConfig, prefix: &str, problems: &mut Vec<String>) {
+    let mut check = |ok: bool, message: &str| {
+        if !ok {
+            problems.push(format!("{}{}", prefix, message));
+        }
+    };
+
+    check(simulation.dt.is_finite() && simulation.dt > 0.0, "dt must be greater than 0");
+    check(non_negative(simulation.attractive_force), "attractive_force must be 0 or greater");
+    check(non_negative(simulation.repulsive_force), "repulsive_force must be 0 or greater");
+    check(non_negative(simulation.drag), "drag must be 0 or greater");
+    check(simulation.microsteps >= 1, "microsteps must be at least 1");
+    check(simulation.gravity_strength.is_finite(), "gravity_strength must be a finite number");
+    check(simulation.mouse_strength.is_finite(), "mouse_strength must be a finite number");
+    check(simulation.mouse_radius.is_finite() && simulation.mouse_radius > 0.0, "mouse_radius must be greater than 0");
+}
+
+/// Problems with how the particles are spawned, with `prefix` before the key names
+pub(crate) fn spawn_problems(simulation: &SimulationConfig, prefix: &str, problems: &mut Vec<String>) {
+    let mut check = |ok: bool, message: &str| {
+        if !ok {
+            problems.push(format!("{}{}", prefix, message));
+        }
+    };
+
+    check(non_negative(simulation.max_spawn_velocity), "max_spawn_velocity must be 0 or greater");
+    check(simulation.num_particles >= 1, "num_particles must be at least 1");
+    match &simulation.spawn {
+        SPAWN_DISTRIBUTION::RING { center, inner_radius, outer_radius } => {
+            check(finite(center), "spawn.center must be finite");
+            check(non_negative(*inner_radius) && outer_radius.is_finite() && outer_radius >= inner_radius, "spawn radii must satisfy 0 <= inner_radius <= outer_radius");
+        },
+        SPAWN_DISTRIBUTION::DISK { center, radius } => {
+            check(finite(center), "spawn.center must be finite");
+            check(non_negative(*radius), "spawn.radius must be 0 or greater");
+        },
+        SPAWN_DISTRIBUTION::UNIFORM { min, max } => {
+            check(finite(min) && finite(max) && min[0] <= max[0] && min[1] <= max[1], &format!("spawn.min must be less than or equal to {}spawn.max", prefix));
+        },
+        SPAWN_DISTRIBUTION::GAUSSIAN { center, std_dev } => {
+            check(finite(center), "spawn.center must be finite");
+            check(non_negative(*std_dev), "spawn.std_dev must be 0 or greater");
+        }
+    }
+}
+
+fn non_negative(value: f32) -> bool {
+    value.is_finite() && value >= 0.0
+}
+
+pub(crate) fn finite(value: &[f32; 2]) -> bool {
+    value.iter().all(|v| v.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(source: &str) -> Vec<String> {
+        match Scene::parse("scene.toml", source) {
+            Err(SceneError::Invalid { problems, .. }) => problems,
+            result => panic!("expected an invalid scene for {:?}, got {:?}", source, result)
+        }
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        Scene::parse("scene.toml", "").unwrap();
+        Scene::default().validate("scene.toml").unwrap();
+    }
+
+    #[test]
+    fn rejects_each_invalid_field() {
+        let cases = [
+            ("[simulation]\ndt = 0.0", "simulation.dt must be greater than 0"),
+            ("[simulation]\ndt = nan", "simulation.dt must be greater than 0"),
+            ("[simulation]\nattractive_force = -1.0", "simulation.attractive_force must be 0 or greater"),
+            ("[simulation]\nrepulsive_force = -1.0", "simulation.repulsive_force must be 0 or greater"),
+            ("[simulation]\ndrag = inf", "simulation.drag must be 0 or greater"),
+            ("[simulation]\nmicrosteps = 0", "simulation.microsteps must be at least 1"),
+            ("[simulation]\ngravity_strength = nan", "simulation.gravity_strength must be a finite number"),
+            ("[simulation]\nmouse_strength = -inf", "simulation.mouse_strength must be a finite number"),
+            ("[simulation]\nmouse_radius = 0.0", "simulation.mouse_radius must be greater than 0"),
+            ("[simulation]\nmax_spawn_velocity = -0.1", "simulation.max_spawn_velocity must be 0 or greater"),
+            ("[simulation]\nnum_particles = 0", "simulation.num_particles must be at least 1"),
+            ("[simulation.spawn]\ndistribution = \"ring\"\ncenter = [0.5, nan]\ninner_radius = 0.1\nouter_radius = 0.2", "simulation.spawn.center must be finite"),
+            ("[simulation.spawn]\ndistribution = \"ring\"\ncenter = [0.5, 0.5]\ninner_radius = 0.3\nouter_radius = 0.2", "simulation.spawn radii must satisfy 0 <= inner_radius <= outer_radius"),
+            ("[simulation.spawn]\ndistribution = \"disk\"\ncenter = [0.5, 0.5]\nradius = -0.1", "simulation.spawn.radius must be 0 or greater"),
+            ("[simulation.spawn]\ndistribution = \"uniform\"\nmin = [0.6, 0.0]\nmax = [0.5, 1.0]", "simulation.spawn.min must be less than or equal to simulation.spawn.max"),
+            ("[simulation.spawn]\ndistribution = \"gaussian\"\ncenter = [0.5, 0.5]\nstd_dev = -1.0", "simulation.spawn.std_dev must be 0 or greater"),
+            ("[render.shader]\nsim_resolution = 0.0", "render.shader.sim_resolution must be greater than 0"),
+            ("[render.shader]\nbrightness = -1.0", "render.shader.brightness must be 0 or greater"),
+            ("[render.shader]\nspread = -1.0", "render.shader.spread must be 0 or greater"),
+            ("[render.shader]\ncrosshair_line_width = -1.0", "render.shader.crosshair_line_width must be 0 or greater"),
+            ("[render.shader]\ncrosshair_size = -1.0", "render.shader.crosshair_size must be 0 or greater"),
+            ("[render.shader]\ncrosshair_gap_size = -1.0", "render.shader.crosshair_gap_size must be 0 or greater"),
+            ("[window]\nwidth = 0", "window.width and window.height must be greater than 0"),
+            ("[window]\nheight = 0", "window.width and window.height must be greater than 0"),
+            ("[schedule]\nsteps_per_second = 0.0", "schedule.steps_per_second must be greater than 0"),
+            ("[schedule]\ntime_scale = -1.0", "schedule.time_scale must be 0 or greater"),
+            ("[schedule]\nmax_steps_per_frame = 0", "schedule.max_steps_per_frame must be at least 1"),
+            ("[tools]\nspawn_rate = -1.0", "tools.spawn_rate must be 0 or greater"),
+            ("[tools]\nspawn_velocity = [inf, 0.0]", "tools.spawn_velocity must be finite"),
+            ("[tools]\nspawn_velocity_jitter = -1.0", "tools.spawn_velocity_jitter must be 0 or greater")
+        ];
+
+        for (source, problem) in cases {
+            assert_eq!(problems(source), [problem], "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn reports_every_problem_with_the_path() {
+        let error = Scene::parse("scenes/broken.toml", "[simulation]\ndt = -1.0\nmicrosteps = 0\n\n[window]\nwidth = 0").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid scene scenes/broken.toml:\n  \
+            simulation.dt must be greater than 0\n  \
+            simulation.microsteps must be at least 1\n  \
+            window.width and window.height must be greater than 0");
+    }
+
+    #[test]
+    fn validate_checks_changed_values() {
+        let mut scene = Scene::default();
+        scene.simulation.num_particles = -5;
+        let Err(SceneError::Invalid { path, problems }) = scene.validate("command line") else { panic!("invalid scene was accepted") };
+        assert_eq!(path, Path::new("command line"));
+        assert_eq!(problems, ["simulation.num_particles must be at least 1"]);
+    }
+
+    #[test]
+    fn rejects_unknown_colormaps() {
+        let scene = Scene::parse("scene.toml", "[render]\ncolormap = \"nope\"").unwrap();
+        let error = scene.validate_colormap("scene.toml", &ColormapRegistry::default()).unwrap_err();
+        assert!(error.to_string().contains("render.colormap: unknown colormap \"nope\" (available: lightblue,"), "{}", error);
+    }
+}
@@ -0,0 +1,347 @@
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::simulation::Simulation;
+
+/// Longest the simulation thread sleeps before checking for changes, so stopping stays responsive when paused
+const MAX_IDLE: Duration = Duration::from_millis(50);
+
+/// `[schedule]` table: how simulation steps are paced against real time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Simulation steps per second of real time, at a time scale of 1
+    pub steps_per_second: f32,
+    /// Multiplier on the step rate
+    pub time_scale: f32,
+    /// Most steps run for a single frame. When the simulation can't keep up, it slows down instead of
+    /// falling further and further behind.
+    pub max_steps_per_frame: u32,
+    /// Draw the particles between their last two states, so motion is smooth when frames and steps don't line up
+    pub interpolate: bool,
+    /// Run the simulation on its own thread, so slow steps don't hold up drawing
+    pub threaded: bool
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            steps_per_second: 60.0,
+            time_scale: 1.0,
+            max_steps_per_frame: 8,
+            interpolate: true,
+            threaded: false
+        }
+    }
+}
+
+/// Fixed-timestep scheduler: real time accumulates, and is spent in whole simulation steps
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    /// Real time per step at a time scale of 1, in seconds
+    pub step_interval: f64,
+    pub time_scale: f64,
+    pub max_steps_per_frame: u32,
+    paused: bool,
+    /// Scaled real time not yet spent on steps, in seconds
+    accumulator: f64,
+    /// Steps requested with `step_once`
+    single_steps: u32,
+    last_tick: Option<Instant>
+}
+
+impl FixedTimestep {
+    pub fn new(config: &ScheduleConfig) -> Self {
+        Self {
+            step_interval: 1.0 / config.steps_per_second as f64,
+            time_scale: config.time_scale as f64,
+            max_steps_per_frame: config.max_steps_per_frame,
+            paused: false,
+            accumulator: 0.0,
+            single_steps: 0,
+            last_tick: None
+        }
+    }
+
+    /// `advance` by the real time since the previous tick. The first tick runs no steps.
+    pub fn tick(&mut self) -> u32 {
+        let now = Instant::now();
+        let elapsed = self.last_tick.map_or(Duration::ZERO, |last_tick| now - last_tick);
+        self.last_tick = Some(now);
+        self.advance(elapsed)
+    }
+
+    /// Adds `elapsed` real time and returns the number of steps to run now
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        if self.paused {
+            self.accumulator = 0.0;
+            return std::mem::take(&mut self.single_steps);
+        }
+
+        self.accumulator += elapsed.as_secs_f64() * self.time_scale;
+        let due = (self.accumulator / self.step_interval).floor();
+        //Time beyond the step limit is dropped rather than carried over
+        self.accumulator -= due * self.step_interval;
+        (due as u32).min(self.max_steps_per_frame)
+    }
+
+    /// How far into the next step real time is, from 0 to 1. The state to draw is the previous one mixed with
+    /// the latest one by this amount.
+    pub fn alpha(&self) -> f32 {
+        if self.paused {
+            1.0
+        } else {
+            (self.accumulator / self.step_interval).clamp(0.0, 1.0) as f32
+        }
+    }
+
+    /// Time until the next step is due, in real time
+    pub fn until_next_step(&self) -> Option<Duration> {
+        if self.paused || self.time_scale <= 0.0 {
+            return None;
+        }
+        let remaining = (self.step_interval - self.accumulator).max(0.0) / self.time_scale;
+        Some(Duration::from_secs_f64(remaining))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.accumulator = 0.0;
+        self.single_steps = 0;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.set_paused(!self.paused);
+    }
+
+    /// Pauses, and runs exactly one step on the next tick
+    pub fn step_once(&mut self) {
+        self.paused = true;
+        self.single_steps += 1;
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f64) {
+        self.time_scale = time_scale.max(0.0);
+    }
+}
+
+/// Positions published by a [`SimulationThread`] after every step
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    /// Positions before the latest step, packed like `Simulation::pack`
+    pub previous: Vec<f32>,
+    /// Positions after the latest step
+    pub current: Vec<f32>,
+    /// Simulation time after the latest step
+    pub time: f64,
+    pub steps: u64,
+    /// When the latest step finished
+    pub stepped_at: Option<Instant>
+}
+
+impl Snapshot {
+    fn new(simulation: &Simulation) -> Self {
+        let positions = simulation.pack().concat();
+        Self { previous: positions.clone(), current: positions, time: simulation.t, steps: 0, stepped_at: None }
+    }
+
+    /// Replaces both states with the current positions, so nothing is interpolated from before a jump
+    fn reset(&mut self, simulation: &Simulation) {
+        self.previous.clear();
+        for positions in simulation.pack() {
+            self.previous.extend_from_slice(positions);
+        }
+        self.current.clone_from(&self.previous);
+        self.time = simulation.t;
+        self.stepped_at = None;
+    }
+
+    /// Moves the current positions to `previous` and copies in the new ones, reusing both allocations
+    fn publish(&mut self, simulation: &Simulation) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+        for positions in simulation.pack() {
+            self.current.extend_from_slice(positions);
+        }
+        self.time = simulation.t;
+        self.steps += 1;
+        self.stepped_at = Some(Instant::now());
+    }
+}
+
+enum Message {
+    Simulation(Box<dyn FnOnce(&mut Simulation) + Send>),
+    /// A change to the particles themselves, which is published right away
+    Particles(Box<dyn FnOnce(&mut Simulation) + Send>),
+    Clock(Box<dyn FnOnce(&mut FixedTimestep) + Send>),
+    Stop
+}
+
+/// Steps a simulation on its own thread at a fixed timestep. The thread owns the simulation, so changes are sent
+/// to it as closures, and the positions come back as a double-buffered [`Snapshot`].
+pub struct SimulationThread {
+    messages: mpsc::Sender<Message>,
+    snapshot: Arc<Mutex<Snapshot>>,
+    handle: Option<JoinHandle<Simulation>>
+}
+
+impl SimulationThread {
+    pub fn spawn(mut simulation: Simulation, mut clock: FixedTimestep) -> Self {
+        let (messages, inbox) = mpsc::channel();
+        let snapshot = Arc::new(Mutex::new(Snapshot::new(&simulation)));
+        let published = snapshot.clone();
+
+        let handle = thread::Builder::new().name("simulation".to_string()).spawn(move || {
+            loop {
+                let wait = clock.until_next_step().map_or(MAX_IDLE, |wait| wait.min(MAX_IDLE));
+                let mut message = match inbox.recv_timeout(wait) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return simulation
+                };
+                while let Some(received) = message.take() {
+                    match received {
+                        Message::Simulation(change) => change(&mut simulation),
+                        Message::Particles(change) => {
+                            change(&mut simulation);
+                            published.lock().unwrap().reset(&simulation);
+                        },
+                        Message::Clock(change) => change(&mut clock),
+                        Message::Stop => return simulation
+                    }
+                    message = inbox.try_recv().ok();
+                }
+
+                for _ in 0..clock.tick() {
+                    simulation.step();
+                    published.lock().unwrap().publish(&simulation);
+                }
+            }
+        }).expect("Cannot spawn simulation thread");
+
+        Self { messages, snapshot, handle: Some(handle) }
+    }
+
+    /// Applies `change` to the simulation before its next step
+    pub fn update_simulation(&self, change: impl FnOnce(&mut Simulation) + Send + 'static) {
+        let _ = self.messages.send(Message::Simulation(Box::new(change)));
+    }
+
+    /// Applies a change that replaces or moves particles, and publishes the result without waiting for a step
+    pub fn update_particles(&self, change: impl FnOnce(&mut Simulation) + Send + 'static) {
+        let _ = self.messages.send(Message::Particles(Box::new(change)));
+    }
+
+    /// Applies `change` to the thread's scheduler, e.g. to pause it
+    pub fn update_clock(&self, change: impl FnOnce(&mut FixedTimestep) + Send + 'static) {
+        let _ = self.messages.send(Message::Clock(Box::new(change)));
+    }
+
+    /// Runs `read` on the simulation between steps and waits for the result
+    pub fn query<T: Send + 'static>(&self, read: impl FnOnce(&Simulation) -> T + Send + 'static) -> T {
+        let (sender, receiver) = mpsc::channel();
+        self.update_simulation(move |simulation| {
+            let _ = sender.send(read(simulation));
+        });
+        receiver.recv().expect("Simulation thread stopped")
+    }
+
+    /// The latest positions. The thread waits for the guard to be dropped before publishing again.
+    pub fn snapshot(&self) -> MutexGuard<'_, Snapshot> {
+        self.snapshot.lock().unwrap()
+    }
+
+    /// Stops the thread and returns the simulation
+    pub fn join(mut self) -> Simulation {
+        self.stop().expect("Simulation thread already stopped")
+    }
+
+    fn stop(&mut self) -> Option<Simulation> {
+        let handle = self.handle.take()?;
+        let _ = self.messages.send(Message::Stop);
+        handle.join().ok()
+    }
+}
+
+impl Drop for SimulationThread {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::SimulationConfig;
+
+    fn clock() -> FixedTimestep {
+        FixedTimestep::new(&ScheduleConfig { steps_per_second: 100.0, ..ScheduleConfig::default() })
+    }
+
+    #[test]
+    fn steps_follow_real_time() {
+        let mut clock = clock();
+        assert_eq!(clock.advance(Duration::from_millis(5)), 0);
+        assert!((clock.alpha() - 0.5).abs() < 1e-4);
+        assert_eq!(clock.advance(Duration::from_millis(30)), 3);
+        assert!((clock.alpha() - 0.5).abs() < 1e-4);
+
+        clock.set_time_scale(2.0);
+        assert_eq!(clock.advance(Duration::from_millis(25)), 5);
+    }
+
+    #[test]
+    fn long_frames_are_capped() {
+        let mut clock = clock();
+        assert_eq!(clock.advance(Duration::from_secs(10)), clock.max_steps_per_frame);
+        assert_eq!(clock.advance(Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn paused_clock_only_runs_single_steps() {
+        let mut clock = clock();
+        clock.toggle_pause();
+        assert_eq!(clock.advance(Duration::from_secs(1)), 0);
+        clock.step_once();
+        clock.step_once();
+        assert_eq!(clock.advance(Duration::from_secs(1)), 2);
+        assert_eq!(clock.advance(Duration::from_secs(1)), 0);
+        assert_eq!(clock.alpha(), 1.0);
+    }
+
+    #[test]
+    fn thread_steps_and_publishes() {
+        let config = SimulationConfig { num_particles: 16, seed: Some(5), ..SimulationConfig::default() };
+        let mut expected = Simulation::from_config(&config);
+        let mut paused = clock();
+        paused.set_paused(true);
+
+        let thread = SimulationThread::spawn(Simulation::from_config(&config), paused);
+        thread.update_clock(|clock| {
+            clock.step_once();
+            clock.step_once();
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while thread.snapshot().steps < 2 {
+            assert!(Instant::now() < deadline, "Simulation thread didn't run the requested steps");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        expected.step();
+        let previous = expected.pack().concat();
+        expected.step();
+        {
+            let snapshot = thread.snapshot();
+            assert_eq!(snapshot.previous, previous);
+            assert_eq!(snapshot.current, expected.pack().concat());
+        }
+        assert_eq!(thread.query(|simulation| simulation.particles.velocities().0.to_vec()), expected.particles.velocities().0);
+        assert_eq!(thread.join().pack(), expected.pack());
+    }
+}
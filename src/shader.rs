@@ -1,22 +1,60 @@
 use gl::types::*;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::{CString, NulError};
 use std::ptr;
 use std::string::FromUtf8Error;
 use thiserror::Error;
-use crate::uniform::{UniformManager, UniformValue};
+use crate::shaderprogram::ActiveUniform;
+use crate::uniform::{Uniform, UniformError, UniformManager, UniformValue};
+use crate::shader::diagnostics::{Diagnostic, Severity, format_diagnostics, parse_log};
+use crate::shader::preprocess::{builtin_include, preprocess};
+
+pub mod diagnostics;
+pub mod preprocess;
 
 #[derive(Debug, Error)]
 pub enum ShaderError {
-    #[error("Error while compiling shader: {0}")]
-    CompilationError(String),
-    #[error("Error while linking shaders: {0}")]
-    LinkingError(String),
+    #[error("Error while compiling shader {shader}:\n{}", format_diagnostics(.diagnostics, .log))]
+    CompilationError { shader: String, diagnostics: Vec<Diagnostic>, log: String },
+    #[error("Error while linking shaders:\n{}", format_diagnostics(.diagnostics, .log))]
+    LinkingError { diagnostics: Vec<Diagnostic>, log: String },
+    #[error("Cannot include \"{name}\" from {file}:{line}: no such file")]
+    IncludeNotFound { name: String, file: String, line: usize },
+    #[error("Invalid #include in {file}:{line}, expected #include \"file.glsl\"")]
+    InvalidInclude { file: String, line: usize },
+    #[error("Include cycle: {}", .0.join(" -> "))]
+    IncludeCycle(Vec<String>),
+    #[error("Uniform {name} is declared as {expected} in the shader, but set to {found}")]
+    UniformTypeMismatch { name: String, expected: String, found: String },
+    #[error("Uniform {name} has {size} elements in the shader, but is set to {len}")]
+    UniformArrayTooLong { name: String, size: usize, len: usize },
+    #[error("Uniform block {0} is not declared in the program")]
+    UniformBlockNotFound(String),
+    #[error("Uniform block {name} is {shader_size} bytes in the shader, but {buffer_size} bytes are uploaded to it")]
+    UniformBlockSizeMismatch { name: String, shader_size: usize, buffer_size: usize },
     #[error{"{0}"}]
     Utf8Error(#[from] FromUtf8Error),
     #[error{"{0}"}]
     NulError(#[from] NulError),
 }
 
+/// Preprocessor defines injected into a shader source, as name -> value
+pub type Defines = BTreeMap<String, String>;
+
+/// Reads the info log of a shader or program, given the matching `glGet*iv` and `glGet*InfoLog` functions
+pub(crate) unsafe fn info_log(
+    id: GLuint,
+    get_iv: unsafe fn(GLuint, GLenum, *mut GLint),
+    get_info_log: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar)
+) -> Result<String, FromUtf8Error> {
+    let mut log_size: GLint = 0;
+    get_iv(id, gl::INFO_LOG_LENGTH, &mut log_size);
+    let mut log: Vec<u8> = Vec::with_capacity(log_size.max(0) as usize);
+    get_info_log(id, log_size, &mut log_size, log.as_mut_ptr() as *mut _);
+    log.set_len(log_size.max(0) as usize);
+    String::from_utf8(log)
+}
+
 #[derive(Clone)]
 pub struct Shader {
     pub id: GLuint,
@@ -25,8 +63,13 @@ pub struct Shader {
 }
 
 impl Shader {
-    pub unsafe fn new(name: String, source_code: &str, shader_type: GLenum) -> Result<Self, ShaderError> {
-        let source_code = CString::new(source_code)?;
+    /// Compiles `source_code` after injecting `defines` and resolving `#include`s of the files in `shaders/include`
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(name: String, source_code: &str, shader_type: GLenum, defines: &Defines) -> Result<Self, ShaderError> {
+        let preprocessed = preprocess(&name, source_code, defines, builtin_include)?;
+        let source_code = CString::new(preprocessed.source.as_str())?;
 
         let shader = Self {
             name,
@@ -36,30 +79,25 @@ impl Shader {
 
         gl::ShaderSource(shader.id, 1, &source_code.as_ptr(), ptr::null());
 
-        println!("Compiling shader...");
         gl::CompileShader(shader.id);
 
-        //Check for compilation errors
+        //Check for compilation errors. The log can also hold warnings when compilation succeeds.
         let mut success: GLint = 0;
         gl::GetShaderiv(shader.id, gl::COMPILE_STATUS, &mut success);
+        let log = info_log(shader.id, gl::GetShaderiv, gl::GetShaderInfoLog)?;
+        let diagnostics = parse_log(&log, Some(&preprocessed.source_map), &preprocessed.source);
+
         if success == 1 {
-            println!("Success");
+            for diagnostic in &diagnostics {
+                match diagnostic.severity {
+                    Severity::Warning => log::warn!("{}", diagnostic.details()),
+                    Severity::Note => log::info!("{}", diagnostic.details()),
+                    Severity::Error => ()
+                }
+            }
             Ok(shader)
         } else {
-            let mut error_log_size: GLint = 0;
-            gl::GetShaderiv(shader.id, gl::INFO_LOG_LENGTH, &mut error_log_size);
-            let mut error_log: Vec<u8> = Vec::with_capacity(error_log_size as usize);
-            gl::GetShaderInfoLog(
-                shader.id,
-                error_log_size,
-                &mut error_log_size,
-                error_log.as_mut_ptr() as *mut _
-            );
-
-            error_log.set_len(error_log_size as usize);
-            let log = String::from_utf8(error_log)?;
-            println!("Error: {}", log);
-            Err(ShaderError::CompilationError(log))
+            Err(ShaderError::CompilationError { shader: shader.name.clone(), diagnostics, log })
         }
     }
 
@@ -67,44 +105,27 @@ impl Shader {
         self.uniform_manager.add(key, value);
     }
 
-    pub fn update_uniform_value(&mut self, key: String, new_value: UniformValue) {
-        self.uniform_manager.update_value(key, new_value);
+    pub fn update_uniform_value(&mut self, key: &str, new_value: UniformValue) -> Result<(), UniformError> {
+        self.uniform_manager.update_value(key, new_value)
     }
 
-    fn apply_uniform(&self, program_id: GLuint, key: String) {
-        let location = unsafe {
-            gl::GetUniformLocation(program_id, CString::new(key.clone()).unwrap().as_ptr())
-        };
+    /// Typed setter, e.g. `shader.set_uniform("u_time", 1.5)` or `shader.set_uniform("u_offset", glm::vec3(0.0, 1.0, 0.0))`
+    pub fn set_uniform(&mut self, key: &str, value: impl Into<UniformValue>) -> Result<(), UniformError> {
+        self.uniform_manager.update_value(key, value.into())
+    }
 
-        if(location == -1) {
-            //println!("Uniform {} not found in shader", key.clone());
-        } else {
-            match self.uniform_manager.get_value(&key) {
-                UniformValue::Float(value) => unsafe {
-                    gl::Uniform1f(location, value as GLfloat);
-                },
-                UniformValue::Int(value) => unsafe {
-                    gl::Uniform1i(location, value as GLint);
-                },
-                UniformValue::Mat4(value) => unsafe {
-                    gl::UniformMatrix4fv(location, 1, gl::FALSE, value.as_ptr());
-                },
-                UniformValue::Array_F(value) => unsafe {
-                    gl::Uniform1fv(location, value.len() as GLsizei, value.as_ptr());
-                },
-                UniformValue::Vec2(value) => unsafe {
-                    gl::Uniform2f(location, value.x, value.y);
-                },
-                UniformValue::Bool(value) => unsafe {
-                    gl::Uniform1i(location, value as GLint);
-                }
-            }
-        }
+    pub fn uniforms(&self) -> impl Iterator<Item = &Uniform> {
+        self.uniform_manager.iter()
+    }
+
+    pub fn resolve_uniform_locations(&mut self, active_uniforms: &HashMap<String, ActiveUniform>) {
+        self.uniform_manager.resolve_locations(active_uniforms);
     }
 
-    pub fn apply_uniforms(&self, program_id: GLuint) {
-        for uniform in &self.uniform_manager.uniforms {
-            self.apply_uniform(program_id, uniform.get_key());
+    /// Uploads the uniforms that changed since the last call. The shader's program has to be in use.
+    pub fn apply_uniforms(&mut self) {
+        unsafe {
+            self.uniform_manager.upload_dirty();
         }
     }
 }
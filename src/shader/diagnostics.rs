@@ -0,0 +1,278 @@
+use std::fmt;
+use crate::shader::preprocess::SourceMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note")
+        }
+    }
+}
+
+/// One message from a driver's compile or link log, with its location mapped back to the original file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    /// 1-based, only reported by some drivers
+    pub column: Option<usize>,
+    pub message: String,
+    /// Text of the offending line
+    pub source_line: Option<String>
+}
+
+impl Diagnostic {
+    /// The message with its location and source line, without the severity
+    pub fn details(&self) -> String {
+        let mut details = self.message.clone();
+        let Some(line) = self.line else {
+            return details;
+        };
+        match (&self.file, self.column) {
+            (Some(file), Some(column)) => details += &format!("\n  --> {}:{}:{}", file, line, column),
+            (Some(file), None) => details += &format!("\n  --> {}:{}", file, line),
+            (None, _) => details += &format!("\n  --> line {}", line)
+        }
+
+        if let Some(source_line) = &self.source_line {
+            let gutter = " ".repeat(line.to_string().len());
+            details += &format!("\n{} |\n{} | {}", gutter, line, source_line);
+            if let Some(column) = self.column {
+                //Keep tabs so the caret lines up with the source line
+                let indent: String = source_line.chars().take(column.saturating_sub(1)).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                details += &format!("\n{} | {}^", gutter, indent);
+            }
+        }
+        details
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.details())
+    }
+}
+
+/// Formats diagnostics one after another, falling back to the raw log when nothing could be parsed
+pub fn format_diagnostics(diagnostics: &[Diagnostic], log: &str) -> String {
+    if diagnostics.is_empty() {
+        return log.trim_end().to_string();
+    }
+    diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<String>>().join("\n\n")
+}
+
+/// Parses a compile or link log. Understands the Mesa (`0:12(5): error: ...`), NVIDIA (`0(12) : error C0000: ...`)
+/// and AMD/Intel (`ERROR: 0:12: ...`) formats. Lines in other formats become diagnostics without a location,
+/// and indented lines without a location are treated as a continuation of the previous message.
+pub fn parse_log(log: &str, source_map: Option<&SourceMap>, source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for line in log.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some((severity, line_number, column, message)) = parse_located_line(line) {
+            let location = source_map.and_then(|source_map| source_map.locate(line_number));
+            let file = location.map(|location| location.file.clone());
+            let line_number_in_file = location.map_or(line_number, |location| location.line);
+
+            //Mesa lists candidate overloads as extra indented messages at the same location
+            match diagnostics.last_mut() {
+                Some(previous) if message.starts_with(char::is_whitespace) && previous.file == file && previous.line == Some(line_number_in_file) => {
+                    previous.message.push_str("\n    ");
+                    previous.message.push_str(message.trim());
+                    continue;
+                },
+                _ => ()
+            }
+
+            let source_line = source.lines().nth(line_number - 1).map(|line| line.to_string());
+            diagnostics.push(Diagnostic {
+                severity,
+                file,
+                line: Some(line_number_in_file),
+                column: column.map(|column| source_line.as_deref().map_or(column, |source_line| expand_column(source_line, column))),
+                message: message.trim().to_string(),
+                source_line
+            });
+            continue;
+        }
+
+        match diagnostics.last_mut() {
+            Some(previous) if line.starts_with(char::is_whitespace) => {
+                previous.message.push('\n');
+                previous.message.push_str(line.trim_end());
+            },
+            _ => {
+                let (severity, message) = split_severity(line.trim());
+                diagnostics.push(Diagnostic { severity, file: None, line: None, column: None, message: message.to_string(), source_line: None });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Returns the severity, line, column and message of a log line that starts with a source location
+fn parse_located_line(line: &str) -> Option<(Severity, usize, Option<usize>, String)> {
+    //AMD and Intel put the severity in front of the location
+    let (prefix_severity, rest) = if let Some(rest) = line.strip_prefix("ERROR: ") {
+        (Some(Severity::Error), rest)
+    } else if let Some(rest) = line.strip_prefix("WARNING: ") {
+        (Some(Severity::Warning), rest)
+    } else {
+        (None, line)
+    };
+
+    //Source string number, which is always 0 since every shader is a single string
+    let string_digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if string_digits == 0 {
+        return None;
+    }
+    let rest = &rest[string_digits..];
+
+    let (line_number, column, rest) = if let Some(rest) = rest.strip_prefix(':') {
+        //Mesa `0:12(5):` or AMD `0:12:`
+        let (line_number, rest) = split_number(rest)?;
+        match rest.strip_prefix('(') {
+            Some(rest) => {
+                let (column, rest) = split_number(rest)?;
+                (line_number, Some(column), rest.strip_prefix(')')?)
+            },
+            None => (line_number, None, rest)
+        }
+    } else if let Some(rest) = rest.strip_prefix('(') {
+        //NVIDIA `0(12) :`
+        let (line_number, rest) = split_number(rest)?;
+        (line_number, None, rest.strip_prefix(')')?)
+    } else {
+        return None;
+    };
+
+    if line_number == 0 {
+        return None;
+    }
+
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let (severity, message) = match prefix_severity {
+        Some(severity) => (severity, rest),
+        None => split_severity(rest)
+    };
+    Some((severity, line_number, column, message.to_string()))
+}
+
+/// Mesa counts columns after its preprocessor has collapsed every run of whitespace into a single space.
+/// Converts such a column back to a column in the original line.
+fn expand_column(source_line: &str, collapsed_column: usize) -> usize {
+    let mut collapsed = 1;
+    let mut previous_whitespace = false;
+    for (i, c) in source_line.chars().enumerate() {
+        let whitespace = c.is_whitespace();
+        if !(whitespace && previous_whitespace) {
+            if collapsed == collapsed_column {
+                return i + 1;
+            }
+            collapsed += 1;
+        }
+        previous_whitespace = whitespace;
+    }
+    collapsed_column
+}
+
+fn split_number(text: &str) -> Option<(usize, &str)> {
+    let digits = text.bytes().take_while(|b| b.is_ascii_digit()).count();
+    Some((text[..digits].parse().ok()?, &text[digits..]))
+}
+
+/// Splits `error: message` or NVIDIA's `error C1008: message` into severity and message
+fn split_severity(text: &str) -> (Severity, &str) {
+    let lower = text.to_ascii_lowercase();
+    let severity = if lower.starts_with("error") {
+        Severity::Error
+    } else if lower.starts_with("warning") {
+        Severity::Warning
+    } else {
+        return (if lower.contains("error") { Severity::Error } else { Severity::Note }, text);
+    };
+
+    match text.split_once(':') {
+        Some((_, message)) => (severity, message.strip_prefix(' ').unwrap_or(message)),
+        None => (severity, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::Defines;
+    use crate::shader::preprocess::preprocess;
+
+    fn files(name: &str) -> Option<&'static str> {
+        match name {
+            "lib.glsl" => Some("float f() {\n    return x;\n}\n"),
+            _ => None
+        }
+    }
+
+    fn parse(log: &str) -> Vec<Diagnostic> {
+        let preprocessed = preprocess("main", "#include \"lib.glsl\"\nvoid main() {}\n", &Defines::new(), files).unwrap();
+        parse_log(log, Some(&preprocessed.source_map), &preprocessed.source)
+    }
+
+    fn location(diagnostic: &Diagnostic) -> (Severity, Option<&str>, Option<usize>, Option<usize>, &str) {
+        (diagnostic.severity, diagnostic.file.as_deref(), diagnostic.line, diagnostic.column, diagnostic.message.as_str())
+    }
+
+    #[test]
+    fn parses_mesa_logs() {
+        let diagnostics = parse("0:2(9): error: `x' undeclared\n0:4(1): warning: unused\n");
+        assert_eq!(location(&diagnostics[0]), (Severity::Error, Some("lib.glsl"), Some(2), Some(12), "`x' undeclared"));
+        assert_eq!(diagnostics[0].source_line.as_deref(), Some("    return x;"));
+        assert_eq!(location(&diagnostics[1]), (Severity::Warning, Some("main"), Some(2), Some(1), "unused"));
+    }
+
+    #[test]
+    fn parses_nvidia_logs() {
+        let diagnostics = parse("0(2) : error C1008: undefined variable \"x\"\n");
+        assert_eq!(location(&diagnostics[0]), (Severity::Error, Some("lib.glsl"), Some(2), None, "undefined variable \"x\""));
+    }
+
+    #[test]
+    fn parses_amd_logs() {
+        let diagnostics = parse("ERROR: 0:2: 'x' : undeclared identifier\nERROR: 1 compilation errors.  No code generated.\n");
+        assert_eq!(location(&diagnostics[0]), (Severity::Error, Some("lib.glsl"), Some(2), None, "'x' : undeclared identifier"));
+        assert_eq!(location(&diagnostics[1]), (Severity::Error, None, None, None, "1 compilation errors.  No code generated."));
+    }
+
+    #[test]
+    fn mesa_candidates_are_merged() {
+        let diagnostics = parse("0:2(2): error: no matching function for call to `f(int)'; candidates are:\n0:2(2): error:    float f()\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "no matching function for call to `f(int)'; candidates are:\n    float f()");
+        assert_eq!(diagnostics[0].column, Some(5));
+    }
+
+    #[test]
+    fn unlocated_and_continuation_lines() {
+        let diagnostics = parse("error: fragment shader lacks `main'\n    candidates are: f()\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(location(&diagnostics[0]), (Severity::Error, None, None, None, "fragment shader lacks `main'\n    candidates are: f()"));
+    }
+
+    #[test]
+    fn pretty_prints_source_snippet() {
+        let diagnostics = parse("0:2(9): error: `x' undeclared\n");
+        assert_eq!(diagnostics[0].to_string(), "error: `x' undeclared\n  --> lib.glsl:2:12\n  |\n2 |     return x;\n  |            ^");
+    }
+}
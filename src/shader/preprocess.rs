@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use crate::shader::{Defines, ShaderError};
+
+/// Shared GLSL files that shaders can `#include`, embedded in the binary
+const INCLUDES: &[(&str, &str)] = &[
+    ("common.glsl", include_str!("../shaders/include/common.glsl")),
+    ("colormap.glsl", include_str!("../shaders/include/colormap.glsl")),
+    ("frame.glsl", include_str!("../shaders/include/frame.glsl")),
+    ("simulation.glsl", include_str!("../shaders/include/simulation.glsl"))
+];
+
+/// Name used in the source map for the lines holding injected defines
+const DEFINES_FILE: &str = "<defines>";
+
+pub fn builtin_include(name: &str) -> Option<&'static str> {
+    INCLUDES.iter().find(|(include, _)| *include == name).map(|(_, source)| *source)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    /// 1-based, like the compiler's own line numbers
+    pub line: usize
+}
+
+/// Original file and line of every line in a preprocessed source
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    lines: Vec<SourceLocation>
+}
+
+impl SourceMap {
+    /// Looks up a 1-based line number of the preprocessed source
+    pub fn locate(&self, line: usize) -> Option<&SourceLocation> {
+        line.checked_sub(1).and_then(|index| self.lines.get(index))
+    }
+}
+
+pub struct Preprocessed {
+    pub source: String,
+    pub source_map: SourceMap
+}
+
+/// Injects `defines` after the `#version` line and resolves `#include "file.glsl"` directives through `resolve`.
+/// Included files containing `#pragma once` are only included the first time, and include cycles are an error.
+pub fn preprocess<'a>(name: &str, source: &str, defines: &Defines, resolve: impl Fn(&str) -> Option<&'a str>) -> Result<Preprocessed, ShaderError> {
+    let mut preprocessor = Preprocessor {
+        resolve,
+        output: Preprocessed { source: String::new(), source_map: SourceMap::default() },
+        stack: Vec::new(),
+        included_once: HashSet::new()
+    };
+
+    let (version_line, body, first_line) = match source.split_once('\n') {
+        Some((first, rest)) if first.trim_start().starts_with("#version") => (Some(first), rest, 2),
+        _ => (None, source, 1)
+    };
+
+    if let Some(version_line) = version_line {
+        preprocessor.push_line(version_line, name, 1);
+    }
+    for (i, (define, value)) in defines.iter().enumerate() {
+        preprocessor.push_line(&format!("#define {} {}", define, value), DEFINES_FILE, i + 1);
+    }
+    preprocessor.process(name, body, first_line)?;
+
+    Ok(preprocessor.output)
+}
+
+struct Preprocessor<'a, F: Fn(&str) -> Option<&'a str>> {
+    resolve: F,
+    output: Preprocessed,
+    /// Files currently being processed, to detect cycles
+    stack: Vec<String>,
+    included_once: HashSet<String>
+}
+
+impl<'a, F: Fn(&str) -> Option<&'a str>> Preprocessor<'a, F> {
+    fn push_line(&mut self, line: &str, file: &str, number: usize) {
+        self.output.source.push_str(line);
+        self.output.source.push('\n');
+        self.output.source_map.lines.push(SourceLocation { file: file.to_string(), line: number });
+    }
+
+    fn process(&mut self, file: &str, source: &str, first_line: usize) -> Result<(), ShaderError> {
+        self.stack.push(file.to_string());
+
+        for (i, line) in source.lines().enumerate() {
+            let number = first_line + i;
+            let directive = line.trim_start();
+
+            if directive.starts_with("#pragma") && directive.split_whitespace().nth(1) == Some("once") {
+                self.included_once.insert(file.to_string());
+                continue;
+            }
+
+            let Some(include) = directive.strip_prefix("#include") else {
+                self.push_line(line, file, number);
+                continue;
+            };
+
+            let include = include.trim();
+            let include_name = include.strip_prefix('"').and_then(|include| include.strip_suffix('"'))
+                .ok_or_else(|| ShaderError::InvalidInclude { file: file.to_string(), line: number })?;
+
+            if self.included_once.contains(include_name) {
+                continue;
+            }
+            if self.stack.iter().any(|open| open == include_name) {
+                let mut cycle = self.stack.clone();
+                cycle.push(include_name.to_string());
+                return Err(ShaderError::IncludeCycle(cycle));
+            }
+
+            let include_source = (self.resolve)(include_name).ok_or_else(|| ShaderError::IncludeNotFound {
+                name: include_name.to_string(),
+                file: file.to_string(),
+                line: number
+            })?;
+            self.process(include_name, include_source, 1)?;
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(name: &str) -> Option<&'static str> {
+        match name {
+            "a.glsl" => Some("#pragma once\nfloat a() { return 1.0; }\n"),
+            "b.glsl" => Some("#include \"a.glsl\"\nfloat b() { return a(); }\n"),
+            "loop.glsl" => Some("#include \"loop2.glsl\"\n"),
+            "loop2.glsl" => Some("#include \"loop.glsl\"\n"),
+            _ => None
+        }
+    }
+
+    #[test]
+    fn includes_are_inlined_once_and_mapped() {
+        let source = "#version 330\n#include \"a.glsl\"\n#include \"b.glsl\"\nvoid main() {}\n";
+        let preprocessed = preprocess("main", source, &Defines::from([("N".to_string(), "3".to_string())]), files).unwrap();
+
+        assert_eq!(preprocessed.source, "#version 330\n#define N 3\nfloat a() { return 1.0; }\nfloat b() { return a(); }\nvoid main() {}\n");
+        let locate = |line| preprocessed.source_map.locate(line).map(|location| (location.file.as_str(), location.line));
+        assert_eq!(locate(1), Some(("main", 1)));
+        assert_eq!(locate(2), Some((DEFINES_FILE, 1)));
+        assert_eq!(locate(3), Some(("a.glsl", 2)));
+        assert_eq!(locate(4), Some(("b.glsl", 2)));
+        assert_eq!(locate(5), Some(("main", 4)));
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        match preprocess("main", "#include \"loop.glsl\"\n", &Defines::new(), files) {
+            Err(ShaderError::IncludeCycle(cycle)) => assert_eq!(cycle, ["main", "loop.glsl", "loop2.glsl", "loop.glsl"]),
+            _ => panic!("expected an include cycle")
+        }
+    }
+
+    #[test]
+    fn missing_includes_are_errors() {
+        assert!(matches!(
+            preprocess("main", "\n#include \"missing.glsl\"\n", &Defines::new(), files),
+            Err(ShaderError::IncludeNotFound { line: 2, .. })
+        ));
+    }
+}
@@ -1,63 +1,141 @@
-use crate::shader::{Shader, ShaderError};
+use crate::shader::{Shader, ShaderError, info_log};
+use crate::shader::diagnostics::parse_log;
+use crate::uniform::gl_type_name;
 use gl::types::*;
+use std::collections::HashMap;
+
+/// A uniform the linker kept, as reported by `glGetActiveUniform`
+#[derive(Clone, Debug)]
+pub struct ActiveUniform {
+    pub location: GLint,
+    pub gl_type: GLenum,
+    /// Number of array elements, 1 for single values
+    pub size: usize
+}
 
 pub struct ShaderProgram {
     pub id: GLuint,
-    pub shaders: Vec<Shader>
+    pub shaders: Vec<Shader>,
+    pub active_uniforms: HashMap<String, ActiveUniform>
 }
 
 impl ShaderProgram {
+    /// # Safety
+    /// Needs the OpenGL context the shaders were compiled in to be current.
     pub unsafe fn new(shaders: Vec<Shader>) -> Result<Self, ShaderError> {
-        let program = Self {
+        let mut program = Self {
             id: gl::CreateProgram(),
-            shaders: shaders.clone()
+            shaders: shaders.clone(),
+            active_uniforms: HashMap::new()
         };
 
         for shader in shaders {
             gl::AttachShader(program.id, shader.id);
         }
 
-        println!("Linking shader program...");
-
         gl::LinkProgram(program.id);
 
         let mut success: GLint = 0;
         gl::GetProgramiv(program.id, gl::LINK_STATUS, &mut success);
 
         if success == 1 {
-            println!("Success");
+            program.active_uniforms = query_active_uniforms(program.id);
+            program.validate_uniforms()?;
+            for shader in &mut program.shaders {
+                shader.resolve_uniform_locations(&program.active_uniforms);
+            }
             Ok(program)
         } else {
-            let mut error_log_size: GLint = 0;
-            gl::GetProgramiv(program.id, gl::INFO_LOG_LENGTH, &mut error_log_size);
-            let mut error_log: Vec<u8> = Vec::with_capacity(error_log_size as usize);
-            gl::GetProgramInfoLog(
-                program.id,
-                error_log_size,
-                &mut error_log_size,
-                error_log.as_mut_ptr() as *mut _
-            );
-
-            error_log.set_len(error_log_size as usize);
-            let log = String::from_utf8(error_log)?;
-            println!("Error: {}", log);
-            Err(ShaderError::LinkingError(log))
+            let log = info_log(program.id, gl::GetProgramiv, gl::GetProgramInfoLog)?;
+            Err(ShaderError::LinkingError { diagnostics: parse_log(&log, None, ""), log })
+        }
+    }
+
+    /// Checks every uniform registered on the shaders against the program's active uniforms. Type mismatches are
+    /// errors. Uniforms the linker removed are only reported, since unused uniforms are optimized out.
+    fn validate_uniforms(&self) -> Result<(), ShaderError> {
+        for shader in &self.shaders {
+            for uniform in shader.uniforms() {
+                let name = uniform.key().to_string();
+                let value = uniform.value();
+
+                let Some(active) = self.active_uniforms.get(&name) else {
+                    log::warn!("Uniform {} of shader {} is not used by the program", name, shader.name);
+                    continue;
+                };
+                if !value.matches_gl_type(active.gl_type) {
+                    return Err(ShaderError::UniformTypeMismatch { name, expected: gl_type_name(active.gl_type), found: value.type_name().to_string() });
+                }
+                if value.len() > active.size {
+                    return Err(ShaderError::UniformArrayTooLong { name, size: active.size, len: value.len() });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Connects the std140 block `name` to a uniform buffer binding point. `size` is the size of the data uploaded
+    /// to the buffer, which has to match the layout the driver computed for the block.
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn bind_uniform_block(&self, name: &str, binding: GLuint, size: usize) -> Result<(), ShaderError> {
+        let c_name = std::ffi::CString::new(name)?;
+        let index = gl::GetUniformBlockIndex(self.id, c_name.as_ptr());
+        if index == gl::INVALID_INDEX {
+            return Err(ShaderError::UniformBlockNotFound(name.to_string()));
         }
+
+        let mut shader_size: GLint = 0;
+        gl::GetActiveUniformBlockiv(self.id, index, gl::UNIFORM_BLOCK_DATA_SIZE, &mut shader_size);
+        if shader_size as usize != size {
+            return Err(ShaderError::UniformBlockSizeMismatch { name: name.to_string(), shader_size: shader_size as usize, buffer_size: size });
+        }
+
+        gl::UniformBlockBinding(self.id, index, binding);
+        Ok(())
     }
 
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
     pub unsafe fn apply(&self) {
         gl::UseProgram(self.id);
     }
 
     pub fn get_shader(&mut self, name: String) -> Option<&mut Shader> {
-        for shader in &mut self.shaders {
-            if shader.name == name {
-                return Some(shader);
-            }
-        }
+        self.shaders.iter_mut().find(|shader| shader.name == name)
+    }
+}
+
+unsafe fn query_active_uniforms(program_id: GLuint) -> HashMap<String, ActiveUniform> {
+    let mut count: GLint = 0;
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORMS, &mut count);
+    let mut max_name_length: GLint = 0;
+    gl::GetProgramiv(program_id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_name_length);
+
+    let mut uniforms = HashMap::new();
+    for index in 0..count.max(0) as GLuint {
+        let mut name: Vec<u8> = vec![0; max_name_length.max(1) as usize];
+        let mut name_length: GLsizei = 0;
+        let mut size: GLint = 0;
+        let mut gl_type: GLenum = 0;
+        gl::GetActiveUniform(program_id, index, name.len() as GLsizei, &mut name_length, &mut size, &mut gl_type, name.as_mut_ptr() as *mut GLchar);
+        name.truncate(name_length.max(0) as usize);
 
-        None
+        //Arrays are reported by their first element, e.g. `u_tracer_data[0]`
+        let name = String::from_utf8_lossy(&name);
+        let name = name.strip_suffix("[0]").unwrap_or(&name).to_string();
+        let Ok(c_name) = std::ffi::CString::new(name.clone()) else {
+            continue;
+        };
+
+        //Uniforms in uniform blocks have no location and are skipped
+        let location = gl::GetUniformLocation(program_id, c_name.as_ptr());
+        if location != -1 {
+            uniforms.insert(name, ActiveUniform { location, gl_type, size: size.max(1) as usize });
+        }
     }
+    uniforms
 }
 
 impl Drop for ShaderProgram {
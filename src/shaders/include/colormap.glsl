@@ -0,0 +1,9 @@
+#pragma once
+
+#define COLORMAP_SIZE 256.0 //Number of entries in each colormap lookup texture
+
+vec3 colormap(sampler1D lut, float value) {
+    //Map 0-1 onto the texel centers so the ends of the gradient aren't blended with the clamped edge
+    float coord = (clamp(value, 0.0, 1.0) * (COLORMAP_SIZE - 1.0) + 0.5) / COLORMAP_SIZE;
+    return texture(lut, coord).rgb;
+}
@@ -0,0 +1,12 @@
+#pragma once
+
+//Values shared by every pass, uploaded once per frame. Must match FrameUniforms on the Rust side.
+layout(std140) uniform Frame {
+    vec2 u_resolution; //Framebuffer size in physical pixels
+    vec2 u_view_center; //World position at the center of the screen
+    float u_pixels_per_unit; //Physical pixels per world unit
+    float u_scale_factor; //Physical pixels per logical pixel
+    float u_time;
+    float u_dt;
+    int u_num_particles;
+};
@@ -0,0 +1,61 @@
+#pragma once
+
+//Parameters of a microstep. Must match SimulationParams on the Rust side.
+layout(std140) uniform SimulationParams {
+    vec2 u_mouse_position; //World coordinates
+    float u_dt;
+    float u_attractive_force;
+    float u_repulsive_force;
+    float u_drag;
+    float u_gravity_strength; //0 when gravity is off
+    float u_mouse_strength; //Negative when repelling, 0 when the mouse is inactive
+    int u_num_particles;
+    int u_domain_mode;
+    float u_mouse_radius; //World units
+};
+
+#define DOMAIN_WRAP 0
+#define DOMAIN_INFINITE 1
+#define DOMAIN_WALL 2
+
+#define EPSILON 0.000000001
+
+//Every buffer holds all x components, followed by all y components, like Simulation::pack
+layout(std430, binding = 0) buffer Positions { float positions[]; };
+layout(std430, binding = 1) buffer Velocities { float velocities[]; };
+//Acceleration of the previous microstep
+layout(std430, binding = 2) buffer Accelerations { float accelerations[]; };
+//Acceleration of the current microstep
+layout(std430, binding = 3) buffer NewAccelerations { float new_accelerations[]; };
+//1 for pinned particles, which don't move. One value per particle.
+layout(std430, binding = 4) buffer Pinned { uint pinned[]; };
+
+vec2 particle_position(uint i) {
+    return vec2(positions[i], positions[uint(u_num_particles) + i]);
+}
+
+vec2 lj_potential(vec2 r) {
+    vec2 r2 = r * r;
+    vec2 r6 = r2 * r2 * r2;
+    vec2 r12 = r6 * r6;
+    return 4.0 * (1.0 / (r12 + EPSILON) - 1.0 / (r6 + EPSILON));
+}
+
+vec2 isl_potential(vec2 r) {
+    return 1.0 / (r * r + EPSILON);
+}
+
+//Mouse force per unit of strength, pointing towards the cursor. Must match mouse_falloff on the Rust side.
+vec2 mouse_falloff(vec2 to_mouse) {
+    vec2 scaled = to_mouse / u_mouse_radius;
+    float scaled2 = dot(scaled, scaled);
+    if (scaled2 >= 1.0) {
+        return vec2(0.0);
+    }
+    return scaled * ((1.0 - scaled2) * (1.0 - scaled2));
+}
+
+//Points an acceleration computed from an absolute distance back along `distance`, per axis
+vec2 directed(vec2 acceleration, vec2 distance) {
+    return vec2(distance.x < 0.0 ? -acceleration.x : acceleration.x, distance.y < 0.0 ? -acceleration.y : acceleration.y);
+}
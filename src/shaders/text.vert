@@ -0,0 +1,16 @@
+#version 330
+
+#include "frame.glsl"
+
+layout (location = 0) in vec4 a_vertex; //xy: position in pixels from the top left, zw: atlas uv
+layout (location = 1) in vec4 a_color;
+
+out vec2 v_uv;
+out vec4 v_color;
+
+void main() {
+    vec2 ndc = (a_vertex.xy / u_resolution) * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    v_uv = a_vertex.zw;
+    v_color = a_color;
+}
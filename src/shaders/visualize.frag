@@ -1,36 +1,44 @@
-#define TWO_PI 6.28318530718
-#define NUM_TRACERS 200
-#define TRACER_SIZE 2 //Number of floats per tracer
-#define SIM_RESOLUTION 1000.0 //Grid size
-#define RESOLUTION 800.0 //Canvas size
+#version 330
+
+#include "common.glsl"
+#include "colormap.glsl"
+#include "frame.glsl"
 
+//Defaults for the constants the renderer injects from the scene
+#ifndef SIM_RESOLUTION
+#define SIM_RESOLUTION 1000.0 //Grid size
+#endif
+#ifndef BRIGHTNESS
 #define BRIGHTNESS 0.4
+#endif
+#ifndef SPREAD
 #define SPREAD 6.0
-#define CROSSHAIR_LINE_WIDTH 0.001
-#define CROSSHAIR_SIZE 0.02
-#define CROSSHAIR_GAP_SIZE 0.005
-
-//uniform float u_resolution;
-uniform float u_time;
-uniform float u_tracer_data[NUM_TRACERS * TRACER_SIZE];
-uniform vec2 u_mouse_position;
+#endif
+#ifndef CROSSHAIR_LINE_WIDTH
+#define CROSSHAIR_LINE_WIDTH 0.8 //Crosshair sizes are in logical pixels
+#endif
+#ifndef CROSSHAIR_SIZE
+#define CROSSHAIR_SIZE 16.0
+#endif
+#ifndef CROSSHAIR_GAP_SIZE
+#define CROSSHAIR_GAP_SIZE 4.0
+#endif
+
+uniform samplerBuffer u_tracers; //All x coordinates, followed by all y coordinates
+uniform samplerBuffer u_previous_tracers; //Positions before the latest step, in the same layout
+uniform float u_interpolation; //How far to move from the previous to the latest positions. 1 skips the previous ones.
+uniform vec2 u_mouse_position; //Physical pixels, origin in the top left
+uniform float u_mouse_radius; //Reach of the mouse force, in world units
 uniform bool u_mouse_active;
+uniform bool u_crosshair_visible;
 uniform bool u_mouse_attractive;
+uniform sampler1D u_colormap_a;
+uniform sampler1D u_colormap_b;
+uniform float u_colormap_mix;
 
-vec3 lightblue(float value) {
-    return mix(vec3(0.0, 0.222, 0.731), vec3(0.212, 0.625, 0.684), value);
-}
-
-vec3 sand(float value) {
-    float modded = smoothstep(0.8, 1.0, value);
-    return mix(vec3(0.773, 0.475, 0.428), vec3(0.858, 0.902, 0.865), modded);
-}
-
-vec3 purplered(float value) {
-    return mix(vec3(0.925, 0.4314, 0.678), vec3(0.204, 0.58, 0.9), value);
-}
+out vec4 frag_color;
 
-vec3 draw_crosshair(vec2 st, vec2 mouse_coords) {
+vec3 draw_crosshair(vec2 frag_coord, vec2 mouse_coords) {
     vec3 crosshair_color = vec3(0.0);
     if(u_mouse_attractive && u_mouse_active) {
         crosshair_color = vec3(1.0, 1.0, 0.0);
@@ -42,24 +50,44 @@ vec3 draw_crosshair(vec2 st, vec2 mouse_coords) {
         crosshair_color = vec3(0.0, 0.0, 1.0);
     }
 
-    if(abs(st.x - mouse_coords.x) < CROSSHAIR_GAP_SIZE && abs(st.y - mouse_coords.y) < CROSSHAIR_GAP_SIZE) {
+    vec2 offset = abs(frag_coord - mouse_coords) / u_scale_factor;
+    if(offset.x < CROSSHAIR_GAP_SIZE && offset.y < CROSSHAIR_GAP_SIZE) {
         return vec3(0.0);
     }
-    if(st.x >= mouse_coords.x - CROSSHAIR_LINE_WIDTH && st.x <= mouse_coords.x + CROSSHAIR_LINE_WIDTH && abs(st.y - mouse_coords.y) <= CROSSHAIR_SIZE) {
+    if(offset.x <= CROSSHAIR_LINE_WIDTH && offset.y <= CROSSHAIR_SIZE) {
+        return crosshair_color;
+    }
+    if(offset.y <= CROSSHAIR_LINE_WIDTH && offset.x <= CROSSHAIR_SIZE) {
         return crosshair_color;
     }
-    if(st.y >= mouse_coords.y - CROSSHAIR_LINE_WIDTH && st.y <= mouse_coords.y + CROSSHAIR_LINE_WIDTH && abs(st.x - mouse_coords.x) <= CROSSHAIR_SIZE) {
+    //Circle showing the reach of the mouse force
+    float radius = u_mouse_radius * u_pixels_per_unit / u_scale_factor;
+    if(abs(length(offset) - radius) <= CROSSHAIR_LINE_WIDTH / 2.0) {
         return crosshair_color;
     }
     return vec3(0.0);
 }
 
+//Tracer position between its previous and latest state. Tracers that wrapped around the domain are drawn at their
+//latest position instead of sweeping across it.
+vec2 tracer_position(int i) {
+    vec2 current = vec2(texelFetch(u_tracers, i).r, texelFetch(u_tracers, u_num_particles + i).r);
+    if(u_interpolation >= 1.0) {
+        return current;
+    }
+    vec2 previous = vec2(texelFetch(u_previous_tracers, i).r, texelFetch(u_previous_tracers, u_num_particles + i).r);
+    if(any(greaterThan(abs(current - previous), vec2(0.5)))) {
+        return current;
+    }
+    return mix(previous, current, u_interpolation);
+}
+
 void main() {
     float spread = SPREAD / 1000.0;
     float cell_size = 1.0 / SIM_RESOLUTION;
-    vec2 st = gl_FragCoord.xy / RESOLUTION;
-    vec2 mouse_coords = u_mouse_position / RESOLUTION;
-    mouse_coords.y = 1.0 - mouse_coords.y;
+    //World position of this fragment. Must match View::screen_to_world on the Rust side.
+    vec2 st = (gl_FragCoord.xy - u_resolution / 2.0) / u_pixels_per_unit + u_view_center;
+    vec2 mouse_coords = vec2(u_mouse_position.x, u_resolution.y - u_mouse_position.y);
 
     float density = 0.0;
     vec3 color = vec3(0.0);
@@ -74,10 +102,9 @@ void main() {
     //dist = 1.0 - dist; //Invert the distance so that it's 1 at the edge and 0 at the center
 
     //Iterate over each tracer
-    for (int i = 0; i < NUM_TRACERS * TRACER_SIZE; i += 2) {
+    for (int i = 0; i < u_num_particles; i++) {
         //Get tracer data
-        vec2 tracer_pos = vec2(u_tracer_data[i], u_tracer_data[i + 1]);;
-        //vec2 tracer_pos = vec2(u_tracer_data[0], u_tracer_data[1]);;
+        vec2 tracer_pos = tracer_position(i);
 
         //Calculate distance from point to tracer
         float dist = distance(uv, tracer_pos);
@@ -92,15 +119,15 @@ void main() {
         density += density_contrib * BRIGHTNESS;
     }
 
-    //Render the denisty as a heatmap
-    //color = lightblue(density);
-    //color = purplered(density);
-    color = lightblue(density);
+    //Render the denisty as a heatmap, crossfading between the two bound colormaps
+    color = mix(colormap(u_colormap_a, density), colormap(u_colormap_b, density), u_colormap_mix);
 
-    vec3 crosshair = draw_crosshair(st, mouse_coords);
-    if(crosshair != vec3(0.0)) {
-        color = crosshair;
+    if(u_crosshair_visible) {
+        vec3 crosshair = draw_crosshair(gl_FragCoord.xy, mouse_coords);
+        if(crosshair != vec3(0.0)) {
+            color = crosshair;
+        }
     }
 
-    gl_FragColor = vec4(color, 1.0);
+    frag_color = vec4(color, 1.0);
 }
\ No newline at end of file
@@ -1,10 +1,13 @@
-use std::time::SystemTime;
-use rand::prelude::ThreadRng;
-use rand::Rng;
-use crate::particle::Particle;
+use std::ops::RangeInclusive;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+use crate::particle::{Particle, Particles};
+use crate::snapshot::{MouseSnapshot, ParticleSnapshot, SimulationSnapshot, SNAPSHOT_VERSION, SnapshotError};
 extern crate nalgebra_glm as glm;
 
 #[derive(PartialEq)]
+#[allow(non_camel_case_types)]
 pub enum FORCE_TYPE {
     ISL,
     LJ,
@@ -13,24 +16,296 @@ pub enum FORCE_TYPE {
     GRAVITY
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(non_camel_case_types)]
 pub enum MOUSE_STATE {
     ATTRACTIVE,
     REPULSIVE
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(non_camel_case_types)]
 pub enum DOMAIN_MODE {
     WRAP,
     INFINITE,
     WALL
 }
 
+impl DOMAIN_MODE {
+    /// WRAP -> WALL -> INFINITE -> WRAP
+    pub fn next(&self) -> Self {
+        match self {
+            DOMAIN_MODE::WRAP => DOMAIN_MODE::WALL,
+            DOMAIN_MODE::WALL => DOMAIN_MODE::INFINITE,
+            DOMAIN_MODE::INFINITE => DOMAIN_MODE::WRAP
+        }
+    }
+}
+
+/// How particle positions are chosen when the simulation starts
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "distribution", rename_all = "lowercase", deny_unknown_fields)]
+#[allow(non_camel_case_types)]
+pub enum SPAWN_DISTRIBUTION {
+    /// Uniformly random angle, with the distance from `center` uniformly between the two radii
+    RING { center: [f32; 2], inner_radius: f32, outer_radius: f32 },
+    /// Uniformly random within a circle
+    DISK { center: [f32; 2], radius: f32 },
+    /// Uniformly random within a rectangle
+    UNIFORM { min: [f32; 2], max: [f32; 2] },
+    /// Normally distributed around `center`
+    GAUSSIAN { center: [f32; 2], std_dev: f32 }
+}
+
+impl Default for SPAWN_DISTRIBUTION {
+    fn default() -> Self {
+        SPAWN_DISTRIBUTION::RING { center: [0.5, 0.5], inner_radius: 0.3, outer_radius: 0.35 }
+    }
+}
+
+impl SPAWN_DISTRIBUTION {
+    pub fn sample(&self, rng: &mut impl Rng) -> glm::Vec2 {
+        match self {
+            SPAWN_DISTRIBUTION::RING { center, inner_radius, outer_radius } => {
+                let theta = rng.gen_range(0.0..(2.0 * std::f32::consts::PI));
+                let r = if outer_radius > inner_radius { rng.gen_range(*inner_radius..*outer_radius) } else { *inner_radius };
+                glm::vec2(center[0] + r * theta.cos(), center[1] + r * theta.sin())
+            },
+            SPAWN_DISTRIBUTION::DISK { center, radius } => {
+                //sqrt keeps the density uniform over the area instead of bunching up in the middle
+                let theta = rng.gen_range(0.0..(2.0 * std::f32::consts::PI));
+                let r = radius * rng.gen::<f32>().sqrt();
+                glm::vec2(center[0] + r * theta.cos(), center[1] + r * theta.sin())
+            },
+            SPAWN_DISTRIBUTION::UNIFORM { min, max } => {
+                glm::vec2(
+                    min[0] + (max[0] - min[0]) * rng.gen::<f32>(),
+                    min[1] + (max[1] - min[1]) * rng.gen::<f32>()
+                )
+            },
+            SPAWN_DISTRIBUTION::GAUSSIAN { center, std_dev } => {
+                //Box-Muller transform
+                let u1: f32 = 1.0 - rng.gen::<f32>();
+                let u2: f32 = rng.gen();
+                let r = (-2.0 * u1.ln()).sqrt() * std_dev;
+                let theta = 2.0 * std::f32::consts::PI * u2;
+                glm::vec2(center[0] + r * theta.cos(), center[1] + r * theta.sin())
+            }
+        }
+    }
+}
+
+/// Everything needed to construct a `Simulation`. This is the `[simulation]` table of a scene file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    pub dt: f32,
+    pub attractive_force: f32,
+    pub repulsive_force: f32,
+    pub drag: f32,
+    pub max_spawn_velocity: f32,
+    pub num_particles: i32,
+    pub microsteps: i32,
+    pub gravity: bool,
+    pub gravity_strength: f32,
+    pub mouse_strength: f32,
+    /// Distance from the cursor at which the mouse force fades out, in world units
+    pub mouse_radius: f32,
+    pub domain_mode: DOMAIN_MODE,
+    /// Seed for the initial particle state. A random seed is used when this is missing.
+    pub seed: Option<u64>,
+    pub spawn: SPAWN_DISTRIBUTION,
+    /// Compute forces on all cores. Only has an effect when built with the `parallel` feature.
+    pub parallel: bool,
+    /// Keep parallel results bit for bit identical to the single threaded ones
+    pub deterministic: bool
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            dt: 0.01 / 1000.0,
+            attractive_force: 0.001,
+            repulsive_force: 0.000002,
+            drag: 0.5,
+            max_spawn_velocity: 10.0,
+            num_particles: 200,
+            microsteps: 1,
+            gravity: false,
+            gravity_strength: 10000000.0,
+            mouse_strength: 10000000.0,
+            mouse_radius: 0.2,
+            domain_mode: DOMAIN_MODE::WRAP,
+            seed: None,
+            spawn: SPAWN_DISTRIBUTION::default(),
+            parallel: true,
+            deterministic: true
+        }
+    }
+}
+
 const EPSILON: f32 = 0.000000001;
+/// Mouse radii `set_mouse_size` allows. The mouse falloff divides by the radius.
+pub const MOUSE_RADIUS_RANGE: RangeInclusive<f32> = 0.001..=10.0;
+/// Magnitudes of the mouse strength `set_mouse_size` allows, so scrolling can't reach 0 or overflow
+pub const MOUSE_STRENGTH_RANGE: RangeInclusive<f32> = 1.0..=1e15;
+
+/// Number of independent partial sums in the vectorizable pair kernel
+const LANES: usize = 8;
+
+fn lj_potential(r: f32) -> f32 {
+    let r6 = r.powi(6);
+    let r12 = r6.powi(2);
+    4.0 * (1.0 / (r12 + EPSILON) - 1.0 / (r6 + EPSILON))
+}
+
+fn isl_potential(r: f32) -> f32 {
+    1.0 / (r.powi(2) + EPSILON)
+}
+
+/// Points an acceleration computed from an absolute distance back along `distance`
+#[inline(always)]
+fn directed(acceleration: f32, distance: f32) -> f32 {
+    if distance < 0.0 { -acceleration } else { acceleration }
+}
+
+/// Mouse force per unit of strength on a particle `to_mouse` away from the cursor. It points towards the cursor and
+/// falls off smoothly: 0 at the cursor, strongest at about half the radius and 0 again from `radius` on.
+#[inline(always)]
+fn mouse_falloff(to_mouse: glm::Vec2, radius: f32) -> glm::Vec2 {
+    let scaled = to_mouse / radius;
+    let scaled2 = glm::dot(&scaled, &scaled);
+    if scaled2 >= 1.0 {
+        return glm::vec2(0.0, 0.0);
+    }
+    scaled * (1.0 - scaled2).powi(2)
+}
+
+/// Inverse-square attraction along one axis towards a particle `distance` away
+#[inline(always)]
+fn isl_term(force: f32, distance: f32) -> f32 {
+    directed(force * -isl_potential(distance), distance)
+}
+
+/// Lennard-Jones style repulsion along one axis from a particle `distance` away
+#[inline(always)]
+fn lj_term(force: f32, distance: f32) -> f32 {
+    directed(force * lj_potential(distance), distance)
+}
+
+/// Adds the pair force from every other particle to `sum`, one at a time in index order.
+/// The forces are separable, so each axis only needs the coordinates along it, but both axes are summed in the
+/// same loop.
+#[inline(always)]
+fn pair_sum_ordered(term: impl Fn(f32, f32) -> f32, force: f32, x: &[f32], y: &[f32], i: usize, mut sum: glm::Vec2) -> glm::Vec2 {
+    let own = glm::vec2(x[i], y[i]);
+    for (others_x, others_y) in [(&x[..i], &y[..i]), (&x[i + 1..], &y[i + 1..])] {
+        for (other_x, other_y) in others_x.iter().zip(others_y) {
+            sum.x += term(force, other_x - own.x);
+            sum.y += term(force, other_y - own.y);
+        }
+    }
+    sum
+}
+
+/// Sum of both pair forces from every other particle, accumulated in `LANES` partial sums so the loop vectorizes
+fn pair_sum_lanes(attractive_force: f32, repulsive_force: f32, coordinates: &[f32], i: usize) -> f32 {
+    let own = coordinates[i];
+    let mut lanes = [0.0f32; LANES];
+    let mut rest = 0.0;
+    for others in [&coordinates[..i], &coordinates[i + 1..]] {
+        let chunks = others.chunks_exact(LANES);
+        for other in chunks.remainder() {
+            let distance = other - own;
+            rest += isl_term(attractive_force, distance) + lj_term(repulsive_force, distance);
+        }
+        for chunk in chunks {
+            for lane in 0..LANES {
+                let distance = chunk[lane] - own;
+                lanes[lane] += isl_term(attractive_force, distance) + lj_term(repulsive_force, distance);
+            }
+        }
+    }
+    lanes.iter().sum::<f32>() + rest
+}
+
+/// Pcg64 that counts the numbers drawn from it, so its state can be saved as the seed and the count
+#[derive(Clone, Debug)]
+struct SeededRng {
+    rng: Pcg64,
+    draws: u64
+}
 
+impl SeededRng {
+    fn new(seed: u64) -> Self {
+        Self { rng: Pcg64::seed_from_u64(seed), draws: 0 }
+    }
+
+    /// The generator seeded with `seed` after `draws` numbers, found without drawing them
+    fn restore(seed: u64, draws: u64) -> Self {
+        let mut rng = Pcg64::seed_from_u64(seed);
+        rng.advance(draws as u128);
+        Self { rng, draws }
+    }
+}
+
+impl RngCore for SeededRng {
+    //Every draw of Pcg64 is one step of its state, however many bits are used
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Initial particles drawn from the spawn settings of `config`
+fn spawn_particles(config: &SimulationConfig, rng: &mut impl Rng) -> Particles {
+    let max_spawn_velocity = config.max_spawn_velocity;
+    (0..config.num_particles).map(|_| {
+        let position = config.spawn.sample(rng);
+        let velocity = if max_spawn_velocity > 0.0 {
+            glm::vec2(
+                rng.gen_range(-max_spawn_velocity..max_spawn_velocity),
+                rng.gen_range(-max_spawn_velocity..max_spawn_velocity)
+            )
+        } else {
+            glm::vec2(0.0, 0.0)
+        };
+        Particle::new(position, velocity)
+    }).collect()
+}
+
+/// A particle held by `Simulation::grab`
+#[derive(Clone, Debug)]
+struct Grabbed {
+    index: usize,
+    /// Position relative to the cursor when it was grabbed
+    offset: glm::Vec2,
+    /// Whether it was pinned before, so releasing it restores that
+    was_pinned: bool
+}
+
+#[derive(Clone)]
 pub struct Simulation {
-    pub particles: Vec<Particle>,
-    pub t_start: f64,
+    pub particles: Particles,
+    /// Simulated time, `dt` per microstep since the simulation started
     pub t: f64,
     pub dt: f32,
     pub attractive_force: f32,
@@ -40,169 +315,360 @@ pub struct Simulation {
     mouse_position: glm::Vec2,
     pub mouse_state: MOUSE_STATE,
     pub mouse_active: bool,
-    gravity: bool
+    pub gravity: bool,
+    pub gravity_strength: f32,
+    pub mouse_strength: f32,
+    pub mouse_radius: f32,
+    pub domain_mode: DOMAIN_MODE,
+    pub seed: u64,
+    pub parallel: bool,
+    pub deterministic: bool,
+    rng: SeededRng,
+    config: SimulationConfig,
+    /// Particles held by `grab`
+    grabbed: Vec<Grabbed>,
+    /// Scratch buffer the parallel force evaluation writes into
+    #[cfg(feature = "parallel")]
+    accelerations: Vec<glm::Vec2>
 }
 
 impl Default for Simulation {
     fn default() -> Self {
-        Self::new(
-            0.01 / 1000.0,
-            0.001,
-            0.000002,
-            0.5,
-            10.0,
-            200,
-            1,
-            false
-        )
+        Self::from_config(&SimulationConfig::default())
     }
 }
 
 impl Simulation {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(dt: f32, attractive_force: f32, repulsive_force: f32, drag: f32, max_spawn_velocity: f32, num_particles: i32, microsteps: i32, gravity: bool) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut initial_state = Vec::new();
-        for _ in 0..num_particles {
-            initial_state.push(Particle::new(
-                Simulation::rand_coord(rng.clone()),
-                glm::vec2(
-                    rng.gen_range(-max_spawn_velocity..max_spawn_velocity),
-                    rng.gen_range(-max_spawn_velocity..max_spawn_velocity)
-                ),
-                DOMAIN_MODE::WRAP
-            ));
-        }
-
-        Self {
-            particles: initial_state,
-            t: 0.0,
-            t_start: (SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as f64) / 1000.0,
+        Self::from_config(&SimulationConfig {
             dt,
             attractive_force,
             repulsive_force,
             drag,
+            max_spawn_velocity,
+            num_particles,
             microsteps,
+            gravity,
+            ..SimulationConfig::default()
+        })
+    }
+
+    pub fn from_config(config: &SimulationConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut rng = SeededRng::new(seed);
+        let particles = spawn_particles(config, &mut rng);
+
+        Self {
+            particles,
+            t: 0.0,
+            dt: config.dt,
+            attractive_force: config.attractive_force,
+            repulsive_force: config.repulsive_force,
+            drag: config.drag,
+            microsteps: config.microsteps,
             mouse_position: glm::vec2(0.0, 0.0),
             mouse_state: MOUSE_STATE::ATTRACTIVE,
             mouse_active: false,
-            gravity
+            gravity: config.gravity,
+            gravity_strength: config.gravity_strength,
+            mouse_strength: config.mouse_strength,
+            mouse_radius: config.mouse_radius,
+            domain_mode: config.domain_mode.clone(),
+            seed,
+            parallel: config.parallel,
+            deterministic: config.deterministic,
+            rng,
+            config: config.clone(),
+            grabbed: Vec::new(),
+            #[cfg(feature = "parallel")]
+            accelerations: Vec::new()
         }
     }
 
+    /// Respawns the particles from the seed and restarts the clock. Forces, gravity, the domain and the mouse
+    /// keep their current settings.
+    pub fn reset(&mut self) {
+        let mut rng = SeededRng::new(self.seed);
+        self.particles = spawn_particles(&self.config, &mut rng);
+        self.rng = rng;
+        self.grabbed.clear();
+        self.t = 0.0;
+    }
+
+    /// Like `reset`, with a different seed
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.reset();
+    }
+
+    /// Everything needed to resume the simulation exactly with `from_snapshot`
+    pub fn snapshot(&self) -> SimulationSnapshot {
+        let particles = &self.particles;
+        let (ax, ay) = particles.accelerations();
+        let mut pinned = particles.pinned().to_vec();
+        for grabbed in &self.grabbed {
+            pinned[grabbed.index] = grabbed.was_pinned;
+        }
+        SimulationSnapshot {
+            version: SNAPSHOT_VERSION,
+            t: self.t,
+            dt: self.dt,
+            attractive_force: self.attractive_force,
+            repulsive_force: self.repulsive_force,
+            drag: self.drag,
+            microsteps: self.microsteps,
+            gravity: self.gravity,
+            gravity_strength: self.gravity_strength,
+            domain_mode: self.domain_mode.clone(),
+            seed: self.seed,
+            rng_draws: self.rng.draws,
+            parallel: self.parallel,
+            deterministic: self.deterministic,
+            mouse: MouseSnapshot {
+                position: [self.mouse_position.x, self.mouse_position.y],
+                state: self.mouse_state.clone(),
+                active: self.mouse_active,
+                strength: self.mouse_strength,
+                radius: self.mouse_radius
+            },
+            //`reset` uses the seed saved above, and TOML can't hold every u64 the config might
+            config: SimulationConfig { seed: None, ..self.config.clone() },
+            particles: particles.iter().enumerate().map(|(i, particle)| ParticleSnapshot {
+                position: [particle.position.x, particle.position.y],
+                velocity: [particle.velocity.x, particle.velocity.y],
+                acceleration: [ax[i], ay[i]],
+                pinned: pinned[i]
+            }).collect()
+        }
+    }
+
+    /// Restores a simulation saved with `snapshot`, after checking its values. The clock continues from the saved time.
+    pub fn from_snapshot(snapshot: &SimulationSnapshot) -> Result<Self, SnapshotError> {
+        let problems = snapshot.problems();
+        if !problems.is_empty() {
+            return Err(SnapshotError::Invalid { problems });
+        }
+
+        let mut particles: Particles = snapshot.particles.iter()
+            .map(|particle| Particle::new(glm::make_vec2(&particle.position), glm::make_vec2(&particle.velocity)))
+            .collect();
+        let (ax, ay) = particles.accelerations_mut();
+        for (i, particle) in snapshot.particles.iter().enumerate() {
+            ax[i] = particle.acceleration[0];
+            ay[i] = particle.acceleration[1];
+        }
+        for (i, particle) in snapshot.particles.iter().enumerate() {
+            //Pinning stops the particle, so the saved velocity goes back in afterwards
+            particles.set_pinned(i, particle.pinned);
+            particles.set_velocity(i, glm::make_vec2(&particle.velocity));
+        }
+
+        let mouse = &snapshot.mouse;
+        Ok(Self {
+            particles,
+            t: snapshot.t,
+            dt: snapshot.dt,
+            attractive_force: snapshot.attractive_force,
+            repulsive_force: snapshot.repulsive_force,
+            drag: snapshot.drag,
+            microsteps: snapshot.microsteps,
+            mouse_position: glm::make_vec2(&mouse.position),
+            mouse_state: mouse.state.clone(),
+            mouse_active: mouse.active,
+            gravity: snapshot.gravity,
+            gravity_strength: snapshot.gravity_strength,
+            mouse_strength: mouse.strength,
+            mouse_radius: mouse.radius,
+            domain_mode: snapshot.domain_mode.clone(),
+            seed: snapshot.seed,
+            parallel: snapshot.parallel,
+            deterministic: snapshot.deterministic,
+            rng: SeededRng::restore(snapshot.seed, snapshot.rng_draws),
+            config: snapshot.config.clone(),
+            grabbed: Vec::new(),
+            #[cfg(feature = "parallel")]
+            accelerations: Vec::new()
+        })
+    }
+
+    /// The config the simulation was created from
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    /// Takes the cursor position in world coordinates
     pub fn set_mouse_position(&mut self, x: f32, y: f32) {
         self.mouse_position = glm::vec2(x, y);
     }
 
-    pub fn on_mouse_click(&mut self) {
-        self.mouse_active = !self.mouse_active;
+    pub fn mouse_position(&self) -> glm::Vec2 {
+        self.mouse_position
     }
 
-    pub fn next_mouse_mode(&mut self) {
-        self.mouse_state = if self.mouse_state == MOUSE_STATE::ATTRACTIVE { MOUSE_STATE::REPULSIVE } else { MOUSE_STATE::ATTRACTIVE };
+    /// Starts the mouse force in the given mode, or stops it with None
+    pub fn set_mouse_force(&mut self, force: Option<MOUSE_STATE>) {
+        self.mouse_active = force.is_some();
+        if let Some(state) = force {
+            self.mouse_state = state;
+        }
     }
 
-    fn remap_trig(value: f32) -> f32 {
-        (value + 1.0) / 2.0
+    /// Sets the radius and strength of the mouse force, clamped to `MOUSE_RADIUS_RANGE` and `MOUSE_STRENGTH_RANGE`.
+    /// The strength keeps its sign.
+    pub fn set_mouse_size(&mut self, radius: f32, strength: f32) {
+        self.mouse_radius = radius.clamp(*MOUSE_RADIUS_RANGE.start(), *MOUSE_RADIUS_RANGE.end());
+        self.mouse_strength = strength.signum() * strength.abs().clamp(*MOUSE_STRENGTH_RANGE.start(), *MOUSE_STRENGTH_RANGE.end());
     }
 
-    pub fn rand_coord(mut rng: ThreadRng) -> glm::Vec2 {
-        let theta = rng.gen_range(0.0..(2.0 * std::f32::consts::PI));
-        let max_r = 0.7;
-        let shift = (1.0 - max_r) / 2.0;
-        let r = rng.gen_range(0.6..max_r);
-        glm::vec2(
-            (Simulation::remap_trig(theta.cos()) * r) + shift,
-            (Simulation::remap_trig(theta.sin()) * r) + shift
-        )
+    /// Adds `count` particles at uniformly random points within `radius` of `center`. Each moves at `velocity`
+    /// plus a random velocity of up to `jitter` in a random direction.
+    pub fn spawn_in(&mut self, center: glm::Vec2, radius: f32, count: usize, velocity: glm::Vec2, jitter: f32) {
+        let disk = SPAWN_DISTRIBUTION::DISK { center: [center.x, center.y], radius };
+        let rng = &mut self.rng;
+        let particles: Vec<Particle> = (0..count).map(|_| {
+            let position = disk.sample(rng);
+            let angle = rng.gen_range(0.0..(2.0 * std::f32::consts::PI));
+            let speed = jitter * rng.gen::<f32>();
+            Particle::new(position, velocity + glm::vec2(speed * angle.cos(), speed * angle.sin()))
+        }).collect();
+        self.particles.extend(particles);
     }
 
-    pub fn apply_force(&mut self, force: f32, force_type: FORCE_TYPE) {
-        for i in 0..self.particles.len() {
-            if force_type == FORCE_TYPE::PROPORTIONAL {
-                let vx_sign = self.particles[i].velocity.x.signum();
-                let vy_sign = self.particles[i].velocity.y.signum();
+    /// Deletes the particles within `radius` of `center` and returns how many there were. Ends a grab.
+    pub fn erase_in(&mut self, center: glm::Vec2, radius: f32) -> usize {
+        self.release_grab();
+        self.particles.remove_where(|_, particle| glm::distance2(&particle.position, &center) < radius * radius)
+    }
 
-                self.particles[i].new_acceleration.x -= vx_sign * self.drag * self.particles[i].velocity.x.powi(2);
-                self.particles[i].new_acceleration.y -= vy_sign * self.drag * self.particles[i].velocity.y.powi(2);
-                continue;
-            }
+    /// Pins or releases the particles within `radius` of `center` and returns how many there were
+    pub fn pin_in(&mut self, center: glm::Vec2, radius: f32, pinned: bool) -> usize {
+        let indices = self.particles.within(center, radius);
+        for i in &indices {
+            self.particles.set_pinned(*i, pinned);
+        }
+        indices.len()
+    }
 
-            if force_type == FORCE_TYPE::GRAVITY {
-                self.particles[i].new_acceleration.y -= force;
-                continue;
-            }
+    /// Releases every pinned particle. Grabbed particles stay held, and are free once the grab ends.
+    pub fn unpin_all(&mut self) {
+        for i in 0..self.particles.len() {
+            self.particles.set_pinned(i, false);
+        }
+        for grabbed in &mut self.grabbed {
+            grabbed.was_pinned = false;
+            self.particles.set_pinned(grabbed.index, true);
+        }
+    }
 
-            if force_type == FORCE_TYPE::MOUSE {
-                if !self.mouse_active {
-                    continue;
-                }
+    /// Holds the particles within `radius` of `center`, so `drag_to` moves them along. They are pinned until
+    /// `release_grab`. Returns the number of particles grabbed.
+    pub fn grab(&mut self, center: glm::Vec2, radius: f32) -> usize {
+        self.release_grab();
+        for i in self.particles.within(center, radius) {
+            self.grabbed.push(Grabbed { index: i, offset: self.particles.position(i) - center, was_pinned: self.particles.is_pinned(i) });
+            self.particles.set_pinned(i, true);
+        }
+        self.grabbed.len()
+    }
 
-                let distance = (self.mouse_position / 800.0) - self.particles[i].position;
-                let mut potential = glm::vec2(0.0, 0.0);
-                potential.x = if self.mouse_state == MOUSE_STATE::REPULSIVE { -1.0 } else { 1.0 } * self.lj_potential(distance.x);
-                potential.y = if self.mouse_state == MOUSE_STATE::REPULSIVE { -1.0 } else { 1.0 } * self.lj_potential(distance.y);
+    /// Moves the grabbed particles so they keep their offsets from `center`
+    pub fn drag_to(&mut self, center: glm::Vec2) {
+        for grabbed in &self.grabbed {
+            self.particles.set_position(grabbed.index, center + grabbed.offset);
+        }
+    }
 
-                let mut a_x = force * potential.x;
-                let mut a_y = force * potential.y;
+    /// Lets go of the grabbed particles. Particles that were pinned before the grab stay pinned.
+    pub fn release_grab(&mut self) {
+        for grabbed in self.grabbed.drain(..) {
+            self.particles.set_pinned(grabbed.index, grabbed.was_pinned);
+        }
+    }
 
-                if distance.x < 0.0 {
-                    a_x *= -1.0;
-                }
+    /// Adds a single force to the acceleration of every particle
+    pub fn apply_force(&mut self, force: f32, force_type: FORCE_TYPE) {
+        for i in 0..self.particles.len() {
+            let acceleration = self.accumulate_force(i, force, &force_type, self.particles.new_acceleration(i));
+            self.particles.set_new_acceleration(i, acceleration);
+        }
+    }
 
-                if distance.y < 0.0 {
-                    a_y *= -1.0;
+    /// Adds one force acting on particle `i` to `acceleration`. Pair forces are added one particle at a time,
+    /// in index order.
+    fn accumulate_force(&self, i: usize, force: f32, force_type: &FORCE_TYPE, mut acceleration: glm::Vec2) -> glm::Vec2 {
+        let particles = &self.particles;
+        match force_type {
+            FORCE_TYPE::ISL => {
+                acceleration = pair_sum_ordered(isl_term, force, particles.x(), particles.y(), i, acceleration);
+            },
+            FORCE_TYPE::LJ => {
+                acceleration = pair_sum_ordered(lj_term, force, particles.x(), particles.y(), i, acceleration);
+            },
+            FORCE_TYPE::GRAVITY => {
+                acceleration.y -= force;
+            },
+            FORCE_TYPE::PROPORTIONAL => {
+                let velocity = particles.velocity(i);
+                acceleration.x -= velocity.x.signum() * self.drag * velocity.x.powi(2);
+                acceleration.y -= velocity.y.signum() * self.drag * velocity.y.powi(2);
+            },
+            FORCE_TYPE::MOUSE => {
+                if self.mouse_active {
+                    let sign = if self.mouse_state == MOUSE_STATE::REPULSIVE { -1.0 } else { 1.0 };
+                    acceleration += (sign * force) * mouse_falloff(self.mouse_position - particles.position(i), self.mouse_radius);
                 }
-
-                self.particles[i].new_acceleration.x += a_x;
-                self.particles[i].new_acceleration.y += a_y;
-                continue;
             }
+        }
+        acceleration
+    }
 
-            for j in 0..self.particles.len() {
-                if i != j {
-                    let distance = self.particles[j].position - self.particles[i].position;
-                    let mut potential = glm::vec2(0.0, 0.0);
-
-                    match force_type {
-                        FORCE_TYPE::ISL => {
-                            potential.x = -1.0 * self.isl_potential(distance.x);
-                            potential.y = -1.0 * self.isl_potential(distance.y);
-                        },
-                        FORCE_TYPE::LJ => {
-                            potential.x = self.lj_potential(distance.x);
-                            potential.y = self.lj_potential(distance.y);
-                        },
-                        _ => {}
-                    }
-
-                    // Avoid division by zero by adding a small epsilon
-                    let mut a_x = force * potential.x;
-                    let mut a_y = force * potential.y;
-
-                    if distance.x < 0.0 {
-                        a_x *= -1.0;
-                    }
-
-                    if distance.y < 0.0 {
-                        a_y *= -1.0;
-                    }
-
-                    // Update acceleration
-                    self.particles[i].new_acceleration.x += a_x;
-                    self.particles[i].new_acceleration.y += a_y;
-                }
-            }
+    /// New acceleration of particle `i` after every force has been applied. In deterministic mode the terms are
+    /// summed in the same order as applying each force with `apply_force`, so the result is bit for bit the same.
+    /// Otherwise both pair forces are summed in one pass with several independent partial sums, which the compiler
+    /// can vectorize but which rounds differently.
+    fn total_acceleration(&self, i: usize) -> glm::Vec2 {
+        let mut acceleration = self.particles.new_acceleration(i);
+
+        if self.deterministic {
+            acceleration = self.accumulate_force(i, self.attractive_force, &FORCE_TYPE::ISL, acceleration);
+            acceleration = self.accumulate_force(i, self.repulsive_force, &FORCE_TYPE::LJ, acceleration);
+        } else {
+            let particles = &self.particles;
+            acceleration.x += pair_sum_lanes(self.attractive_force, self.repulsive_force, particles.x(), i);
+            acceleration.y += pair_sum_lanes(self.attractive_force, self.repulsive_force, particles.y(), i);
         }
+
+        if self.gravity {
+            acceleration = self.accumulate_force(i, self.gravity_strength, &FORCE_TYPE::GRAVITY, acceleration);
+        }
+        acceleration = self.accumulate_force(i, self.drag, &FORCE_TYPE::PROPORTIONAL, acceleration);
+        self.accumulate_force(i, self.mouse_strength, &FORCE_TYPE::MOUSE, acceleration)
     }
 
-    fn lj_potential(&self, r: f32) -> f32 {
-        let r6 = r.powi(6);
-        let r12 = r6.powi(2);
-        4.0 * (1.0 / (r12 + EPSILON) - 1.0 / (r6 + EPSILON))
+    /// Applies every force of a microstep, one particle at a time
+    fn apply_forces_serial(&mut self) {
+        for i in 0..self.particles.len() {
+            let acceleration = self.total_acceleration(i);
+            self.particles.set_new_acceleration(i, acceleration);
+        }
     }
 
-    fn isl_potential(&self, r: f32) -> f32 {
-        1.0 / (r.powi(2) + EPSILON)
+    /// Computes the new acceleration of every particle on the rayon thread pool. The results go into a separate
+    /// buffer while the particles are only read, and are copied back afterwards.
+    #[cfg(feature = "parallel")]
+    fn apply_forces_parallel(&mut self) {
+        use rayon::prelude::*;
+
+        let mut accelerations = std::mem::take(&mut self.accelerations);
+        (0..self.particles.len()).into_par_iter()
+            .map(|i| self.total_acceleration(i))
+            .collect_into_vec(&mut accelerations);
+
+        for (i, acceleration) in accelerations.iter().enumerate() {
+            self.particles.set_new_acceleration(i, *acceleration);
+        }
+        self.accelerations = accelerations;
     }
 
     pub fn step(&mut self) {
@@ -212,26 +678,207 @@ impl Simulation {
     }
 
     fn microstep(&mut self) {
-        self.t = (SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as f64 / 1000.0) - self.t_start;
+        #[cfg(feature = "parallel")]
+        if self.parallel {
+            self.apply_forces_parallel();
+        } else {
+            self.apply_forces_serial();
+        }
+        #[cfg(not(feature = "parallel"))]
+        self.apply_forces_serial();
 
-        self.apply_force(self.attractive_force, FORCE_TYPE::ISL);
-        self.apply_force(self.repulsive_force, FORCE_TYPE::LJ);
+        self.particles.integrate(self.dt, &self.domain_mode);
+        self.t += self.dt as f64;
+    }
 
-        if self.gravity {
-            self.apply_force(10000000.0, FORCE_TYPE::GRAVITY);
+    /// Every x coordinate and every y coordinate, without copying. Renderers store them one after the other.
+    pub fn pack(&self) -> [&[f32]; 2] {
+        [self.particles.x(), self.particles.y()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SimulationConfig {
+        SimulationConfig { num_particles: 64, seed: Some(7), gravity: true, gravity_strength: 10.0, ..SimulationConfig::default() }
+    }
+
+    fn state(simulation: &Simulation) -> Vec<[u32; 4]> {
+        simulation.particles.iter()
+            .map(|p| [p.position.x.to_bits(), p.position.y.to_bits(), p.velocity.x.to_bits(), p.velocity.y.to_bits()])
+            .collect()
+    }
+
+    fn with_mouse(mut simulation: Simulation) -> Simulation {
+        simulation.set_mouse_position(0.4, 0.6);
+        simulation.set_mouse_force(Some(MOUSE_STATE::ATTRACTIVE));
+        simulation
+    }
+
+    #[test]
+    fn fused_forces_match_separate_passes() {
+        let mut fused = with_mouse(Simulation::from_config(&SimulationConfig { parallel: false, ..config() }));
+        let mut passes = with_mouse(Simulation::from_config(&config()));
+
+        for step in 0..20 {
+            fused.step();
+
+            passes.apply_force(passes.attractive_force, FORCE_TYPE::ISL);
+            passes.apply_force(passes.repulsive_force, FORCE_TYPE::LJ);
+            passes.apply_force(passes.gravity_strength, FORCE_TYPE::GRAVITY);
+            passes.apply_force(passes.drag, FORCE_TYPE::PROPORTIONAL);
+            passes.apply_force(passes.mouse_strength, FORCE_TYPE::MOUSE);
+            passes.particles.integrate(passes.dt, &passes.domain_mode);
+
+            assert_eq!(state(&fused), state(&passes), "diverged at step {}", step);
+        }
+    }
+
+    #[test]
+    fn vectorized_pair_sum_is_close_to_ordered() {
+        let simulation = Simulation::from_config(&SimulationConfig { num_particles: 101, ..config() });
+        let (x, y) = (simulation.particles.x(), simulation.particles.y());
+        for i in [0, 50, 100] {
+            let ordered = pair_sum_ordered(isl_term, simulation.attractive_force, x, y, i, glm::vec2(0.0, 0.0));
+            let ordered = pair_sum_ordered(lj_term, simulation.repulsive_force, x, y, i, ordered);
+            let lanes = glm::vec2(pair_sum_lanes(simulation.attractive_force, simulation.repulsive_force, x, i), pair_sum_lanes(simulation.attractive_force, simulation.repulsive_force, y, i));
+            for (ordered, lanes) in ordered.iter().zip(lanes.iter()) {
+                assert!((ordered - lanes).abs() <= ordered.abs() * 1e-4, "{} != {}", ordered, lanes);
+            }
+        }
+    }
+
+    #[test]
+    fn reset_respawns_the_initial_state() {
+        let initial = state(&Simulation::from_config(&config()));
+        let mut simulation = Simulation::from_config(&config());
+        simulation.gravity = false;
+        for _ in 0..5 {
+            simulation.step();
         }
+        assert_ne!(state(&simulation), initial);
+
+        simulation.reset();
+        assert_eq!(state(&simulation), initial);
+        assert!(!simulation.gravity);
 
-        self.apply_force(self.drag, FORCE_TYPE::PROPORTIONAL);
-        self.apply_force(0.001, FORCE_TYPE::MOUSE);
+        simulation.reseed(8);
+        assert_eq!(state(&simulation), state(&Simulation::from_config(&SimulationConfig { seed: Some(8), ..config() })));
+    }
 
-        for particle in &mut self.particles {
-            particle.update(self.dt);
+    #[test]
+    fn brushes_edit_particles() {
+        let mut simulation = Simulation::from_config(&SimulationConfig { num_particles: 0, ..config() });
+        let center = glm::vec2(0.3, 0.3);
+        simulation.spawn_in(center, 0.1, 50, glm::vec2(1.0, 0.0), 0.0);
+        simulation.spawn_in(glm::vec2(0.8, 0.8), 0.05, 10, glm::vec2(0.0, 0.0), 0.0);
+        assert_eq!(simulation.particles.len(), 60);
+        assert!(simulation.particles.iter().take(50).all(|particle| glm::distance(&particle.position, &center) <= 0.1 && particle.velocity == glm::vec2(1.0, 0.0)));
+
+        //The far group keeps its positions and order when the first one is erased
+        let far: Vec<Particle> = simulation.particles.iter().skip(50).collect();
+        assert_eq!(simulation.erase_in(center, 0.1), 50);
+        assert_eq!(simulation.particles.iter().collect::<Vec<_>>(), far);
+
+        //Grabbed particles follow the cursor and are pinned until released, unless they were pinned before
+        assert_eq!(simulation.pin_in(glm::vec2(0.8, 0.8), 0.05, true), 10);
+        simulation.particles.set_pinned(0, false);
+        assert_eq!(simulation.grab(glm::vec2(0.8, 0.8), 0.05), 10);
+        simulation.drag_to(glm::vec2(0.5, 0.8));
+        assert!((simulation.particles.position(3) - (far[3].position - glm::vec2(0.3, 0.0))).norm() < 1e-6);
+        simulation.release_grab();
+        assert!(!simulation.particles.is_pinned(0));
+        assert!(simulation.particles.is_pinned(1));
+        simulation.unpin_all();
+        assert!(simulation.particles.pinned().iter().all(|pinned| !pinned));
+    }
+
+    #[test]
+    fn pinned_particles_stay_put() {
+        let mut simulation = Simulation::from_config(&config());
+        simulation.particles.set_pinned(3, true);
+        let pinned = simulation.particles.get(3);
+        for _ in 0..5 {
+            simulation.step();
         }
+        assert_eq!(simulation.particles.get(3), Particle::new(pinned.position, glm::vec2(0.0, 0.0)));
+        assert_ne!(simulation.particles.get(4), Simulation::from_config(&config()).particles.get(4));
     }
 
-    pub fn pack(&self) -> Vec<f32> {
-        self.particles.iter()
-            .flat_map(|p| p.to_flat().into_iter())
-            .collect()
+    #[test]
+    fn mouse_force_falls_off_radially() {
+        let mut simulation = Simulation::from_config(&config());
+        simulation.set_mouse_position(0.5, 0.5);
+        let mouse = |simulation: &Simulation, x: f32, y: f32| simulation.mouse_strength * mouse_falloff(glm::vec2(0.5 - x, 0.5 - y), simulation.mouse_radius);
+
+        //Same pull in every direction, pointing at the cursor, and nothing at the cursor or past the radius
+        let right = mouse(&simulation, 0.6, 0.5);
+        let diagonal = mouse(&simulation, 0.5 + 0.1 / 2.0_f32.sqrt(), 0.5 + 0.1 / 2.0_f32.sqrt());
+        assert!(right.x < 0.0 && right.y == 0.0);
+        assert!((glm::length(&right) - glm::length(&diagonal)).abs() < 1e-3 * glm::length(&right));
+        assert_eq!(mouse(&simulation, 0.5, 0.5), glm::vec2(0.0, 0.0));
+        assert_eq!(mouse(&simulation, 0.5, 0.5 + 1.01 * simulation.mouse_radius), glm::vec2(0.0, 0.0));
+
+        //Inactive until a force is held, and repelling flips the direction
+        let position = simulation.particles.position(0);
+        let force = |simulation: &Simulation| simulation.accumulate_force(0, simulation.mouse_strength, &FORCE_TYPE::MOUSE, glm::vec2(0.0, 0.0));
+        simulation.set_mouse_position(position.x + 0.05, position.y);
+        assert_eq!(force(&simulation), glm::vec2(0.0, 0.0));
+        simulation.set_mouse_force(Some(MOUSE_STATE::ATTRACTIVE));
+        let attraction = force(&simulation);
+        simulation.set_mouse_force(Some(MOUSE_STATE::REPULSIVE));
+        assert!(attraction.x > 0.0);
+        assert_eq!(force(&simulation), -attraction);
+        simulation.set_mouse_force(None);
+        assert_eq!(force(&simulation), glm::vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn mouse_size_stays_in_range() {
+        let mut simulation = Simulation::from_config(&config());
+        let position = simulation.particles.position(0);
+        simulation.set_mouse_position(position.x + 0.0001, position.y);
+        simulation.set_mouse_force(Some(MOUSE_STATE::ATTRACTIVE));
+        for _ in 0..1000 {
+            simulation.set_mouse_size(simulation.mouse_radius / 1.1, simulation.mouse_strength / 1.1);
+        }
+        assert_eq!((simulation.mouse_radius, simulation.mouse_strength), (*MOUSE_RADIUS_RANGE.start(), *MOUSE_STRENGTH_RANGE.start()));
+        let force = simulation.accumulate_force(0, simulation.mouse_strength, &FORCE_TYPE::MOUSE, glm::vec2(0.0, 0.0));
+        assert!(force.x.is_finite() && force.x != 0.0, "{:?}", force);
+
+        for _ in 0..1000 {
+            simulation.set_mouse_size(simulation.mouse_radius * 1.1, simulation.mouse_strength * 1.1);
+        }
+        assert_eq!((simulation.mouse_radius, simulation.mouse_strength), (*MOUSE_RADIUS_RANGE.end(), *MOUSE_STRENGTH_RANGE.end()));
+        let force = simulation.accumulate_force(0, simulation.mouse_strength, &FORCE_TYPE::MOUSE, glm::vec2(0.0, 0.0));
+        assert!(force.x.is_finite(), "{:?}", force);
+
+        simulation.set_mouse_size(0.1, -0.5);
+        assert_eq!(simulation.mouse_strength, -*MOUSE_STRENGTH_RANGE.start());
+    }
+
+    #[test]
+    fn time_advances_by_dt_per_microstep() {
+        let mut simulation = Simulation::from_config(&SimulationConfig { microsteps: 3, ..config() });
+        simulation.step();
+        simulation.step();
+        assert_eq!(simulation.t, 6.0 * simulation.dt as f64);
+        simulation.reset();
+        assert_eq!(simulation.t, 0.0);
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn deterministic_parallel_matches_serial() {
+        let mut serial = with_mouse(Simulation::from_config(&SimulationConfig { parallel: false, ..config() }));
+        let mut parallel = with_mouse(Simulation::from_config(&SimulationConfig { parallel: true, deterministic: true, ..config() }));
+
+        for step in 0..50 {
+            serial.step();
+            parallel.step();
+            assert_eq!(state(&serial), state(&parallel), "diverged at step {}", step);
+        }
+    }
+}
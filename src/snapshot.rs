@@ -0,0 +1,367 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+use crate::scene::{finite, parameter_problems, spawn_problems};
+use crate::simulation::{DOMAIN_MODE, MOUSE_STATE, SimulationConfig};
+
+/// Version written to new snapshots. Loading any other version is an error.
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// First bytes of a binary snapshot
+const MAGIC: &[u8; 8] = b"CALLSNAP";
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Cannot read snapshot {path}: {source}")]
+    Io { path: PathBuf, source: std::io::Error },
+    #[error("Cannot write snapshot {path}: {source}")]
+    Write { path: PathBuf, source: std::io::Error },
+    #[error("Cannot parse snapshot {path}: {source}")]
+    Parse { path: PathBuf, source: toml::de::Error },
+    #[error("Cannot serialize snapshot {path}: {source}")]
+    Serialize { path: PathBuf, source: toml::ser::Error },
+    #[error("Corrupt snapshot {path}: {problem}")]
+    Corrupt { path: PathBuf, problem: String },
+    #[error("Invalid snapshot:\n  {}", .problems.join("\n  "))]
+    Invalid { problems: Vec<String> }
+}
+
+/// How a snapshot is stored on disk. Loading detects the format from the file contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum SNAPSHOT_FORMAT {
+    /// Human-readable TOML with a `[[particles]]` table per particle
+    #[default]
+    TOML,
+    /// The TOML header without the particles, followed by the particle arrays as little-endian floats
+    BINARY
+}
+
+impl SNAPSHOT_FORMAT {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SNAPSHOT_FORMAT::TOML => "toml",
+            SNAPSHOT_FORMAT::BINARY => "snap"
+        }
+    }
+}
+
+/// State of the mouse force
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseSnapshot {
+    /// Cursor position in world coordinates
+    pub position: [f32; 2],
+    pub state: MOUSE_STATE,
+    pub active: bool,
+    pub strength: f32,
+    pub radius: f32
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParticleSnapshot {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    /// Acceleration of the previous microstep, which the next one integrates with
+    pub acceleration: [f32; 2],
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool
+}
+
+/// Everything needed to resume a `Simulation` exactly, from `Simulation::snapshot`. A grab in progress is not
+/// part of it: grabbed particles are saved where they were dragged to, with zero velocity, and only pinned if they
+/// were pinned before the grab.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationSnapshot {
+    pub version: u32,
+    /// Simulated time, see `Simulation::t`
+    pub t: f64,
+    pub dt: f32,
+    pub attractive_force: f32,
+    pub repulsive_force: f32,
+    pub drag: f32,
+    pub microsteps: i32,
+    pub gravity: bool,
+    pub gravity_strength: f32,
+    pub domain_mode: DOMAIN_MODE,
+    /// Seed of the random generator. A string, since TOML integers are signed.
+    #[serde(serialize_with = "seed_to_string", deserialize_with = "seed_from_string")]
+    pub seed: u64,
+    /// Numbers drawn from the random generator since it was seeded
+    pub rng_draws: u64,
+    pub parallel: bool,
+    pub deterministic: bool,
+    pub mouse: MouseSnapshot,
+    /// The config `Simulation::reset` respawns from
+    pub config: SimulationConfig,
+    #[serde(default)]
+    pub particles: Vec<ParticleSnapshot>
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn seed_to_string<S: Serializer>(seed: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&seed.to_string())
+}
+
+fn seed_from_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+}
+
+/// Reads the little-endian values of a binary snapshot
+struct Reader<'a> {
+    bytes: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < count {
+            return Err("unexpected end of file".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32s(&mut self, count: usize) -> Result<Vec<f32>, String> {
+        Ok(self.take(4 * count)?.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect())
+    }
+}
+
+impl SimulationSnapshot {
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+
+    pub fn from_toml(source: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(source)
+    }
+
+    /// Every value a hand-edited snapshot could get wrong, with the same checks as scene files
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mouse = &self.mouse;
+        let parameters = SimulationConfig {
+            dt: self.dt,
+            attractive_force: self.attractive_force,
+            repulsive_force: self.repulsive_force,
+            drag: self.drag,
+            microsteps: self.microsteps,
+            gravity_strength: self.gravity_strength,
+            mouse_strength: mouse.strength,
+            mouse_radius: mouse.radius,
+            ..self.config.clone()
+        };
+        parameter_problems(&parameters, "", &mut problems);
+        parameter_problems(&self.config, "config.", &mut problems);
+        spawn_problems(&self.config, "config.", &mut problems);
+
+        if !(self.t.is_finite() && self.t >= 0.0) {
+            problems.push("t must be 0 or greater".to_string());
+        }
+        if !finite(&mouse.position) {
+            problems.push("mouse.position must be finite".to_string());
+        }
+        let broken = self.particles.iter()
+            .filter(|particle| !(finite(&particle.position) && finite(&particle.velocity) && finite(&particle.acceleration)))
+            .count();
+        if broken > 0 {
+            problems.push(format!("particles: {} have a position, velocity or acceleration that isn't finite", broken));
+        }
+        problems
+    }
+
+    /// Layout: the magic bytes, the version, the length and text of the TOML header without the particles, the
+    /// particle count, then x, y, vx, vy, ax and ay of every particle as f32 and one byte per particle for pinned.
+    /// Everything is little-endian.
+    pub fn to_binary(&self) -> Result<Vec<u8>, toml::ser::Error> {
+        let header = toml::to_string(&SimulationSnapshot { particles: Vec::new(), ..self.clone() })?;
+        let count = self.particles.len();
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 16 + header.len() + 25 * count);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&(count as u64).to_le_bytes());
+        let components: [fn(&ParticleSnapshot) -> f32; 6] = [
+            |p| p.position[0], |p| p.position[1], |p| p.velocity[0], |p| p.velocity[1], |p| p.acceleration[0], |p| p.acceleration[1]
+        ];
+        for component in components {
+            for particle in &self.particles {
+                bytes.extend_from_slice(&component(particle).to_le_bytes());
+            }
+        }
+        bytes.extend(self.particles.iter().map(|particle| particle.pinned as u8));
+        Ok(bytes)
+    }
+
+    /// Reads a snapshot written by `to_binary`. Errors describe the problem without the path.
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a binary snapshot".to_string());
+        }
+        check_version(reader.u32()?)?;
+        let header_len = reader.u32()? as usize;
+        let header = std::str::from_utf8(reader.take(header_len)?).map_err(|e| e.to_string())?;
+        let mut snapshot: SimulationSnapshot = toml::from_str(header).map_err(|e| e.to_string())?;
+
+        let count = reader.u64()? as usize;
+        if count > reader.bytes.len() / 25 {
+            return Err(format!("{} particles don't fit in the file", count));
+        }
+        let [x, y, vx, vy, ax, ay] = [(); 6].map(|_| reader.f32s(count));
+        let (x, y, vx, vy, ax, ay) = (x?, y?, vx?, vy?, ax?, ay?);
+        let pinned = reader.take(count)?;
+        if !reader.bytes.is_empty() {
+            return Err("trailing data after the particles".to_string());
+        }
+        snapshot.particles = (0..count).map(|i| ParticleSnapshot {
+            position: [x[i], y[i]],
+            velocity: [vx[i], vy[i]],
+            acceleration: [ax[i], ay[i]],
+            pinned: pinned[i] != 0
+        }).collect();
+        Ok(snapshot)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>, format: SNAPSHOT_FORMAT) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        let bytes = match format {
+            SNAPSHOT_FORMAT::TOML => self.to_toml().map(String::into_bytes),
+            SNAPSHOT_FORMAT::BINARY => self.to_binary()
+        }.map_err(|source| SnapshotError::Serialize { path: path.to_path_buf(), source })?;
+        fs::write(path, bytes).map_err(|source| SnapshotError::Write { path: path.to_path_buf(), source })
+    }
+
+    /// Loads a snapshot in either format. The values are checked by `Simulation::from_snapshot`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|source| SnapshotError::Io { path: path.to_path_buf(), source })?;
+        let invalid = |problem: String| SnapshotError::Corrupt { path: path.to_path_buf(), problem };
+
+        if bytes.starts_with(MAGIC) {
+            return Self::from_binary(&bytes).map_err(invalid);
+        }
+        let source = std::str::from_utf8(&bytes).map_err(|_| invalid("neither TOML nor a binary snapshot".to_string()))?;
+        let snapshot = Self::from_toml(source).map_err(|source| SnapshotError::Parse { path: path.to_path_buf(), source })?;
+        check_version(snapshot.version).map_err(invalid)?;
+        Ok(snapshot)
+    }
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version != SNAPSHOT_VERSION {
+        return Err(format!("version {} is not supported, expected {}", version, SNAPSHOT_VERSION));
+    }
+    Ok(())
+}
+
+/// `snapshot_00000.<extension>`, `snapshot_00001.<extension>`, ... in `dir`, whichever doesn't exist yet
+pub fn next_snapshot_path(dir: impl AsRef<Path>, format: SNAPSHOT_FORMAT) -> PathBuf {
+    let dir = dir.as_ref();
+    (0..)
+        .map(|i| dir.join(format!("snapshot_{:05}.{}", i, format.extension())))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Simulation;
+    extern crate nalgebra_glm as glm;
+
+    fn state(simulation: &Simulation) -> Vec<u32> {
+        let (vx, vy) = simulation.particles.velocities();
+        let (ax, ay) = simulation.particles.accelerations();
+        [simulation.particles.x(), simulation.particles.y(), vx, vy, ax, ay].concat().iter().map(|value| value.to_bits()).collect()
+    }
+
+    /// A simulation some way into a run, with every part of the state away from its default
+    fn running() -> Simulation {
+        let mut simulation = Simulation::from_config(&SimulationConfig { num_particles: 48, seed: Some(u64::MAX - 3), ..SimulationConfig::default() });
+        simulation.set_mouse_position(0.45, 0.55);
+        simulation.set_mouse_force(Some(MOUSE_STATE::REPULSIVE));
+        simulation.mouse_radius = 0.3;
+        simulation.gravity = true;
+        simulation.domain_mode = DOMAIN_MODE::WALL;
+        simulation.pin_in(glm::vec2(0.8, 0.5), 0.1, true);
+        simulation.spawn_in(glm::vec2(0.5, 0.5), 0.1, 5, glm::vec2(1.0, 0.0), 2.0);
+        for _ in 0..5 {
+            simulation.step();
+        }
+        simulation
+    }
+
+    #[test]
+    fn resumes_exactly_from_both_formats() {
+        let mut original = running();
+        let snapshot = original.snapshot();
+        let from_toml = SimulationSnapshot::from_toml(&snapshot.to_toml().unwrap()).unwrap();
+        let from_binary = SimulationSnapshot::from_binary(&snapshot.to_binary().unwrap()).unwrap();
+        assert_eq!(from_toml.particles, snapshot.particles);
+        assert_eq!(from_binary.particles, snapshot.particles);
+
+        let mut resumed: Vec<Simulation> = [from_toml, from_binary].iter().map(|snapshot| Simulation::from_snapshot(snapshot).unwrap()).collect();
+        for simulation in [&mut original].into_iter().chain(&mut resumed) {
+            for _ in 0..5 {
+                simulation.step();
+            }
+            //The random generator continues where it was too
+            simulation.spawn_in(glm::vec2(0.5, 0.5), 0.1, 3, glm::vec2(0.0, 0.0), 1.0);
+            simulation.step();
+        }
+        for simulation in &resumed {
+            assert_eq!(state(simulation), state(&original));
+            assert_eq!(simulation.particles.pinned(), original.particles.pinned());
+            assert_eq!(simulation.seed, original.seed);
+            assert_eq!(simulation.mouse_position(), original.mouse_position());
+            assert_eq!(simulation.t, original.t);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let mut snapshot = running().snapshot();
+        snapshot.dt = 0.0;
+        snapshot.microsteps = 0;
+        snapshot.mouse.radius = 0.0;
+        snapshot.particles[3].position[0] = f32::NAN;
+        snapshot.config.spawn = crate::simulation::SPAWN_DISTRIBUTION::DISK { center: [0.5, 0.5], radius: -1.0 };
+
+        let Err(SnapshotError::Invalid { problems }) = Simulation::from_snapshot(&snapshot) else {
+            panic!("Invalid snapshot accepted");
+        };
+        assert_eq!(problems, [
+            "dt must be greater than 0",
+            "microsteps must be at least 1",
+            "mouse_radius must be greater than 0",
+            "config.spawn.radius must be 0 or greater",
+            "particles: 1 have a position, velocity or acceleration that isn't finite"
+        ]);
+    }
+
+    #[test]
+    fn rejects_broken_binary_snapshots() {
+        let bytes = running().snapshot().to_binary().unwrap();
+        assert!(SimulationSnapshot::from_binary(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SimulationSnapshot::from_binary(&[bytes.as_slice(), &[0]].concat()).is_err());
+
+        let mut other_version = bytes.clone();
+        other_version[MAGIC.len()] = 2;
+        assert!(SimulationSnapshot::from_binary(&other_version).unwrap_err().contains("version 2"));
+    }
+}
@@ -0,0 +1,275 @@
+use std::fs;
+use std::thread;
+use serde::{Deserialize, Serialize};
+use crate::colormap::Colormap;
+use crate::frames::{FrameError, SequenceOptions, write_png};
+use crate::view::View;
+use crate::simulation::{MOUSE_STATE, Simulation};
+extern crate nalgebra_glm as glm;
+
+/// Rendering constants of the density visualization. The defaults match the `#define`s in `shaders/visualize.frag`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DensityParams {
+    pub sim_resolution: f32,
+    pub brightness: f32,
+    pub spread: f32,
+    pub crosshair_line_width: f32,
+    pub crosshair_size: f32,
+    pub crosshair_gap_size: f32
+}
+
+impl Default for DensityParams {
+    fn default() -> Self {
+        Self {
+            sim_resolution: 1000.0,
+            brightness: 0.4,
+            spread: 6.0,
+            crosshair_line_width: 0.8,
+            crosshair_size: 16.0,
+            crosshair_gap_size: 4.0
+        }
+    }
+}
+
+/// Pure Rust implementation of `visualize.frag`, for machines without a working OpenGL driver and as an
+/// exact reference for image comparisons. Produces tightly packed RGBA rows, top row first.
+pub struct SoftwareRenderer {
+    pub params: DensityParams,
+    /// Cursor position in physical pixels from the top left. The crosshair is drawn when this is set.
+    pub crosshair: Option<glm::Vec2>,
+    pixels: Vec<u8>
+}
+
+impl SoftwareRenderer {
+    pub fn new(params: DensityParams) -> Self {
+        Self {
+            params,
+            crosshair: None,
+            pixels: Vec::new()
+        }
+    }
+
+    /// Renders a frame of `view.screen_size`, which is empty if either dimension is 0
+    pub fn render(&mut self, simulation: &Simulation, view: &View, colormap: &Colormap) -> &[u8] {
+        let width = view.screen_size.x as usize;
+        let height = view.screen_size.y as usize;
+        self.pixels.resize(width * height * 4, 0);
+        if self.pixels.is_empty() {
+            return &self.pixels;
+        }
+
+        let tracers = simulation.pack();
+        let crosshair_color = crosshair_color(simulation);
+        let mouse_radius = simulation.mouse_radius;
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        let rows_per_chunk = height.div_ceil(threads).max(1);
+        let params = &self.params;
+        let crosshair = self.crosshair;
+
+        thread::scope(|scope| {
+            for (chunk_index, chunk) in self.pixels.chunks_mut(rows_per_chunk * width * 4).enumerate() {
+                scope.spawn(move || {
+                    for (i, pixel) in chunk.chunks_exact_mut(4).enumerate() {
+                        let row = chunk_index * rows_per_chunk + i / width;
+                        let column = i % width;
+
+                        //Equivalent of gl_FragCoord, which has its origin in the bottom left and samples pixel centers
+                        let frag_coord = glm::vec2(column as f32 + 0.5, (height - 1 - row) as f32 + 0.5);
+                        let mut color = colormap.sample(shade_density(params, view, tracers, frag_coord));
+
+                        if let Some(mouse) = crosshair {
+                            let mouse_coords = glm::vec2(mouse.x, view.screen_size.y - mouse.y);
+                            if let Some(crosshair) = draw_crosshair(params, view, frag_coord, mouse_coords, mouse_radius, crosshair_color) {
+                                color = crosshair;
+                            }
+                        }
+
+                        pixel[0] = to_unorm8(color[0]);
+                        pixel[1] = to_unorm8(color[1]);
+                        pixel[2] = to_unorm8(color[2]);
+                        pixel[3] = 255;
+                    }
+                });
+            }
+        });
+
+        &self.pixels
+    }
+}
+
+/// CPU equivalent of `headless::render_sequence`, for machines without any OpenGL implementation
+pub fn render_sequence(simulation: &mut Simulation, colormap: &Colormap, params: &DensityParams, options: &SequenceOptions) -> Result<usize, FrameError> {
+    fs::create_dir_all(&options.output_dir)?;
+
+    let view = View::new(options.width, options.height, 1.0);
+    let mut renderer = SoftwareRenderer::new(params.clone());
+
+    let mut frames = 0;
+    for step in 0..options.steps {
+        simulation.step();
+        if step % options.every != 0 {
+            continue;
+        }
+
+        let rgba = renderer.render(simulation, &view, colormap);
+        write_png(options.frame_path(frames), options.width, options.height, rgba)?;
+        frames += 1;
+    }
+
+    Ok(frames)
+}
+
+/// Density at a fragment, before the colormap is applied
+fn shade_density(params: &DensityParams, view: &View, tracers: [&[f32]; 2], frag_coord: glm::Vec2) -> f32 {
+    let spread = params.spread / 1000.0;
+    let cell_size = 1.0 / params.sim_resolution;
+    let st = (frag_coord - view.screen_size / 2.0) / view.pixels_per_unit() + view.camera.center;
+
+    let grid_pos = glm::vec2((st.x * params.sim_resolution).floor(), (st.y * params.sim_resolution).floor());
+    let uv = grid_pos / params.sim_resolution + glm::vec2(cell_size / 2.0, cell_size / 2.0);
+
+    let mut density = 0.0;
+    let [xs, ys] = tracers;
+    for (x, y) in xs.iter().zip(ys) {
+        let dist = glm::distance(&uv, &glm::vec2(*x, *y));
+        let density_contrib = (spread / dist).min(1.0);
+        density += density_contrib * params.brightness;
+    }
+    density
+}
+
+fn crosshair_color(simulation: &Simulation) -> [f32; 3] {
+    let attractive = simulation.mouse_state == MOUSE_STATE::ATTRACTIVE;
+    match (attractive, simulation.mouse_active) {
+        (true, true) => [1.0, 1.0, 0.0],
+        (false, true) => [1.0, 0.0, 0.0],
+        (true, false) => [0.0, 1.0, 0.0],
+        (false, false) => [0.0, 0.0, 1.0]
+    }
+}
+
+fn draw_crosshair(params: &DensityParams, view: &View, frag_coord: glm::Vec2, mouse_coords: glm::Vec2, mouse_radius: f32, color: [f32; 3]) -> Option<[f32; 3]> {
+    let offset = glm::abs(&(frag_coord - mouse_coords)) / view.scale_factor as f32;
+    if offset.x < params.crosshair_gap_size && offset.y < params.crosshair_gap_size {
+        return None;
+    }
+    if (offset.x <= params.crosshair_line_width && offset.y <= params.crosshair_size) || (offset.y <= params.crosshair_line_width && offset.x <= params.crosshair_size) {
+        return Some(color);
+    }
+    let radius = mouse_radius * view.pixels_per_unit() / view.scale_factor as f32;
+    if (glm::length(&offset) - radius).abs() <= params.crosshair_line_width / 2.0 {
+        return Some(color);
+    }
+    None
+}
+
+/// Same float to 8 bit conversion OpenGL does when writing to a normalized framebuffer
+fn to_unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colormap::ColormapRegistry;
+    use crate::particle::Particle;
+
+    fn simulation_with(positions: &[(f32, f32)]) -> Simulation {
+        let mut simulation = Simulation::default();
+        simulation.particles = positions.iter()
+            .map(|(x, y)| Particle::new(glm::vec2(*x, *y), glm::vec2(0.0, 0.0)))
+            .collect();
+        simulation
+    }
+
+    fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * width + x) * 4;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    }
+
+    #[test]
+    fn empty_simulation_renders_bottom_of_colormap() {
+        let registry = ColormapRegistry::default();
+        let colormap = registry.get("viridis").unwrap();
+        let mut renderer = SoftwareRenderer::new(DensityParams::default());
+        let view = View::new(16, 8, 1.0);
+
+        let pixels = renderer.render(&simulation_with(&[]), &view, colormap);
+
+        let expected = colormap.sample(0.0).map(to_unorm8);
+        assert_eq!(pixels.len(), 16 * 8 * 4);
+        for rgba in pixels.chunks_exact(4) {
+            assert_eq!(rgba, [expected[0], expected[1], expected[2], 255]);
+        }
+    }
+
+    #[test]
+    fn zero_sized_view_renders_nothing() {
+        let registry = ColormapRegistry::default();
+        let colormap = registry.get("lightblue").unwrap();
+        let mut renderer = SoftwareRenderer::new(DensityParams::default());
+        let mut view = View::new(16, 8, 1.0);
+        view.screen_size.x = 0.0;
+
+        assert!(renderer.render(&simulation_with(&[(0.5, 0.5)]), &view, colormap).is_empty());
+    }
+
+    #[test]
+    fn density_peaks_at_particle() {
+        let registry = ColormapRegistry::default();
+        let colormap = registry.get("magma").unwrap();
+        let mut renderer = SoftwareRenderer::new(DensityParams::default());
+        let view = View::new(64, 64, 1.0);
+
+        //Magma gets brighter with density, so the pixel on top of the particle should be the brightest
+        let pixels = renderer.render(&simulation_with(&[(0.25, 0.75)]), &view, colormap).to_vec();
+        let brightness = |[r, g, b, _]: [u8; 4]| r as u32 + g as u32 + b as u32;
+        let at_particle = brightness(pixel(&pixels, 64, 16, 16));
+        let far_away = brightness(pixel(&pixels, 64, 48, 48));
+        assert!(at_particle > far_away);
+    }
+
+    #[test]
+    fn render_is_deterministic() {
+        let registry = ColormapRegistry::default();
+        let colormap = registry.get("lightblue").unwrap();
+        let simulation = simulation_with(&[(0.3, 0.3), (0.6, 0.4), (0.5, 0.9)]);
+        let view = View::new(37, 23, 1.0);
+
+        let first = SoftwareRenderer::new(DensityParams::default()).render(&simulation, &view, colormap).to_vec();
+        let second = SoftwareRenderer::new(DensityParams::default()).render(&simulation, &view, colormap).to_vec();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn crosshair_is_drawn_at_cursor() {
+        let registry = ColormapRegistry::default();
+        let colormap = registry.get("lightblue").unwrap();
+        let mut renderer = SoftwareRenderer::new(DensityParams::default());
+        renderer.crosshair = Some(glm::vec2(32.0, 32.0));
+        let view = View::new(64, 64, 1.0);
+
+        //Inactive attractive mouse is green. The arm starts past the gap, and the center is left empty.
+        let pixels = renderer.render(&simulation_with(&[]), &view, colormap).to_vec();
+        assert_eq!(pixel(&pixels, 64, 32 + 8, 31), [0, 255, 0, 255]);
+        assert_ne!(pixel(&pixels, 64, 32, 31), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn crosshair_circle_shows_mouse_radius() {
+        let registry = ColormapRegistry::default();
+        let colormap = registry.get("lightblue").unwrap();
+        let mut renderer = SoftwareRenderer::new(DensityParams::default());
+        renderer.crosshair = Some(glm::vec2(32.0, 32.0));
+        let view = View::new(64, 64, 1.0);
+        let mut simulation = simulation_with(&[]);
+        simulation.mouse_radius = 20.0 / view.pixels_per_unit();
+
+        //The circle crosses the diagonal 20 pixels from the cursor, well away from the arms
+        let offset = (20.0 / 2.0_f32.sqrt()).round() as usize;
+        let pixels = renderer.render(&simulation, &view, colormap).to_vec();
+        assert_eq!(pixel(&pixels, 64, 32 + offset, 32 - offset), [0, 255, 0, 255]);
+        assert_ne!(pixel(&pixels, 64, 32 + offset - 4, 32 - offset + 4), [0, 255, 0, 255]);
+    }
+}
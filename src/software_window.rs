@@ -0,0 +1,245 @@
+use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window, WindowOptions};
+use callisto::colormap::{Colormap, ColormapRegistry};
+use callisto::input::{ACTION, Input, InputMapper, MOUSE_BUTTON, Modifiers};
+use callisto::view::View;
+use callisto::scene::RenderConfig;
+use callisto::scheduler::{FixedTimestep, ScheduleConfig};
+use callisto::simulation::{MOUSE_STATE, Simulation};
+use callisto::snapshot::{next_snapshot_path, SNAPSHOT_FORMAT, SnapshotError};
+use callisto::software::SoftwareRenderer;
+use crate::report;
+extern crate nalgebra_glm as glm;
+
+/// Factor the time scale changes by per keypress, as in the OpenGL app
+const TIME_SCALE_STEP: f64 = 2.0;
+/// Factor the zoom and the mouse radius and strength change by per scroll wheel notch, as in the OpenGL app
+const SCROLL_STEP: f32 = 1.1;
+/// Mouse buttons the bindings can use
+const MOUSE_BUTTONS: [(MouseButton, MOUSE_BUTTON); 3] = [
+    (MouseButton::Left, MOUSE_BUTTON::LEFT),
+    (MouseButton::Right, MOUSE_BUTTON::RIGHT),
+    (MouseButton::Middle, MOUSE_BUTTON::MIDDLE)
+];
+
+/// Name of `key` in bindings files, which use winit's `VirtualKeyCode` names
+fn key_name(key: Key) -> String {
+    let name = match key {
+        Key::Backquote => "Grave",
+        Key::Equal => "Equals",
+        Key::LeftBracket => "LBracket",
+        Key::RightBracket => "RBracket",
+        Key::Backspace => "Back",
+        Key::Enter => "Return",
+        Key::LeftCtrl => "LControl",
+        Key::RightCtrl => "RControl",
+        Key::LeftShift => "LShift",
+        Key::RightShift => "RShift",
+        Key::LeftAlt => "LAlt",
+        Key::RightAlt => "RAlt",
+        Key::LeftSuper => "LWin",
+        Key::RightSuper => "RWin",
+        Key::NumPadDot => "NumpadDecimal",
+        Key::NumPadSlash => "NumpadDivide",
+        Key::NumPadAsterisk => "NumpadMultiply",
+        Key::NumPadMinus => "NumpadSubtract",
+        Key::NumPadPlus => "NumpadAdd",
+        Key::NumPadEnter => "NumpadEnter",
+        _ => return format!("{:?}", key).replace("NumPad", "Numpad")
+    };
+    name.to_string()
+}
+
+/// State of the software window that actions change. It has no tools, HUD or help overlay, so those actions do
+/// nothing and the left button always attracts.
+struct SoftwareApp {
+    simulation: Simulation,
+    clock: FixedTimestep,
+    view: View,
+    colormaps: Vec<Colormap>,
+    colormap_index: usize,
+    snapshot_format: SNAPSHOT_FORMAT,
+    /// Cursor position in physical pixels
+    mouse_position: glm::Vec2,
+    /// Mouse forces that are held down. The last one pressed applies.
+    mouse_forces: Vec<MOUSE_STATE>,
+    panning: bool
+}
+
+impl SoftwareApp {
+    fn set_mouse_position(&mut self, position: glm::Vec2) {
+        if self.panning {
+            self.view.pan(position - self.mouse_position);
+        }
+        self.mouse_position = position;
+        let world = self.view.screen_to_world(position);
+        self.simulation.set_mouse_position(world.x, world.y);
+    }
+
+    fn hold_mouse_force(&mut self, force: MOUSE_STATE, held: bool) {
+        self.mouse_forces.retain(|held_force| *held_force != force);
+        if held {
+            self.mouse_forces.push(force);
+        }
+        self.simulation.set_mouse_force(self.mouse_forces.last().cloned());
+    }
+
+    /// Starts `action`, like `Renderer::on_action`
+    fn on_action(&mut self, action: ACTION) -> Result<Option<String>, SnapshotError> {
+        let simulation = &mut self.simulation;
+        match action {
+            ACTION::USE_TOOL => self.hold_mouse_force(MOUSE_STATE::ATTRACTIVE, true),
+            ACTION::REPEL => self.hold_mouse_force(MOUSE_STATE::REPULSIVE, true),
+            ACTION::PAN => self.panning = true,
+            ACTION::ZOOM_IN => self.view.zoom_at(SCROLL_STEP, self.mouse_position),
+            ACTION::ZOOM_OUT => self.view.zoom_at(1.0 / SCROLL_STEP, self.mouse_position),
+            ACTION::GROW_MOUSE_RADIUS => simulation.set_mouse_size(simulation.mouse_radius * SCROLL_STEP, simulation.mouse_strength),
+            ACTION::SHRINK_MOUSE_RADIUS => simulation.set_mouse_size(simulation.mouse_radius / SCROLL_STEP, simulation.mouse_strength),
+            ACTION::STRENGTHEN_MOUSE => simulation.set_mouse_size(simulation.mouse_radius, simulation.mouse_strength * SCROLL_STEP),
+            ACTION::WEAKEN_MOUSE => simulation.set_mouse_size(simulation.mouse_radius, simulation.mouse_strength / SCROLL_STEP),
+            ACTION::UNPIN_ALL => simulation.unpin_all(),
+            ACTION::NEXT_COLORMAP => {
+                self.colormap_index = (self.colormap_index + 1) % self.colormaps.len();
+                return Ok(Some(format!("Colormap: {}", self.colormaps[self.colormap_index].name)));
+            },
+            ACTION::FIT_CAMERA => self.view.fit(simulation.particles.positions()),
+            ACTION::TOGGLE_PAUSE => self.clock.toggle_pause(),
+            ACTION::STEP_ONCE => self.clock.step_once(),
+            ACTION::SLOW_DOWN => self.clock.set_time_scale(self.clock.time_scale / TIME_SCALE_STEP),
+            ACTION::SPEED_UP => self.clock.set_time_scale(self.clock.time_scale * TIME_SCALE_STEP),
+            ACTION::RESET => simulation.reset(),
+            ACTION::RESEED => simulation.reseed(rand::random()),
+            ACTION::TOGGLE_GRAVITY => simulation.gravity = !simulation.gravity,
+            ACTION::NEXT_DOMAIN_MODE => simulation.domain_mode = simulation.domain_mode.next(),
+            ACTION::SAVE_SNAPSHOT => {
+                let path = next_snapshot_path("", self.snapshot_format);
+                simulation.snapshot().save(&path, self.snapshot_format)?;
+                return Ok(Some(format!("Saved snapshot {}", path.display())));
+            },
+            ACTION::TOOL_FORCE | ACTION::TOOL_SPAWN | ACTION::TOOL_ERASE | ACTION::TOOL_GRAB | ACTION::TOOL_PIN | ACTION::TOGGLE_HUD | ACTION::TOGGLE_HELP => ()
+        }
+        //Zooming and panning move the world under the cursor
+        self.set_mouse_position(self.mouse_position);
+        Ok(None)
+    }
+
+    /// Ends a held action
+    fn on_action_release(&mut self, action: ACTION) {
+        match action {
+            ACTION::USE_TOOL => self.hold_mouse_force(MOUSE_STATE::ATTRACTIVE, false),
+            ACTION::REPEL => self.hold_mouse_force(MOUSE_STATE::REPULSIVE, false),
+            ACTION::PAN => self.panning = false,
+            _ => ()
+        }
+    }
+}
+
+/// Minimal interactive window driven by the CPU renderer, for machines where OpenGL is unavailable. Input goes
+/// through the same bindings as the OpenGL app. Steps follow the fixed-timestep clock, but are drawn without
+/// interpolation.
+#[allow(clippy::too_many_arguments)]
+pub fn run(simulation: Simulation, colormaps: ColormapRegistry, mut mapper: InputMapper, config: &RenderConfig, schedule: &ScheduleConfig, snapshot_format: SNAPSHOT_FORMAT, width: usize, height: usize) -> Result<(), minifb::Error> {
+    let mut window = Window::new("Callisto (software)", width, height, WindowOptions { resize: true, ..WindowOptions::default() })?;
+    window.set_target_fps(60);
+
+    let colormaps: Vec<_> = colormaps.iter().cloned().collect();
+    let colormap_index = colormaps.iter().position(|colormap| colormap.name == config.colormap).unwrap_or(0);
+    let mut app = SoftwareApp {
+        simulation,
+        clock: FixedTimestep::new(schedule),
+        view: View::new(width as u32, height as u32, 1.0),
+        colormaps,
+        colormap_index,
+        snapshot_format,
+        mouse_position: glm::vec2(0.0, 0.0),
+        mouse_forces: Vec::new(),
+        panning: false
+    };
+    let mut renderer = SoftwareRenderer::new(config.shader.clone());
+    let mut buffer: Vec<u32> = Vec::new();
+    let mut buttons_down = [false; MOUSE_BUTTONS.len()];
+
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        //Minimized windows report a size of 0, skip drawing until there is something to draw into
+        let (width, height) = window.get_size();
+        if width == 0 || height == 0 {
+            window.update();
+            continue;
+        }
+        app.view.resize(width as u32, height as u32);
+
+        if let Some((x, y)) = window.get_mouse_pos(MouseMode::Pass) {
+            app.set_mouse_position(glm::vec2(x, y));
+            renderer.crosshair = Some(glm::vec2(x, y));
+        }
+
+        let held = |keys: [Key; 2]| keys.iter().any(|key| window.is_key_down(*key));
+        mapper.set_modifiers(Modifiers {
+            ctrl: held([Key::LeftCtrl, Key::RightCtrl]),
+            shift: held([Key::LeftShift, Key::RightShift]),
+            alt: held([Key::LeftAlt, Key::RightAlt]),
+            logo: held([Key::LeftSuper, Key::RightSuper])
+        });
+
+        for key in window.get_keys_pressed(KeyRepeat::No) {
+            if let Some(action) = mapper.press(Input::Key(key_name(key))) {
+                report(app.on_action(action));
+            }
+        }
+        for key in window.get_keys_released() {
+            if let Some(action) = mapper.release(Input::Key(key_name(key))) {
+                app.on_action_release(action);
+            }
+        }
+        for ((button, input), was_down) in MOUSE_BUTTONS.iter().zip(&mut buttons_down) {
+            let down = window.get_mouse_down(*button);
+            if down && !*was_down {
+                if let Some(action) = mapper.press(Input::Mouse(*input)) {
+                    report(app.on_action(action));
+                }
+            } else if !down && *was_down {
+                if let Some(action) = mapper.release(Input::Mouse(*input)) {
+                    app.on_action_release(action);
+                }
+            }
+            *was_down = down;
+        }
+        if let Some((_, steps)) = window.get_scroll_wheel().filter(|(_, steps)| *steps != 0.0) {
+            if let Some(action) = mapper.scroll(steps) {
+                report(app.on_action(action));
+            }
+        }
+
+        for _ in 0..app.clock.tick() {
+            app.simulation.step();
+        }
+
+        let rgba = renderer.render(&app.simulation, &app.view, &app.colormaps[app.colormap_index]);
+        buffer.clear();
+        buffer.extend(rgba.chunks_exact(4).map(|p| ((p[0] as u32) << 16) | ((p[1] as u32) << 8) | p[2] as u32));
+        window.update_with_buffer(&buffer, width, height)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use callisto::input::Binding;
+
+    #[test]
+    fn key_names_match_the_bindings() {
+        let keys = [
+            Key::A, Key::Z, Key::Key0, Key::Key9, Key::F1, Key::F12, Key::Space, Key::Escape, Key::Enter, Key::Backspace,
+            Key::Tab, Key::Delete, Key::Left, Key::Up, Key::LeftCtrl, Key::RightShift, Key::LeftAlt, Key::RightSuper,
+            Key::Minus, Key::Equal, Key::LeftBracket, Key::RightBracket, Key::Backslash, Key::Semicolon, Key::Apostrophe,
+            Key::Comma, Key::Period, Key::Slash, Key::Backquote, Key::NumPad0, Key::NumPad9, Key::NumPadPlus,
+            Key::NumPadMinus, Key::NumPadAsterisk, Key::NumPadSlash, Key::NumPadDot, Key::NumPadEnter, Key::PageUp
+        ];
+        for key in keys {
+            assert!(key_name(key).parse::<Binding>().is_ok(), "{:?} maps to the unknown key {}", key, key_name(key));
+        }
+        assert_eq!(key_name(Key::LeftBracket), "LBracket");
+        assert_eq!(key_name(Key::NumPad3), "Numpad3");
+    }
+}
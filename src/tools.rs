@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+/// What the primary mouse button does in the interactive app. Every tool works within the mouse radius.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TOOL {
+    /// Attract particles to the cursor
+    FORCE,
+    /// Paint new particles
+    SPAWN,
+    /// Delete particles
+    ERASE,
+    /// Drag particles with the cursor
+    GRAB,
+    /// Lock particles in place
+    PIN
+}
+
+impl TOOL {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TOOL::FORCE => "force",
+            TOOL::SPAWN => "spawn",
+            TOOL::ERASE => "erase",
+            TOOL::GRAB => "grab",
+            TOOL::PIN => "pin"
+        }
+    }
+}
+
+/// `[tools]` table: settings of the brush tools
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolConfig {
+    /// Particles the spawn brush paints per second of real time
+    pub spawn_rate: f32,
+    /// Velocity of painted particles
+    pub spawn_velocity: [f32; 2],
+    /// Largest random speed added to `spawn_velocity`, in a random direction
+    pub spawn_velocity_jitter: f32,
+    /// The spawn brush stops painting at this many particles
+    pub max_particles: usize
+}
+
+impl Default for ToolConfig {
+    fn default() -> Self {
+        Self {
+            spawn_rate: 200.0,
+            spawn_velocity: [0.0, 0.0],
+            spawn_velocity_jitter: 0.0,
+            max_particles: 20000
+        }
+    }
+}
+
+/// Spreads the spawn rate over frames, carrying fractions of a particle over to the next frame
+#[derive(Clone, Debug, Default)]
+pub struct SpawnClock {
+    pending: f32
+}
+
+impl SpawnClock {
+    /// Number of particles to paint after `elapsed` seconds at `rate` particles per second
+    pub fn advance(&mut self, rate: f32, elapsed: f32) -> usize {
+        self.pending += rate * elapsed;
+        let due = self.pending.floor();
+        self.pending -= due;
+        due as usize
+    }
+
+    pub fn reset(&mut self) {
+        self.pending = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_rate_carries_fractions_over() {
+        let mut clock = SpawnClock::default();
+        let counts: Vec<usize> = (0..4).map(|_| clock.advance(100.0, 0.025)).collect();
+        assert_eq!(counts.iter().sum::<usize>(), 10);
+        assert!(counts.iter().all(|count| *count == 2 || *count == 3));
+    }
+}
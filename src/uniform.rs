@@ -1,69 +1,399 @@
+use std::collections::HashMap;
+use gl::types::*;
+use thiserror::Error;
+use crate::shaderprogram::ActiveUniform;
 extern crate nalgebra_glm as glm;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[allow(non_camel_case_types)]
 pub enum UniformValue {
-    Float(f64),
-    Int(i64),
+    Float(f32),
+    Int(i32),
+    Bool(bool),
+    Vec2(glm::Vec2),
+    Vec3(glm::Vec3),
+    Vec4(glm::Vec4),
+    IVec2(glm::IVec2),
+    IVec3(glm::IVec3),
+    IVec4(glm::IVec4),
+    Mat2(glm::Mat2),
+    Mat3(glm::Mat3),
     Mat4(glm::TMat4<f32>),
     Array_F(Vec<f32>),
-    Vec2(glm::Vec2),
-    Bool(bool)
+    Array_I(Vec<i32>),
+    Array_Vec2(Vec<glm::Vec2>),
+    Array_Vec3(Vec<glm::Vec3>),
+    Array_Vec4(Vec<glm::Vec4>),
+    /// Texture unit a sampler reads from
+    Sampler(u32)
+}
+
+impl UniformValue {
+    /// Name of the GLSL type this value is uploaded as, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            UniformValue::Float(_) => "float",
+            UniformValue::Int(_) => "int",
+            UniformValue::Bool(_) => "bool",
+            UniformValue::Vec2(_) => "vec2",
+            UniformValue::Vec3(_) => "vec3",
+            UniformValue::Vec4(_) => "vec4",
+            UniformValue::IVec2(_) => "ivec2",
+            UniformValue::IVec3(_) => "ivec3",
+            UniformValue::IVec4(_) => "ivec4",
+            UniformValue::Mat2(_) => "mat2",
+            UniformValue::Mat3(_) => "mat3",
+            UniformValue::Mat4(_) => "mat4",
+            UniformValue::Array_F(_) => "float[]",
+            UniformValue::Array_I(_) => "int[]",
+            UniformValue::Array_Vec2(_) => "vec2[]",
+            UniformValue::Array_Vec3(_) => "vec3[]",
+            UniformValue::Array_Vec4(_) => "vec4[]",
+            UniformValue::Sampler(_) => "sampler"
+        }
+    }
+
+    /// Number of array elements, 1 for single values
+    pub fn len(&self) -> usize {
+        match self {
+            UniformValue::Array_F(values) => values.len(),
+            UniformValue::Array_I(values) => values.len(),
+            UniformValue::Array_Vec2(values) => values.len(),
+            UniformValue::Array_Vec3(values) => values.len(),
+            UniformValue::Array_Vec4(values) => values.len(),
+            _ => 1
+        }
+    }
+
+    /// True for empty arrays
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether this value can be uploaded to an active uniform of the given type, as reported by `glGetActiveUniform`
+    pub fn matches_gl_type(&self, gl_type: GLenum) -> bool {
+        match self {
+            UniformValue::Float(_) | UniformValue::Array_F(_) => gl_type == gl::FLOAT,
+            UniformValue::Int(_) | UniformValue::Array_I(_) => gl_type == gl::INT,
+            UniformValue::Bool(_) => gl_type == gl::BOOL,
+            UniformValue::Vec2(_) | UniformValue::Array_Vec2(_) => gl_type == gl::FLOAT_VEC2,
+            UniformValue::Vec3(_) | UniformValue::Array_Vec3(_) => gl_type == gl::FLOAT_VEC3,
+            UniformValue::Vec4(_) | UniformValue::Array_Vec4(_) => gl_type == gl::FLOAT_VEC4,
+            UniformValue::IVec2(_) => gl_type == gl::INT_VEC2,
+            UniformValue::IVec3(_) => gl_type == gl::INT_VEC3,
+            UniformValue::IVec4(_) => gl_type == gl::INT_VEC4,
+            UniformValue::Mat2(_) => gl_type == gl::FLOAT_MAT2,
+            UniformValue::Mat3(_) => gl_type == gl::FLOAT_MAT3,
+            UniformValue::Mat4(_) => gl_type == gl::FLOAT_MAT4,
+            UniformValue::Sampler(_) => is_sampler(gl_type)
+        }
+    }
+
+    /// Uploads the value to a uniform location of the program currently in use
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with the program `location` belongs to in use.
+    pub unsafe fn upload(&self, location: GLint) {
+        match self {
+            UniformValue::Float(value) => gl::Uniform1f(location, *value),
+            UniformValue::Int(value) => gl::Uniform1i(location, *value),
+            UniformValue::Bool(value) => gl::Uniform1i(location, *value as GLint),
+            UniformValue::Vec2(value) => gl::Uniform2f(location, value.x, value.y),
+            UniformValue::Vec3(value) => gl::Uniform3f(location, value.x, value.y, value.z),
+            UniformValue::Vec4(value) => gl::Uniform4f(location, value.x, value.y, value.z, value.w),
+            UniformValue::IVec2(value) => gl::Uniform2i(location, value.x, value.y),
+            UniformValue::IVec3(value) => gl::Uniform3i(location, value.x, value.y, value.z),
+            UniformValue::IVec4(value) => gl::Uniform4i(location, value.x, value.y, value.z, value.w),
+            //nalgebra matrices are column major, like OpenGL expects
+            UniformValue::Mat2(value) => gl::UniformMatrix2fv(location, 1, gl::FALSE, value.as_ptr()),
+            UniformValue::Mat3(value) => gl::UniformMatrix3fv(location, 1, gl::FALSE, value.as_ptr()),
+            UniformValue::Mat4(value) => gl::UniformMatrix4fv(location, 1, gl::FALSE, value.as_ptr()),
+            UniformValue::Array_F(values) => gl::Uniform1fv(location, values.len() as GLsizei, values.as_ptr()),
+            UniformValue::Array_I(values) => gl::Uniform1iv(location, values.len() as GLsizei, values.as_ptr()),
+            UniformValue::Array_Vec2(values) => gl::Uniform2fv(location, values.len() as GLsizei, values.as_ptr() as *const GLfloat),
+            UniformValue::Array_Vec3(values) => gl::Uniform3fv(location, values.len() as GLsizei, values.as_ptr() as *const GLfloat),
+            UniformValue::Array_Vec4(values) => gl::Uniform4fv(location, values.len() as GLsizei, values.as_ptr() as *const GLfloat),
+            UniformValue::Sampler(unit) => gl::Uniform1i(location, *unit as GLint)
+        }
+    }
+}
+
+impl From<f32> for UniformValue {
+    fn from(value: f32) -> Self {
+        UniformValue::Float(value)
+    }
+}
+
+impl From<i32> for UniformValue {
+    fn from(value: i32) -> Self {
+        UniformValue::Int(value)
+    }
+}
+
+impl From<bool> for UniformValue {
+    fn from(value: bool) -> Self {
+        UniformValue::Bool(value)
+    }
+}
+
+impl From<glm::Vec2> for UniformValue {
+    fn from(value: glm::Vec2) -> Self {
+        UniformValue::Vec2(value)
+    }
+}
+
+impl From<glm::Vec3> for UniformValue {
+    fn from(value: glm::Vec3) -> Self {
+        UniformValue::Vec3(value)
+    }
+}
+
+impl From<glm::Vec4> for UniformValue {
+    fn from(value: glm::Vec4) -> Self {
+        UniformValue::Vec4(value)
+    }
+}
+
+impl From<glm::IVec2> for UniformValue {
+    fn from(value: glm::IVec2) -> Self {
+        UniformValue::IVec2(value)
+    }
+}
+
+impl From<glm::IVec3> for UniformValue {
+    fn from(value: glm::IVec3) -> Self {
+        UniformValue::IVec3(value)
+    }
+}
+
+impl From<glm::IVec4> for UniformValue {
+    fn from(value: glm::IVec4) -> Self {
+        UniformValue::IVec4(value)
+    }
+}
+
+impl From<glm::Mat2> for UniformValue {
+    fn from(value: glm::Mat2) -> Self {
+        UniformValue::Mat2(value)
+    }
+}
+
+impl From<glm::Mat3> for UniformValue {
+    fn from(value: glm::Mat3) -> Self {
+        UniformValue::Mat3(value)
+    }
+}
+
+impl From<glm::Mat4> for UniformValue {
+    fn from(value: glm::Mat4) -> Self {
+        UniformValue::Mat4(value)
+    }
+}
+
+impl From<Vec<f32>> for UniformValue {
+    fn from(value: Vec<f32>) -> Self {
+        UniformValue::Array_F(value)
+    }
+}
+
+impl From<Vec<i32>> for UniformValue {
+    fn from(value: Vec<i32>) -> Self {
+        UniformValue::Array_I(value)
+    }
+}
+
+impl From<Vec<glm::Vec2>> for UniformValue {
+    fn from(value: Vec<glm::Vec2>) -> Self {
+        UniformValue::Array_Vec2(value)
+    }
+}
+
+impl From<Vec<glm::Vec3>> for UniformValue {
+    fn from(value: Vec<glm::Vec3>) -> Self {
+        UniformValue::Array_Vec3(value)
+    }
+}
+
+impl From<Vec<glm::Vec4>> for UniformValue {
+    fn from(value: Vec<glm::Vec4>) -> Self {
+        UniformValue::Array_Vec4(value)
+    }
+}
+
+fn is_sampler(gl_type: GLenum) -> bool {
+    matches!(gl_type,
+        gl::SAMPLER_1D | gl::SAMPLER_2D | gl::SAMPLER_3D | gl::SAMPLER_CUBE | gl::SAMPLER_BUFFER |
+        gl::SAMPLER_1D_ARRAY | gl::SAMPLER_2D_ARRAY | gl::SAMPLER_2D_RECT | gl::SAMPLER_2D_MULTISAMPLE |
+        gl::SAMPLER_1D_SHADOW | gl::SAMPLER_2D_SHADOW | gl::SAMPLER_CUBE_SHADOW |
+        gl::INT_SAMPLER_1D | gl::INT_SAMPLER_2D | gl::INT_SAMPLER_3D | gl::INT_SAMPLER_BUFFER | gl::INT_SAMPLER_2D_ARRAY |
+        gl::UNSIGNED_INT_SAMPLER_1D | gl::UNSIGNED_INT_SAMPLER_2D | gl::UNSIGNED_INT_SAMPLER_3D |
+        gl::UNSIGNED_INT_SAMPLER_BUFFER | gl::UNSIGNED_INT_SAMPLER_2D_ARRAY
+    )
+}
+
+/// GLSL name of a type reported by `glGetActiveUniform`, for error messages
+pub fn gl_type_name(gl_type: GLenum) -> String {
+    let name = match gl_type {
+        gl::FLOAT => "float",
+        gl::INT => "int",
+        gl::UNSIGNED_INT => "uint",
+        gl::BOOL => "bool",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::BOOL_VEC2 => "bvec2",
+        gl::BOOL_VEC3 => "bvec3",
+        gl::BOOL_VEC4 => "bvec4",
+        gl::FLOAT_MAT2 => "mat2",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl_type if is_sampler(gl_type) => "sampler",
+        _ => return format!("type {:#x}", gl_type)
+    };
+    name.to_string()
+}
+
+#[derive(Debug, Error)]
+pub enum UniformError {
+    #[error("Uniform {0} was never added to the shader")]
+    NotFound(String),
+    #[error("Uniform {name} is a {current} value, cannot set it to {new}")]
+    TypeMismatch { name: String, current: &'static str, new: &'static str },
 }
 
 #[derive(Clone)]
 pub struct Uniform {
     value: UniformValue,
-    key: String
+    key: String,
+    /// Resolved once after linking. None until then, or when the linker removed the uniform.
+    location: Option<GLint>,
+    /// Whether the value changed since it was last uploaded
+    dirty: bool
 }
 
 impl Uniform {
     pub fn new(key: String, value: UniformValue) -> Self {
         Self {
             value,
-            key
+            key,
+            location: None,
+            dirty: true
         }
     }
 
-    pub fn get_value(&self) -> UniformValue {
-        self.value.clone()
+    pub fn value(&self) -> &UniformValue {
+        &self.value
     }
 
-    pub fn get_key(&self) -> String {
-        self.key.clone()
+    pub fn key(&self) -> &str {
+        &self.key
     }
 }
 
-#[derive(Clone)]
+/// Uniform values of a shader, looked up by name through a hash index
+#[derive(Clone, Default)]
 pub struct UniformManager {
-    pub uniforms: Vec<Uniform>
+    uniforms: Vec<Uniform>,
+    index: HashMap<String, usize>
 }
 
 impl UniformManager {
     pub fn new() -> Self {
-        Self {
-            uniforms: Vec::new()
-        }
+        Self::default()
     }
 
+    /// Adds a uniform, replacing any previous one with the same name
     pub fn add(&mut self, key: String, value: UniformValue) {
-        self.uniforms.push(Uniform { value, key });
+        match self.index.get(&key) {
+            Some(&i) => self.uniforms[i] = Uniform { location: self.uniforms[i].location, ..Uniform::new(key, value) },
+            None => {
+                self.index.insert(key.clone(), self.uniforms.len());
+                self.uniforms.push(Uniform::new(key, value));
+            }
+        }
+    }
+
+    /// Sets a new value, which is only uploaded on the next `upload_dirty` if it differs from the current one
+    pub fn update_value(&mut self, key: &str, new_value: UniformValue) -> Result<(), UniformError> {
+        let i = *self.index.get(key).ok_or_else(|| UniformError::NotFound(key.to_string()))?;
+        let uniform = &mut self.uniforms[i];
+
+        if std::mem::discriminant(&uniform.value) != std::mem::discriminant(&new_value) {
+            return Err(UniformError::TypeMismatch { name: key.to_string(), current: uniform.value.type_name(), new: new_value.type_name() });
+        }
+        if uniform.value != new_value {
+            uniform.value = new_value;
+            uniform.dirty = true;
+        }
+        Ok(())
+    }
+
+    pub fn get_value(&self, key: &str) -> Result<&UniformValue, UniformError> {
+        self.index.get(key)
+            .map(|&i| &self.uniforms[i].value)
+            .ok_or_else(|| UniformError::NotFound(key.to_string()))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Uniform> {
+        self.uniforms.iter()
     }
 
-    pub fn update_value(&mut self, key: String, new_value: UniformValue) {
+    /// Caches the location of every uniform from the program's active uniforms and marks them all for upload,
+    /// since a freshly linked program starts with every uniform at zero
+    pub fn resolve_locations(&mut self, active_uniforms: &HashMap<String, ActiveUniform>) {
         for uniform in &mut self.uniforms {
-            if uniform.key == key {
-                uniform.value = new_value.clone();
-            }
+            uniform.location = active_uniforms.get(&uniform.key).map(|active| active.location);
+            uniform.dirty = true;
         }
     }
 
-    pub fn get_value(&self, key: &String) -> UniformValue {
-        for uniform in &self.uniforms {
-            if &uniform.key == key {
-                return uniform.value.clone();
+    /// Uploads the values that changed since the last call. The program has to be in use.
+    ///
+    /// # Safety
+    /// Needs the OpenGL context the program was created in to be current, with the program in use.
+    pub unsafe fn upload_dirty(&mut self) {
+        for uniform in self.uniforms.iter_mut().filter(|uniform| uniform.dirty) {
+            if let Some(location) = uniform.location {
+                uniform.value.upload(location);
             }
+            uniform.dirty = false;
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirty(manager: &UniformManager, key: &str) -> bool {
+        manager.uniforms[manager.index[key]].dirty
+    }
 
-        panic!("Uniform {} not found", key);
+    #[test]
+    fn unchanged_values_stay_clean() {
+        let mut manager = UniformManager::new();
+        manager.add("u_mix".to_string(), UniformValue::Float(0.5));
+        manager.uniforms[0].dirty = false;
+
+        manager.update_value("u_mix", UniformValue::Float(0.5)).unwrap();
+        assert!(!dirty(&manager, "u_mix"));
+
+        manager.update_value("u_mix", UniformValue::Float(0.75)).unwrap();
+        assert!(dirty(&manager, "u_mix"));
+        assert_eq!(manager.get_value("u_mix").unwrap(), &UniformValue::Float(0.75));
+    }
+
+    #[test]
+    fn missing_and_mistyped_uniforms_are_errors() {
+        let mut manager = UniformManager::new();
+        manager.add("u_mix".to_string(), UniformValue::Float(0.5));
+
+        assert!(matches!(manager.update_value("u_missing", UniformValue::Float(1.0)), Err(UniformError::NotFound(_))));
+        assert!(matches!(manager.get_value("u_missing"), Err(UniformError::NotFound(_))));
+        assert!(matches!(manager.update_value("u_mix", UniformValue::Int(1)), Err(UniformError::TypeMismatch { .. })));
+        assert_eq!(manager.get_value("u_mix").unwrap(), &UniformValue::Float(0.5));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,279 @@
+use gl::types::*;
+use crate::gpu::Buffer;
+extern crate nalgebra_glm as glm;
+
+/// Alignment of arrays, matrix columns and whole blocks in std140
+const VEC4_ALIGN: usize = 16;
+
+fn align_to(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// A value that can be written into a std140 uniform block
+pub trait Std140Value {
+    /// Base alignment in bytes
+    const ALIGN: usize;
+    /// Size in bytes, without padding after the value
+    const SIZE: usize;
+    fn write(&self, out: &mut [u8]);
+}
+
+impl Std140Value for f32 {
+    const ALIGN: usize = 4;
+    const SIZE: usize = 4;
+    fn write(&self, out: &mut [u8]) {
+        out[..4].copy_from_slice(&self.to_ne_bytes());
+    }
+}
+
+impl Std140Value for i32 {
+    const ALIGN: usize = 4;
+    const SIZE: usize = 4;
+    fn write(&self, out: &mut [u8]) {
+        out[..4].copy_from_slice(&self.to_ne_bytes());
+    }
+}
+
+impl Std140Value for u32 {
+    const ALIGN: usize = 4;
+    const SIZE: usize = 4;
+    fn write(&self, out: &mut [u8]) {
+        out[..4].copy_from_slice(&self.to_ne_bytes());
+    }
+}
+
+/// GLSL bools take up a whole 32 bit word
+impl Std140Value for bool {
+    const ALIGN: usize = 4;
+    const SIZE: usize = 4;
+    fn write(&self, out: &mut [u8]) {
+        (*self as u32).write(out);
+    }
+}
+
+fn write_components<T: Std140Value + Copy>(components: &[T], out: &mut [u8]) {
+    for (i, component) in components.iter().enumerate() {
+        component.write(&mut out[i * 4..]);
+    }
+}
+
+impl Std140Value for glm::Vec2 {
+    const ALIGN: usize = 8;
+    const SIZE: usize = 8;
+    fn write(&self, out: &mut [u8]) {
+        write_components(self.as_slice(), out);
+    }
+}
+
+/// vec3 is aligned like a vec4, but a following scalar can use its last 4 bytes
+impl Std140Value for glm::Vec3 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 12;
+    fn write(&self, out: &mut [u8]) {
+        write_components(self.as_slice(), out);
+    }
+}
+
+impl Std140Value for glm::Vec4 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 16;
+    fn write(&self, out: &mut [u8]) {
+        write_components(self.as_slice(), out);
+    }
+}
+
+impl Std140Value for glm::IVec2 {
+    const ALIGN: usize = 8;
+    const SIZE: usize = 8;
+    fn write(&self, out: &mut [u8]) {
+        write_components(self.as_slice(), out);
+    }
+}
+
+impl Std140Value for glm::IVec3 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 12;
+    fn write(&self, out: &mut [u8]) {
+        write_components(self.as_slice(), out);
+    }
+}
+
+impl Std140Value for glm::IVec4 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 16;
+    fn write(&self, out: &mut [u8]) {
+        write_components(self.as_slice(), out);
+    }
+}
+
+/// Matrices are stored as arrays of column vectors, so every column is padded to 16 bytes
+fn write_columns(columns: usize, rows: usize, values: &[f32], out: &mut [u8]) {
+    for column in 0..columns {
+        write_components(&values[column * rows..(column + 1) * rows], &mut out[column * VEC4_ALIGN..]);
+    }
+}
+
+impl Std140Value for glm::Mat2 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 2 * VEC4_ALIGN;
+    fn write(&self, out: &mut [u8]) {
+        write_columns(2, 2, self.as_slice(), out);
+    }
+}
+
+impl Std140Value for glm::Mat3 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 3 * VEC4_ALIGN;
+    fn write(&self, out: &mut [u8]) {
+        write_columns(3, 3, self.as_slice(), out);
+    }
+}
+
+impl Std140Value for glm::Mat4 {
+    const ALIGN: usize = 16;
+    const SIZE: usize = 4 * VEC4_ALIGN;
+    fn write(&self, out: &mut [u8]) {
+        write_columns(4, 4, self.as_slice(), out);
+    }
+}
+
+/// Builds the contents of a std140 uniform block. Members have to be pushed in the order they are declared in GLSL.
+#[derive(Clone, Debug, Default)]
+pub struct Std140Block {
+    data: Vec<u8>
+}
+
+impl Std140Block {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a member and returns its offset
+    pub fn push<T: Std140Value>(&mut self, value: &T) -> usize {
+        let offset = align_to(self.data.len(), T::ALIGN);
+        self.data.resize(offset + T::SIZE, 0);
+        value.write(&mut self.data[offset..]);
+        offset
+    }
+
+    /// Appends an array member and returns its offset. Every element is padded to 16 bytes.
+    pub fn push_array<T: Std140Value>(&mut self, values: &[T]) -> usize {
+        let stride = align_to(T::SIZE, VEC4_ALIGN);
+        let offset = align_to(self.data.len(), VEC4_ALIGN);
+        self.data.resize(offset + stride * values.len(), 0);
+        for (i, value) in values.iter().enumerate() {
+            value.write(&mut self.data[offset + i * stride..]);
+        }
+        //The member after an array starts on a 16 byte boundary
+        self.data.resize(align_to(self.data.len(), VEC4_ALIGN), 0);
+        offset
+    }
+
+    /// The block contents, padded to a multiple of 16 bytes like `GL_UNIFORM_BLOCK_DATA_SIZE`
+    pub fn finish(mut self) -> Vec<u8> {
+        self.data.resize(align_to(self.data.len(), VEC4_ALIGN), 0);
+        self.data
+    }
+}
+
+/// A uniform buffer bound to a fixed binding point, which any number of programs can read from
+pub struct UniformBuffer {
+    pub buffer: Buffer,
+    pub binding: GLuint,
+    pub size: usize,
+    /// Last uploaded contents, so unchanged blocks aren't uploaded again
+    contents: Vec<u8>
+}
+
+impl UniformBuffer {
+    /// # Safety
+    /// Needs a current OpenGL context with loaded function pointers.
+    pub unsafe fn new(binding: GLuint, size: usize) -> Self {
+        let buffer = Buffer::new(gl::UNIFORM_BUFFER);
+        buffer.allocate(size, gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+
+        Self { buffer, binding, size, contents: Vec::new() }
+    }
+
+    /// Uploads the block if it changed, and binds the buffer to its binding point
+    ///
+    /// # Safety
+    /// Needs the OpenGL context this was created in to be current.
+    pub unsafe fn update(&mut self, contents: Vec<u8>) {
+        debug_assert_eq!(contents.len(), self.size, "Uniform block size changed");
+        if contents != self.contents {
+            self.buffer.set_sub_data(0, &contents);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            self.contents = contents;
+        }
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, self.binding, self.buffer.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks_exact(4).map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn scalars_and_vectors_follow_base_alignment() {
+        let mut block = Std140Block::new();
+        assert_eq!(block.push(&1.0f32), 0);
+        assert_eq!(block.push(&glm::vec2(2.0, 3.0)), 8);
+        assert_eq!(block.push(&glm::vec3(4.0, 5.0, 6.0)), 16);
+        //A scalar after a vec3 fills its last component
+        assert_eq!(block.push(&7i32), 28);
+        assert_eq!(block.push(&glm::vec4(8.0, 9.0, 10.0, 11.0)), 32);
+        assert_eq!(block.push(&true), 48);
+
+        let data = block.finish();
+        assert_eq!(data.len(), 64);
+        assert_eq!(floats(&data[0..4]), [1.0]);
+        assert_eq!(floats(&data[8..24]), [2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(i32::from_ne_bytes(data[28..32].try_into().unwrap()), 7);
+        assert_eq!(u32::from_ne_bytes(data[48..52].try_into().unwrap()), 1);
+    }
+
+    #[test]
+    fn matrix_columns_are_padded_to_vec4() {
+        let mut block = Std140Block::new();
+        block.push(&1.0f32);
+        let matrix = glm::mat3(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0);
+        assert_eq!(block.push(&matrix), 16);
+        assert_eq!(block.push(&2.0f32), 64);
+
+        let data = block.finish();
+        assert_eq!(data.len(), 80);
+        //glm::mat3 takes rows, so the first column is (1, 4, 7)
+        assert_eq!(floats(&data[16..64]), [1.0, 4.0, 7.0, 0.0, 2.0, 5.0, 8.0, 0.0, 3.0, 6.0, 9.0, 0.0]);
+    }
+
+    #[test]
+    fn array_elements_have_16_byte_stride() {
+        let mut block = Std140Block::new();
+        block.push(&1.0f32);
+        assert_eq!(block.push_array(&[2.0f32, 3.0, 4.0]), 16);
+        assert_eq!(block.push(&5.0f32), 64);
+        assert_eq!(block.push_array(&[glm::vec3(6.0, 7.0, 8.0), glm::vec3(9.0, 10.0, 11.0)]), 80);
+
+        let data = block.finish();
+        assert_eq!(data.len(), 112);
+        assert_eq!(floats(&data[16..20]), [2.0]);
+        assert_eq!(floats(&data[32..36]), [3.0]);
+        assert_eq!(floats(&data[48..52]), [4.0]);
+        assert_eq!(floats(&data[96..108]), [9.0, 10.0, 11.0]);
+    }
+
+    #[test]
+    fn block_size_is_rounded_to_16() {
+        let mut block = Std140Block::new();
+        block.push(&glm::vec2(1.0, 2.0));
+        block.push(&3.0f32);
+        assert_eq!(block.finish().len(), 16);
+        assert_eq!(Std140Block::new().finish().len(), 0);
+    }
+}
@@ -0,0 +1,213 @@
+extern crate nalgebra_glm as glm;
+
+const MIN_ZOOM: f32 = 0.01;
+const MAX_ZOOM: f32 = 1000.0;
+/// Fraction of the screen left empty around the particles by `fit`
+const FIT_MARGIN: f32 = 0.1;
+
+/// 2D camera over the simulation domain
+#[derive(Clone, Debug)]
+pub struct Camera {
+    /// World position shown at the center of the screen
+    pub center: glm::Vec2,
+    /// Magnification relative to fitting the unit square domain to the window
+    pub zoom: f32
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            center: glm::vec2(0.5, 0.5),
+            zoom: 1.0
+        }
+    }
+}
+
+/// Maps between screen space (physical pixels, origin in the top left, y down) and world space
+/// (simulation coordinates, y up). At a zoom of 1 the unit square domain is fit to the shorter side
+/// of the window, so any size or aspect ratio shows the whole domain without stretching.
+#[derive(Clone, Debug)]
+pub struct View {
+    pub screen_size: glm::Vec2,
+    pub scale_factor: f64,
+    pub camera: Camera
+}
+
+impl View {
+    pub fn new(width: u32, height: u32, scale_factor: f64) -> Self {
+        Self {
+            screen_size: glm::vec2(width.max(1) as f32, height.max(1) as f32),
+            scale_factor,
+            camera: Camera::default()
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.screen_size = glm::vec2(width.max(1) as f32, height.max(1) as f32);
+    }
+
+    pub fn pixels_per_unit(&self) -> f32 {
+        self.screen_size.x.min(self.screen_size.y) * self.camera.zoom
+    }
+
+    pub fn screen_to_world(&self, screen: glm::Vec2) -> glm::Vec2 {
+        let centered = glm::vec2(screen.x - self.screen_size.x / 2.0, self.screen_size.y / 2.0 - screen.y);
+        centered / self.pixels_per_unit() + self.camera.center
+    }
+
+    pub fn world_to_screen(&self, world: glm::Vec2) -> glm::Vec2 {
+        let centered = (world - self.camera.center) * self.pixels_per_unit();
+        glm::vec2(centered.x + self.screen_size.x / 2.0, self.screen_size.y / 2.0 - centered.y)
+    }
+
+    /// Moves the camera so the world follows a cursor drag of `delta` screen pixels
+    pub fn pan(&mut self, delta: glm::Vec2) {
+        self.camera.center -= glm::vec2(delta.x, -delta.y) / self.pixels_per_unit();
+    }
+
+    /// Multiplies the zoom by `factor`, keeping the world position under `anchor` (in screen pixels) fixed
+    pub fn zoom_at(&mut self, factor: f32, anchor: glm::Vec2) {
+        let world_anchor = self.screen_to_world(anchor);
+        self.camera.zoom = (self.camera.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.camera.center += world_anchor - self.screen_to_world(anchor);
+    }
+
+    /// Centers the camera on the given world positions and zooms so they all fit on screen
+    pub fn fit(&mut self, positions: impl Iterator<Item = glm::Vec2>) {
+        let mut min = glm::vec2(f32::INFINITY, f32::INFINITY);
+        let mut max = glm::vec2(f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for position in positions.filter(|position| position.x.is_finite() && position.y.is_finite()) {
+            min = glm::min2(&min, &position);
+            max = glm::max2(&max, &position);
+        }
+
+        if min.x > max.x {
+            self.camera = Camera::default();
+            return;
+        }
+
+        let extent = (max - min).component_mul(&glm::vec2(1.0 / self.screen_size.x, 1.0 / self.screen_size.y));
+        let base_pixels_per_unit = self.screen_size.x.min(self.screen_size.y);
+        let fit_pixels_per_unit = (1.0 - FIT_MARGIN) / extent.x.max(extent.y).max(f32::EPSILON);
+
+        self.camera.center = (min + max) / 2.0;
+        self.camera.zoom = (fit_pixels_per_unit / base_pixels_per_unit).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: glm::Vec2, expected: glm::Vec2, tolerance: f32) {
+        assert!((actual - expected).abs().max() < tolerance, "{:?} != {:?}", actual, expected);
+    }
+
+    fn moved_view(width: u32, height: u32) -> View {
+        let mut view = View::new(width, height, 1.0);
+        view.camera = Camera { center: glm::vec2(0.3, 0.7), zoom: 2.5 };
+        view
+    }
+
+    const SCREEN_POINTS: [[f32; 2]; 5] = [[0.0, 0.0], [800.0, 600.0], [400.0, 300.0], [123.0, 456.0], [-50.0, 900.0]];
+
+    #[test]
+    fn screen_to_world_round_trips() {
+        for (width, height) in [(800, 600), (600, 800)] {
+            let view = moved_view(width, height);
+            for [x, y] in SCREEN_POINTS {
+                let screen = glm::vec2(x, y);
+                assert_close(view.world_to_screen(view.screen_to_world(screen)), screen, 1e-3);
+            }
+            for world in [glm::vec2(0.0, 0.0), glm::vec2(1.0, 1.0), glm::vec2(-3.0, 7.5)] {
+                assert_close(view.screen_to_world(view.world_to_screen(world)), world, 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn screen_center_is_the_camera_center_with_y_up() {
+        let view = moved_view(800, 600);
+        assert_close(view.screen_to_world(glm::vec2(400.0, 300.0)), view.camera.center, 1e-6);
+        //Moving down on screen moves down in the world
+        let below = view.screen_to_world(glm::vec2(400.0, 400.0));
+        assert!(below.y < view.camera.center.y);
+    }
+
+    #[test]
+    fn domain_fits_the_shorter_side() {
+        for (width, height) in [(1600, 800), (800, 1600)] {
+            let view = View::new(width, height, 1.0);
+            let shorter = width.min(height) as f32;
+            let extent = view.world_to_screen(glm::vec2(1.0, 0.0)) - view.world_to_screen(glm::vec2(0.0, 1.0));
+            //Not stretched, and centered on the longer side
+            assert_close(extent, glm::vec2(shorter, shorter), 1e-3);
+            let origin = view.world_to_screen(glm::vec2(0.0, 0.0));
+            assert_close(origin, glm::vec2((width as f32 - shorter) / 2.0, (height as f32 + shorter) / 2.0), 1e-3);
+        }
+    }
+
+    #[test]
+    fn zoom_at_keeps_the_cursor_point_fixed() {
+        for factor in [1.1, 0.5, 4.0, 1e6, 1e-6] {
+            let mut view = moved_view(800, 600);
+            for [x, y] in SCREEN_POINTS {
+                let anchor = glm::vec2(x, y);
+                let before = view.screen_to_world(anchor);
+                view.zoom_at(factor, anchor);
+                assert_close(view.screen_to_world(anchor), before, 1e-3);
+            }
+            assert!((MIN_ZOOM..=MAX_ZOOM).contains(&view.camera.zoom));
+        }
+    }
+
+    /// Screen space bounding box of the world positions after `fit`
+    fn fitted_extent(width: u32, height: u32, positions: &[glm::Vec2]) -> (View, glm::Vec2, glm::Vec2) {
+        let mut view = moved_view(width, height);
+        view.fit(positions.iter().copied());
+        let corners: Vec<_> = positions.iter().map(|position| view.world_to_screen(*position)).collect();
+        let min = corners.iter().fold(glm::vec2(f32::INFINITY, f32::INFINITY), |min, corner| glm::min2(&min, corner));
+        let max = corners.iter().fold(glm::vec2(f32::NEG_INFINITY, f32::NEG_INFINITY), |max, corner| glm::max2(&max, corner));
+        (view, min, max)
+    }
+
+    #[test]
+    fn fit_wide_particles_on_a_wide_screen() {
+        let positions = [glm::vec2(-1.0, 0.0), glm::vec2(3.0, 0.5)];
+        let (view, min, max) = fitted_extent(1600, 800, &positions);
+        assert_close(view.camera.center, glm::vec2(1.0, 0.25), 1e-6);
+        //The width limits the zoom, with the margin split between both sides
+        assert!(((max.x - min.x) - 1600.0 * (1.0 - FIT_MARGIN)).abs() < 0.1, "{:?} {:?}", min, max);
+        assert!(min.y > 0.0 && max.y < 800.0);
+    }
+
+    #[test]
+    fn fit_tall_particles_on_a_tall_screen() {
+        let positions = [glm::vec2(0.0, -2.0), glm::vec2(1.0, 4.0)];
+        let (view, min, max) = fitted_extent(600, 1200, &positions);
+        assert_close(view.camera.center, glm::vec2(0.5, 1.0), 1e-6);
+        assert!(((max.y - min.y) - 1200.0 * (1.0 - FIT_MARGIN)).abs() < 0.1, "{:?} {:?}", min, max);
+        assert!(min.x > 0.0 && max.x < 600.0);
+    }
+
+    #[test]
+    fn fit_square_particles_uses_the_shorter_side() {
+        let positions = [glm::vec2(0.0, 0.0), glm::vec2(1.0, 1.0)];
+        for (width, height) in [(1600, 800), (800, 1600)] {
+            let (_, min, max) = fitted_extent(width, height, &positions);
+            let shorter = width.min(height) as f32;
+            let extent = max - min;
+            assert!((extent.x - shorter * (1.0 - FIT_MARGIN)).abs() < 0.1, "{:?}", extent);
+            //Not stretched
+            assert!((extent.x - extent.y).abs() < 0.1, "{:?}", extent);
+        }
+    }
+
+    #[test]
+    fn fit_without_finite_positions_resets_the_camera() {
+        let mut view = moved_view(800, 600);
+        view.fit([glm::vec2(f32::NAN, 0.0), glm::vec2(f32::INFINITY, 1.0)].into_iter());
+        assert_close(view.camera.center, Camera::default().center, f32::EPSILON);
+        assert_eq!(view.camera.zoom, 1.0);
+    }
+}